@@ -0,0 +1,64 @@
+use std::time::Instant;
+
+use dfu::{DfuDevice, DfuError, UploadResult};
+
+use crate::{
+    CliError, config::config, progress::NoProgress, read::upload_range,
+};
+
+const BENCH_LENGTH: u32 = 256 * 1024;
+const BENCH_TRANSFER_SIZES: &[u16] = &[256, 512, 1024, 2048];
+
+pub(crate) fn bench(
+    device: DfuDevice,
+    start_address: Option<u32>,
+    length: Option<u32>,
+) -> Result<(), CliError> {
+    if !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
+
+    let start_address =
+        start_address.unwrap_or(device.get_default_start_address());
+    let (_, intf_segments) = device.connect_for_address(start_address, None)?;
+    let length = length
+        .unwrap_or(BENCH_LENGTH)
+        .min(intf_segments.end_addr() - start_address);
+
+    println!(
+        "Reading {} KB from {:#010x} with each transfer size...",
+        length / 1024,
+        start_address
+    );
+
+    let mut fastest: Option<(u16, f64)> = None;
+    for &transfer_size in BENCH_TRANSFER_SIZES {
+        let (connection, _) = device.connect_for_address(
+            start_address,
+            Some(start_address + length - 1),
+        )?;
+        let connection = config()
+            .configure(connection)
+            .with_transfer_size(transfer_size);
+        connection.reset_state()?;
+
+        let start = Instant::now();
+        upload_range(&connection, start_address, length, &mut NoProgress)?;
+        let speed = UploadResult {
+            start_address,
+            bytes_read: length as u64,
+            elapsed: start.elapsed(),
+        }
+        .kb_per_second();
+        println!("  {:5} bytes: {:8.1} KB/s", transfer_size, speed);
+
+        if fastest.is_none_or(|(_, best)| speed > best) {
+            fastest = Some((transfer_size, speed));
+        }
+    }
+
+    if let Some((transfer_size, _)) = fastest {
+        println!("Recommended transfer size: {transfer_size} bytes");
+    }
+    Ok(())
+}
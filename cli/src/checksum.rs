@@ -0,0 +1,26 @@
+use clap::ValueEnum;
+use sha2::{Sha256, digest::DynDigest};
+
+/// Algorithm used to fingerprint the written image
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum Checksum {
+    Sha256,
+}
+
+impl Checksum {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "SHA-256",
+        }
+    }
+
+    pub fn hasher(&self) -> Box<dyn DynDigest> {
+        match self {
+            Checksum::Sha256 => Box::new(Sha256::default()),
+        }
+    }
+}
+
+pub(crate) fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
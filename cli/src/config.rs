@@ -0,0 +1,158 @@
+use std::{env, fs, io, path::PathBuf, sync::OnceLock, time::Duration};
+
+use dfu::{
+    DfuConnection, DfuDevice, DfuError, find_dfu_devices, wait_for_dfu_devices,
+};
+use serde::Deserialize;
+
+use crate::CliError;
+
+const CONFIG_DIR: &str = "rs-dfu";
+const CONFIG_FILE: &str = "config.toml";
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Optional defaults read from `<config dir>/rs-dfu/config.toml`
+///
+/// Example:
+/// ```toml
+/// vendor = 0x0483
+/// product = 0xdf11
+/// transfer_size = 2048
+/// fallback_transfer_size = 64 # if the device does not report one
+/// timeout = 5000 # milliseconds
+/// clear_halt = true # for devices coming up with a stalled endpoint
+/// configuration = 1 # USB configuration exposing the DFU interfaces
+/// ```
+///
+/// Command line options always take precedence.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    pub vendor: Option<u16>,
+    pub product: Option<u16>,
+    pub transfer_size: Option<u16>,
+    pub fallback_transfer_size: Option<u16>,
+    pub timeout: Option<u64>,
+    pub clear_halt: Option<bool>,
+    pub configuration: Option<u8>,
+    /// Time to wait for a device to appear (command line only)
+    #[serde(skip)]
+    pub wait: Option<Duration>,
+    /// Skip the initial state reset (command line only)
+    #[serde(skip)]
+    pub no_reset_state: bool,
+    /// Only consider the device with this serial number (command line only)
+    #[serde(skip)]
+    pub serial: Option<String>,
+}
+
+impl Config {
+    fn load() -> Result<Self, CliError> {
+        let Some(path) = config_path() else {
+            return Ok(Config::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(|err| {
+                CliError::Config(format!("{}: {}", path.display(), err))
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Ok(Config::default())
+            }
+            Err(err) => Err(CliError::IO(err)),
+        }
+    }
+
+    /// Apply connection defaults (transfer sizes, timeout, clear-halt)
+    pub fn configure(&self, connection: DfuConnection) -> DfuConnection {
+        let connection = match self.fallback_transfer_size {
+            Some(size) => connection.with_fallback_transfer_size(size),
+            None => connection,
+        };
+        let connection = match self.transfer_size {
+            Some(size) => connection.with_transfer_size(size),
+            None => connection,
+        };
+        let connection = match self.timeout {
+            Some(ms) => connection.with_timeout(Duration::from_millis(ms)),
+            None => connection,
+        };
+        connection.with_clear_halt(self.clear_halt.unwrap_or(false))
+    }
+
+    /// Bring the device back to `dfuIDLE` (clearing errors and aborting
+    /// pending transfers) before a command, unless `--no-reset-state`
+    /// was given
+    pub fn reset_state(
+        &self,
+        connection: &DfuConnection,
+    ) -> Result<(), DfuError> {
+        if self.no_reset_state {
+            return Ok(());
+        }
+        println!("Resetting device state...");
+        connection.reset_state()
+    }
+
+    /// Find the DFU devices matching the filter (or the configured
+    /// defaults), waiting for one to appear if `--wait` was given
+    pub fn find_devices(
+        &self,
+        vid: Option<u16>,
+        pid: Option<u16>,
+    ) -> Result<Vec<DfuDevice>, CliError> {
+        let (vid, pid) = (vid.or(self.vendor), pid.or(self.product));
+        let serial = self.serial.clone();
+        match self.wait {
+            Some(timeout) => {
+                match wait_for_dfu_devices(
+                    vid,
+                    pid,
+                    serial,
+                    timeout,
+                    WAIT_INTERVAL,
+                ) {
+                    Err(DfuError::Timeout) => Ok(Vec::new()),
+                    result => Ok(result?),
+                }
+            }
+            None => Ok(find_dfu_devices(vid, pid, serial)?),
+        }
+    }
+}
+
+/// Load the configuration file (if any) and apply the global command
+/// line options, must be called once at startup
+pub(crate) fn init(
+    configuration: Option<u8>,
+    wait: Option<Duration>,
+    no_reset_state: bool,
+    serial: Option<String>,
+    timeout: Option<u64>,
+) -> Result<(), CliError> {
+    let mut config = Config::load()?;
+    config.configuration = configuration.or(config.configuration);
+    config.timeout = timeout.or(config.timeout);
+    config.wait = wait;
+    config.no_reset_state = no_reset_state;
+    config.serial = serial;
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+pub(crate) fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config_dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::home_dir().map(|home| home.join(".config")))
+    };
+    config_dir.map(|dir| dir.join(CONFIG_DIR).join(CONFIG_FILE))
+}
@@ -0,0 +1,142 @@
+use dfu::{DfuDescriptor, list_dfu_device_infos};
+use nusb::{
+    MaybeFuture,
+    descriptors::{ConfigurationDescriptor, Descriptor},
+};
+
+use crate::{CliError, config::config};
+
+const INTERFACE_DESC_TYPE: u8 = 0x04;
+const ENDPOINT_DESC_TYPE: u8 = 0x05;
+
+/// Print the raw USB descriptors of the matching DFU devices along with
+/// their decoded fields
+///
+/// Devices that cannot be opened are reported and skipped.
+pub(crate) fn print_descriptors(
+    vid: Option<u16>,
+    pid: Option<u16>,
+) -> Result<(), CliError> {
+    let config = config();
+    let devices = list_dfu_device_infos(
+        vid.or(config.vendor),
+        pid.or(config.product),
+        config.serial.as_deref(),
+    )?;
+    if devices.is_empty() {
+        println!("No DFU device found");
+    }
+    for info in devices {
+        println!(
+            "Bus {} Device {:03}: ID {:04x}:{:04x}",
+            info.bus_id(),
+            info.device_address(),
+            info.vendor_id(),
+            info.product_id(),
+        );
+        let device = match info.open().wait() {
+            Ok(device) => device,
+            Err(err) => {
+                println!("  Cannot open device: {err}");
+                continue;
+            }
+        };
+
+        let desc = device.device_descriptor();
+        println!("  Device descriptor: {}", hex(desc.as_bytes()));
+        println!(
+            "    bcdUSB={:#06x}, class={:#04x}, subclass={:#04x}, \
+            protocol={:#04x}, max_packet_size={}",
+            desc.usb_version(),
+            desc.class(),
+            desc.subclass(),
+            desc.protocol(),
+            desc.max_packet_size_0(),
+        );
+        println!(
+            "    idVendor={:#06x}, idProduct={:#06x}, bcdDevice={:#06x}, \
+            configurations={}",
+            desc.vendor_id(),
+            desc.product_id(),
+            desc.device_version(),
+            desc.num_configurations(),
+        );
+        for configuration in device.configurations() {
+            print_configuration(&configuration);
+        }
+    }
+    Ok(())
+}
+
+fn print_configuration(configuration: &ConfigurationDescriptor) {
+    let raw = configuration.as_bytes();
+    println!(
+        "  Configuration {}: {}",
+        configuration.configuration_value(),
+        hex(&raw[..raw[0] as usize]),
+    );
+    println!(
+        "    interfaces={}, attributes={:#04x}, max_power={} mA",
+        configuration.num_interfaces(),
+        configuration.attributes(),
+        configuration.max_power() as u32 * 2,
+    );
+    for desc in configuration.descriptors() {
+        print_descriptor(&desc);
+    }
+}
+
+fn print_descriptor(desc: &Descriptor) {
+    match desc.descriptor_type() {
+        INTERFACE_DESC_TYPE if desc.len() >= 9 => {
+            println!(
+                "    Interface {}, alt {}: {}",
+                desc[2],
+                desc[3],
+                hex(desc)
+            );
+            println!(
+                "      class={:#04x}, subclass={:#04x}, protocol={:#04x}, \
+                endpoints={}, string_index={}",
+                desc[5], desc[6], desc[7], desc[4], desc[8],
+            );
+        }
+        ENDPOINT_DESC_TYPE => {
+            println!("      Endpoint: {}", hex(desc));
+        }
+        _ => match DfuDescriptor::parse(desc) {
+            Some(dfu_desc) => {
+                println!("      DFU descriptor: {}", hex(desc));
+                println!(
+                    "        attributes={:#04x} (download={}, upload={}, \
+                    manifestation_tolerant={}, will_detach={})",
+                    dfu_desc.attributes(),
+                    dfu_desc.can_download(),
+                    dfu_desc.can_upload(),
+                    dfu_desc.manifestation_tolerant(),
+                    dfu_desc.will_detach(),
+                );
+                println!(
+                    "        detach_timeout={} ms, transfer_size={}, \
+                    version={:#06x}",
+                    dfu_desc.detach_timeout(),
+                    dfu_desc.transfer_size(),
+                    dfu_desc.dfu_version(),
+                );
+            }
+            None => println!(
+                "      Descriptor type {:#04x}: {}",
+                desc.descriptor_type(),
+                hex(desc)
+            ),
+        },
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
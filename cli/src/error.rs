@@ -7,8 +7,17 @@ pub enum CliError {
     IO(io::Error),
     Dfu(DfuError),
     UF2(UF2DecodeError),
+    Config(String),
     NoDFUDevice,
     ManyDFUDevices,
+    ImageOutOfBounds { start: u32, end: u32 },
+    EmptyImage,
+    InvalidLength { start: u32, length: u32 },
+    TargetMismatch { expected: String, found: String },
+    ChecksumMismatch,
+    VerifyMismatch { address: u32 },
+    UnknownRegion(String),
+    PartialWrite { failed: usize, total: usize },
 }
 
 impl From<io::Error> for CliError {
@@ -35,8 +44,42 @@ impl Display for CliError {
             CliError::IO(err) => write!(f, "IO error: {err}"),
             CliError::Dfu(err) => write!(f, "DFU error: {err}"),
             CliError::UF2(err) => write!(f, "{err}"),
+            CliError::Config(err) => write!(f, "Config error: {err}"),
             CliError::NoDFUDevice => write!(f, "No DFU device"),
-            CliError::ManyDFUDevices => write!(f, "More than one DFU devices"),
+            CliError::ManyDFUDevices => {
+                write!(
+                    f,
+                    "More than one DFU devices (use --serial to select one)"
+                )
+            }
+            CliError::ImageOutOfBounds { start, end } => write!(
+                f,
+                "Image ({start:#010x} - {end:#010x}) exceeds device flash"
+            ),
+            CliError::EmptyImage => write!(f, "Image is empty"),
+            CliError::InvalidLength { start, length } => write!(
+                f,
+                "Cannot read {length} bytes from {start:#010x} \
+                (empty or beyond the address space)"
+            ),
+            CliError::TargetMismatch { expected, found } => write!(
+                f,
+                "Firmware is for '{found}', not '{expected}' \
+                (use --ignore-target-mismatch to flash anyway)"
+            ),
+            CliError::ChecksumMismatch => {
+                write!(f, "Device checksum does not match the image")
+            }
+            CliError::UnknownRegion(name) => {
+                write!(f, "No memory region named \"{}\"", name)
+            }
+            CliError::PartialWrite { failed, total } => {
+                write!(f, "{failed} of {total} ranges could not be written")
+            }
+            CliError::VerifyMismatch { address } => write!(
+                f,
+                "Device content differs from the image at {address:#010x}"
+            ),
         }
     }
 }
@@ -0,0 +1,54 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+use uf2::{UF2_BLOCK_SIZE, is_uf2_block_start};
+
+use crate::CliError;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Read a firmware file, transparently decompressing gzip files
+/// (detected by their magic value or `.gz` extension)
+pub(crate) fn read_firmware(path: &Path) -> Result<Vec<u8>, CliError> {
+    let data = fs::read(path)?;
+    let is_gzip = data.starts_with(&GZIP_MAGIC)
+        || path.extension().is_some_and(|ext| ext == "gz");
+    if is_gzip { decompress(&data) } else { Ok(data) }
+}
+
+/// Open an uncompressed UF2 file to be read block by block, `None` for
+/// other files
+pub(crate) fn open_uf2(
+    path: &Path,
+) -> Result<Option<BufReader<File>>, CliError> {
+    let mut file = File::open(path)?;
+    let mut block = Vec::with_capacity(UF2_BLOCK_SIZE);
+    file.by_ref()
+        .take(UF2_BLOCK_SIZE as u64)
+        .read_to_end(&mut block)?;
+    if !is_uf2_block_start(&block) {
+        return Ok(None);
+    }
+    file.rewind()?;
+    Ok(Some(BufReader::new(file)))
+}
+
+#[cfg(feature = "gzip")]
+fn decompress(data: &[u8]) -> Result<Vec<u8>, CliError> {
+    use std::io::Read;
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress(_data: &[u8]) -> Result<Vec<u8>, CliError> {
+    Err(CliError::IO(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "gzip support not enabled (\"gzip\" feature)",
+    )))
+}
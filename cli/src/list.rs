@@ -1,21 +1,23 @@
-use dfu::{DfuDevice, DfuMemSegment, find_dfu_devices};
+use dfu::{DfuDevice, DfuMemSegment};
 
-use crate::CliError;
+use crate::{CliError, config::config};
 
 pub(crate) fn list_dfu_devices(
     vid: Option<u16>,
     pid: Option<u16>,
+    verbose: bool,
+    commands: bool,
 ) -> Result<(), CliError> {
-    let devices = find_dfu_devices(vid, pid)?;
+    let devices = config().find_devices(vid, pid)?;
     if devices.is_empty() {
         println!("No DFU device found");
     } else {
-        print_devices(&devices);
+        print_devices(&devices, verbose, commands)?;
     }
     Ok(())
 }
 
-fn print_segment(prefix: &str, segment: &DfuMemSegment) {
+pub(crate) fn print_segment(prefix: &str, segment: &DfuMemSegment) {
     let mut page_size = segment.page_size();
     let page_char = if page_size >= 1024 {
         page_size /= 1024;
@@ -24,19 +26,66 @@ fn print_segment(prefix: &str, segment: &DfuMemSegment) {
         " "
     };
     println!(
-        "{}0x{:08X} {:2} pages of {:4}{} bytes ({}{}{})",
+        "{}0x{:08X} {:2} pages of {:4}{} bytes ({})",
         prefix,
         segment.start_addr(),
         segment.pages(),
         page_size,
         page_char,
-        if segment.readable() { "r" } else { "" },
-        if segment.writable() { "w" } else { "" },
-        if segment.erasable() { "e" } else { "" },
+        segment.access(),
     );
 }
 
-fn print_devices(devices: &Vec<DfuDevice>) {
+fn print_descriptor(device: &DfuDevice) -> Result<(), CliError> {
+    let desc = device.dfu_descriptor()?;
+    println!("  Device release: bcdDevice={:#06x}", device.bcd_device());
+    println!(
+        "  DFU descriptor: attributes={:#04x} (download={}, upload={}, \
+        manifestation_tolerant={}, will_detach={})",
+        desc.attributes(),
+        desc.can_download(),
+        desc.can_upload(),
+        desc.manifestation_tolerant(),
+        desc.will_detach(),
+    );
+    println!(
+        "                  detach_timeout={} ms, transfer_size={}, \
+        version={:#06x}",
+        desc.detach_timeout(),
+        desc.transfer_size(),
+        desc.dfu_version(),
+    );
+    Ok(())
+}
+
+// DfuSe special commands advertised by the device, plain DFU devices and
+// failed queries are reported without aborting the listing
+fn print_commands(device: &DfuDevice) {
+    if !device.is_dfuse() {
+        println!("  DfuSe commands: not supported (plain DFU device)");
+        return;
+    }
+    let commands = device.connect(0, 0).and_then(|connection| {
+        let connection = config().configure(connection);
+        config().reset_state(&connection)?;
+        connection.dfuse_get_commands()
+    });
+    match commands {
+        Ok(commands) => {
+            println!("  DfuSe commands:");
+            for command in commands {
+                println!("    {:#04x} {command}", command.code());
+            }
+        }
+        Err(err) => println!("  DfuSe commands: unavailable ({err})"),
+    }
+}
+
+fn print_devices(
+    devices: &Vec<DfuDevice>,
+    verbose: bool,
+    commands: bool,
+) -> Result<(), CliError> {
     for device in devices {
         println!(
             "Bus {} Device {:03}: ID {:04x}:{:04x} (ver={}, dfuse={})",
@@ -47,6 +96,12 @@ fn print_devices(devices: &Vec<DfuDevice>) {
             device.device_version(),
             device.is_dfuse(),
         );
+        if verbose {
+            print_descriptor(device)?;
+        }
+        if commands {
+            print_commands(device);
+        }
 
         for interface in device.interfaces() {
             let layout = interface.layout();
@@ -60,5 +115,22 @@ fn print_devices(devices: &Vec<DfuDevice>) {
                 print_segment("    ", segment);
             }
         }
+        if device.interfaces().is_empty() {
+            println!("  No parsable memory layout, cannot read or write");
+        }
+        for (interface, alt_setting, layout) in device.unparsed_interfaces() {
+            println!(
+                "  interface {} (alt={}, unparsed layout): {}",
+                interface, alt_setting, layout,
+            );
+        }
+        for (intf_a, alt_a, intf_b, alt_b) in device.overlapping_interfaces() {
+            println!(
+                "  Warning: interface {} (alt={}) and interface {} (alt={}) \
+                overlap, the first one is used",
+                intf_a, alt_a, intf_b, alt_b,
+            );
+        }
     }
+    Ok(())
 }
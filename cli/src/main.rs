@@ -1,21 +1,51 @@
-use std::{fs, path::PathBuf, process::ExitCode};
+use std::{
+    cmp, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
 
 use clap::{Parser, Subcommand};
 use clap_num::maybe_hex;
 use parse_size::parse_size;
 
-use dfu::{DfuDevice, find_dfu_devices};
+use bench::*;
+use checksum::Checksum;
+use descriptors::print_descriptors;
+use dfu::{DfuDevice, REBOOT_MAGIC, RebootFormat, list_runtime_devices};
 use error::CliError;
+use input::{open_uf2, read_firmware};
 use list::*;
+use map::print_memory_map;
+use progress::TerminalProgress;
+use range::{AddressRange, parse_range};
 use read::*;
 use reboot::*;
+use report::print_report;
+use status::*;
+use transfer::{InterfaceTransferSize, parse_interface_transfer_size};
 use uf2::*;
+use uid::*;
+use verify::verify;
 use write::*;
 
+mod bench;
+mod checksum;
+mod config;
+mod descriptors;
 mod error;
+mod input;
 mod list;
+mod map;
+mod progress;
+mod range;
 mod read;
 mod reboot;
+mod report;
+mod status;
+mod transfer;
+mod uid;
+mod verify;
 mod write;
 
 #[derive(Parser)]
@@ -23,6 +53,28 @@ mod write;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// USB configuration to use (for devices exposing DFU in several)
+    #[clap(long, global = true)]
+    configuration: Option<u8>,
+    /// wait up to the given number of seconds for a device to appear
+    #[clap(long, global = true, value_name = "SECONDS")]
+    wait: Option<u64>,
+    /// do not reset the device state before reading or writing (the
+    /// first command fails if the device is not idle)
+    #[clap(long, global = true)]
+    no_reset_state: bool,
+    /// only use the device with this USB serial number (case-insensitive)
+    #[clap(long, global = true, value_name = "SERIAL")]
+    serial: Option<String>,
+    /// timeout of each USB control transfer, in milliseconds (default 5000),
+    /// waiting for a busy device is limited to 20 times this
+    #[clap(
+        long,
+        global = true,
+        value_name = "MS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    timeout: Option<u64>,
 }
 
 #[derive(Subcommand)]
@@ -35,6 +87,13 @@ enum Commands {
         /// product ID (ex: "df11")
         #[clap(short, long, value_parser=hex_u16)]
         product: Option<u16>,
+        /// print the DFU functional descriptor
+        #[clap(long)]
+        verbose: bool,
+        /// query and print the DfuSe special commands supported by each
+        /// device (set address, erase, read unprotect...)
+        #[clap(long)]
+        commands: bool,
     },
     /// read from device
     Read {
@@ -49,9 +108,86 @@ enum Commands {
         /// start address (ex: 0x0800000)
         #[clap(short, long, value_parser=maybe_hex::<u32>)]
         start_address: Option<u32>,
-        /// length (ex: 64K, 2MB)
-        #[clap(short, long, value_parser=parse_length)]
+        /// length (ex: 64K, 2MB), defaults to the end of the memory
+        /// segment containing the start address
+        #[clap(
+            short,
+            long,
+            value_parser=parse_nonzero_length,
+            group = "extent"
+        )]
         length: Option<u32>,
+        /// read up to the end of the segment containing the start address
+        #[clap(long, conflicts_with = "length")]
+        to_segment_end: bool,
+        /// read up to the end of the interface's last segment
+        #[clap(long, conflicts_with_all = ["length", "to_segment_end"])]
+        to_interface_end: bool,
+        /// address range (ex: 0x08000000-0x0801FFFF, 0x08000000:128K)
+        #[clap(
+            long,
+            value_parser=parse_range,
+            group = "extent",
+            conflicts_with_all = [
+                "start_address",
+                "length",
+                "to_segment_end",
+                "to_interface_end",
+            ]
+        )]
+        range: Option<AddressRange>,
+        /// read from any address, even outside of the memory segments
+        /// (ex: peripheral registers), requires a length
+        #[clap(
+            long,
+            requires = "extent",
+            conflicts_with = "continue_on_error"
+        )]
+        raw_address: bool,
+        /// fill unreadable blocks with this byte (default: 0xFF) and go on
+        #[clap(
+            long,
+            value_parser=maybe_hex::<u8>,
+            num_args=0..=1,
+            require_equals=true,
+            default_missing_value="0xFF"
+        )]
+        continue_on_error: Option<u8>,
+        /// read back the ranges of this UF2 file and write them as UF2
+        /// with the same structure (for comparison with the original)
+        #[clap(
+            long,
+            value_name = "ORIGINAL_UF2",
+            conflicts_with_all = [
+                "start_address",
+                "length",
+                "to_segment_end",
+                "to_interface_end",
+                "range",
+                "continue_on_error",
+            ]
+        )]
+        as_flashed: Option<PathBuf>,
+    },
+    /// read a string stored on the device (ex: firmware version)
+    ReadString {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+        /// start address (ex: 0x08000200)
+        #[clap(short, long, value_parser=maybe_hex::<u32>)]
+        start_address: u32,
+        /// maximum length (ex: 64, 1K)
+        #[clap(
+            short,
+            long,
+            value_parser=parse_nonzero_length,
+            default_value = "64"
+        )]
+        length: u32,
     },
     /// write to device
     Write {
@@ -66,12 +202,177 @@ enum Commands {
         /// start address (ex: 0x0800000)
         #[clap(short, long, value_parser=maybe_hex::<u32>)]
         start_address: Option<u32>,
+        /// accept UF2 blocks without final magic value
+        #[clap(long)]
+        lenient: bool,
+        /// stay in DFU mode after writing (use `leave` when done)
+        #[clap(long)]
+        no_leave: bool,
+        /// expected UF2 target (ex: "TX16S"), checked against the device
+        /// description of the file
+        #[clap(long)]
+        target: Option<String>,
+        /// flash UF2 files even if built for another target
+        #[clap(long)]
+        ignore_target_mismatch: bool,
+        /// pad the image up to the next page boundary (default fill: 0xFF)
+        #[clap(
+            long,
+            value_parser=maybe_hex::<u8>,
+            num_args=0..=1,
+            require_equals=true,
+            default_missing_value="0xFF"
+        )]
+        pad: Option<u8>,
+        /// read back the written image and print its checksum
+        #[clap(long, value_enum)]
+        checksum: Option<Checksum>,
+        /// address range the image must fit in
+        /// (ex: 0x08000000-0x0801FFFF, 0x08000000:128K)
+        #[clap(long, value_parser=parse_range, conflicts_with = "start_address")]
+        range: Option<AddressRange>,
+        /// transfer size for an interface (ex: "0:1=256" for alternate
+        /// setting 1 of interface 0, "0=1024" for all of them)
+        #[clap(
+            long,
+            value_name = "INTERFACE[:ALT]=SIZE",
+            value_parser=parse_interface_transfer_size
+        )]
+        transfer_size_for: Vec<InterfaceTransferSize>,
+        /// write a raw binary at the start of the memory region with this
+        /// name (ex: "Internal Flash"), the image must fit in the region
+        #[clap(long, conflicts_with_all = ["start_address", "range"])]
+        region: Option<String>,
+        /// print an estimate of the flashing time and exit
+        #[clap(long)]
+        estimate: bool,
+        /// erase all pages first or right before writing each chunk
+        #[clap(long, value_enum, default_value_t)]
+        erase_strategy: EraseStrategy,
+        /// compare each page first and skip the unchanged ones
+        #[clap(long)]
+        only_changed: bool,
+        /// keep writing the remaining UF2 ranges when one fails and print
+        /// which ranges were written at the end
+        #[clap(long)]
+        keep_going: bool,
+        /// read back every written range and compare it with the image
+        /// before leaving DFU mode
+        #[clap(long)]
+        verify: bool,
+    },
+    /// compare a file with the device content
+    Verify {
+        /// file to compare (either raw binary or UF2)
+        file: PathBuf,
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+        /// start address (ex: 0x0800000)
+        #[clap(short, long, value_parser=maybe_hex::<u32>)]
+        start_address: Option<u32>,
+        /// accept UF2 blocks without final magic value
+        #[clap(long)]
+        lenient: bool,
+        /// print the differing bytes (up to N lines of 16 bytes)
+        #[clap(
+            long,
+            value_name = "N",
+            num_args=0..=1,
+            require_equals=true,
+            default_missing_value="16"
+        )]
+        hexdiff: Option<usize>,
+    },
+    /// leave DFU mode and start the firmware
+    Leave {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
     },
     /// reboot into EdgeTX DFU bootloader
     Reboot {
-        /// reboot tag address
-        #[clap(value_parser=maybe_hex::<u32>)]
-        address: u32,
+        /// reboot tag address (same as --tag-address)
+        #[clap(
+            value_parser=maybe_hex::<u32>,
+            required_unless_present_any=["from_uf2", "tag_address"],
+            conflicts_with_all=["from_uf2", "tag_address"]
+        )]
+        address: Option<u32>,
+        /// address where the reboot tag is written, usually in RAM
+        /// (ex: 0x2001fff0)
+        #[clap(long, value_parser=maybe_hex::<u32>, conflicts_with="from_uf2")]
+        tag_address: Option<u32>,
+        /// use reboot tag and address from UF2 file
+        #[clap(long)]
+        from_uf2: Option<PathBuf>,
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+        /// address where execution resumes after the reboot, in flash
+        /// (default: 0x08000000, or as declared in the UF2 file)
+        #[clap(
+            short='s',
+            long,
+            visible_alias="start-address",
+            value_parser=maybe_hex::<u32>
+        )]
+        jump_address: Option<u32>,
+        /// reboot structure expected by the bootloader
+        /// (default: "tag", or as declared in the UF2 file)
+        #[clap(long, value_enum)]
+        reboot_format: Option<RebootFormatArg>,
+        /// flags of the extended reboot format (implies "extended")
+        #[clap(long, value_parser=maybe_hex::<u32>)]
+        reboot_flags: Option<u32>,
+    },
+    /// write the reboot tag without rebooting (the device enters the
+    /// EdgeTX DFU bootloader on its next reset)
+    WriteTag {
+        /// reboot tag address (same as --tag-address)
+        #[clap(
+            value_parser=maybe_hex::<u32>,
+            required_unless_present="tag_address",
+            conflicts_with="tag_address"
+        )]
+        address: Option<u32>,
+        /// address where the reboot tag is written, usually in RAM
+        /// (ex: 0x2001fff0)
+        #[clap(long, value_parser=maybe_hex::<u32>)]
+        tag_address: Option<u32>,
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+        /// jump address stored in the extended format
+        /// (default: 0x08000000)
+        #[clap(
+            short='s',
+            long,
+            visible_alias="start-address",
+            value_parser=maybe_hex::<u32>
+        )]
+        jump_address: Option<u32>,
+        /// reboot structure expected by the bootloader (default: "tag")
+        #[clap(long, value_enum)]
+        reboot_format: Option<RebootFormatArg>,
+        /// flags of the extended reboot format (implies "extended")
+        #[clap(long, value_parser=maybe_hex::<u32>)]
+        reboot_flags: Option<u32>,
+    },
+    /// measure read speed for several transfer sizes
+    Bench {
         /// vendor ID (ex: "0483")
         #[clap(short, long, value_parser=hex_u16)]
         vendor: Option<u16>,
@@ -81,11 +382,138 @@ enum Commands {
         /// start address (ex: 0x0800000)
         #[clap(short, long, value_parser=maybe_hex::<u32>)]
         start_address: Option<u32>,
+        /// length (ex: 64K, 2MB)
+        #[clap(short, long, value_parser=parse_nonzero_length)]
+        length: Option<u32>,
+    },
+    /// print DFU status and state of device
+    Status {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+    },
+    /// switch a device in runtime mode to DFU mode (DFU_DETACH, followed
+    /// by a USB reset if the device does not detach by itself)
+    Detach {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+    },
+    /// dump the raw USB descriptors (device, configurations, interfaces
+    /// and DFU functional descriptors) with their decoded fields
+    Descriptors {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+    },
+    /// print a device report to attach to bug reports
+    Report {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+    },
+    /// print the memory segments of all interfaces as a table
+    Map {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+    },
+    /// print the unique device ID (STM32)
+    Uid {
+        /// vendor ID (ex: "0483")
+        #[clap(short, long, value_parser=hex_u16)]
+        vendor: Option<u16>,
+        /// product ID (ex: "df11")
+        #[clap(short, long, value_parser=hex_u16)]
+        product: Option<u16>,
+        /// UID address (ex: 0x1FFF7A10), detected from the device if omitted
+        #[clap(short, long, value_parser=maybe_hex::<u32>)]
+        address: Option<u32>,
     },
     /// inspect UF2 file
+    #[command(args_conflicts_with_subcommands = true)]
     Uf2 {
+        #[command(subcommand)]
+        command: Option<Uf2Commands>,
+        /// UF2 file
+        #[clap(required = true)]
+        file: Option<PathBuf>,
+        /// accept UF2 blocks without final magic value
+        #[clap(long)]
+        lenient: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum Uf2Commands {
+    /// extract a flash region to a raw binary
+    Extract {
+        /// UF2 file
+        file: PathBuf,
+        /// start address (ex: 0x0800000)
+        #[clap(short, long, value_parser=maybe_hex::<u32>)]
+        start_address: u32,
+        /// length (ex: 64K), defaults to the end of the contiguous range
+        #[clap(short, long, value_parser=parse_length)]
+        length: Option<u32>,
+        /// output file
+        #[clap(short, long)]
+        output: PathBuf,
+        /// accept UF2 blocks without final magic value
+        #[clap(long)]
+        lenient: bool,
+    },
+    /// check every block of a UF2 file (fails on the first error)
+    Validate {
+        /// UF2 file
+        file: PathBuf,
+    },
+    /// flatten the flash ranges to a raw binary starting at the lowest
+    /// address (gaps are filled)
+    ToBin {
         /// UF2 file
         file: PathBuf,
+        /// output file
+        #[clap(short, long)]
+        output: PathBuf,
+        /// byte filling the gaps between ranges
+        #[clap(long, value_parser=maybe_hex::<u8>, default_value = "0xFF")]
+        fill: u8,
+        /// refuse images larger than this (ex: ranges far apart)
+        #[clap(long, value_parser=parse_length, default_value = "256M")]
+        max_size: u32,
+        /// accept UF2 blocks without final magic value
+        #[clap(long)]
+        lenient: bool,
+    },
+    /// encode a raw binary to UF2
+    BinToUf2 {
+        /// raw binary file
+        file: PathBuf,
+        /// address of the first byte (ex: 0x08000000)
+        #[clap(short, long, value_parser=maybe_hex::<u32>)]
+        base_address: u32,
+        /// family ID stored in each block (ex: 0x57755a57)
+        #[clap(long, value_parser=maybe_hex::<u32>)]
+        family_id: Option<u32>,
+        /// output file
+        #[clap(short, long)]
+        output: PathBuf,
     },
 }
 
@@ -94,6 +522,8 @@ impl Default for Commands {
         Commands::List {
             vendor: None,
             product: None,
+            verbose: false,
+            commands: false,
         }
     }
 }
@@ -107,34 +537,243 @@ fn parse_length(s: &str) -> Result<u32, String> {
     len.try_into().map_err(|e| format!("{e}"))
 }
 
+fn parse_nonzero_length(s: &str) -> Result<u32, String> {
+    match parse_length(s)? {
+        0 => Err("length must not be 0".into()),
+        len => Ok(len),
+    }
+}
+
 fn main() -> ExitCode {
     let cli = Cli::parse();
-    env_logger::init();
+    // warnings (ex: devices skipped, transfer size fallback) are shown
+    // unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("warn"),
+    )
+    .init();
+
+    if let Err(err) = config::init(
+        cli.configuration,
+        cli.wait.map(Duration::from_secs),
+        cli.no_reset_state,
+        cli.serial,
+        cli.timeout,
+    ) {
+        eprintln!("Error: {err}");
+        return ExitCode::FAILURE;
+    }
 
     if let Err(err) = match &cli.command.unwrap_or_default() {
-        Commands::List { vendor, product } => {
-            list_dfu_devices(*vendor, *product)
-        }
+        Commands::List {
+            vendor,
+            product,
+            verbose,
+            commands,
+        } => list_dfu_devices(*vendor, *product, *verbose, *commands),
+        Commands::Read {
+            file,
+            vendor,
+            product,
+            as_flashed: Some(original),
+            ..
+        } => read_as_flashed(file, vendor, product, original),
         Commands::Read {
             file,
             vendor,
             product,
             start_address,
             length,
-        } => read_file(file, vendor, product, start_address, length),
+            to_segment_end: _,
+            to_interface_end,
+            range,
+            continue_on_error,
+            raw_address,
+            as_flashed: None,
+        } => {
+            let length = range.map(|r| r.len()).or(*length);
+            let read_end = match length {
+                Some(length) => ReadEnd::Length(length),
+                None if *to_interface_end => ReadEnd::InterfaceEnd,
+                None => ReadEnd::SegmentEnd,
+            };
+            let start_address = range.map(|r| r.start).or(*start_address);
+            read_file(
+                file,
+                vendor,
+                product,
+                &start_address,
+                read_end,
+                *continue_on_error,
+                *raw_address,
+            )
+        }
+        Commands::ReadString {
+            vendor,
+            product,
+            start_address,
+            length,
+        } => read_string_cmd(vendor, product, *start_address, *length),
         Commands::Write {
             file,
             vendor,
             product,
             start_address,
-        } => write_file(file, vendor, product, start_address),
+            lenient,
+            no_leave,
+            target,
+            ignore_target_mismatch,
+            pad,
+            checksum,
+            range,
+            region,
+            estimate,
+            erase_strategy,
+            only_changed,
+            transfer_size_for,
+            keep_going,
+            verify,
+        } => write_file(
+            file,
+            vendor,
+            product,
+            *estimate,
+            region.as_deref(),
+            WriteOptions {
+                start_address: range.map(|r| r.start).or(*start_address),
+                end_address: range.map(|r| r.end),
+                mode: decode_mode(*lenient),
+                no_leave: *no_leave,
+                target: target.clone(),
+                ignore_target_mismatch: *ignore_target_mismatch,
+                pad: *pad,
+                checksum: *checksum,
+                erase_strategy: *erase_strategy,
+                only_changed: *only_changed,
+                transfer_sizes: transfer_size_for.clone(),
+                keep_going: *keep_going,
+                verify: *verify,
+            },
+        ),
+        Commands::Verify {
+            file,
+            vendor,
+            product,
+            start_address,
+            lenient,
+            hexdiff,
+        } => verify_file(
+            file,
+            vendor,
+            product,
+            *start_address,
+            *lenient,
+            *hexdiff,
+        ),
+        Commands::Leave { vendor, product } => leave_cmd(vendor, product),
         Commands::Reboot {
             address,
+            tag_address,
+            from_uf2,
+            vendor,
+            product,
+            jump_address,
+            reboot_format,
+            reboot_flags,
+        } => match from_uf2 {
+            Some(file) => reboot_uf2_cmd(
+                file,
+                vendor,
+                product,
+                jump_address,
+                reboot_format,
+                reboot_flags,
+            ),
+            None => reboot_cmd(
+                &address.or(*tag_address).unwrap_or_default(),
+                vendor,
+                product,
+                jump_address,
+                reboot::reboot_format(*reboot_format, *reboot_flags),
+            ),
+        },
+        Commands::WriteTag {
+            address,
+            tag_address,
+            vendor,
+            product,
+            jump_address,
+            reboot_format,
+            reboot_flags,
+        } => write_tag_cmd(
+            &address.or(*tag_address).unwrap_or_default(),
+            vendor,
+            product,
+            jump_address,
+            reboot::reboot_format(*reboot_format, *reboot_flags),
+        ),
+        Commands::Bench {
             vendor,
             product,
             start_address,
-        } => reboot_cmd(address, vendor, product, start_address),
-        Commands::Uf2 { file } => show_uf2(file),
+            length,
+        } => bench_cmd(vendor, product, start_address, length),
+        Commands::Status { vendor, product } => status_cmd(vendor, product),
+        Commands::Detach { vendor, product } => detach_cmd(vendor, product),
+        Commands::Map { vendor, product } => map_cmd(vendor, product),
+        Commands::Descriptors { vendor, product } => {
+            print_descriptors(*vendor, *product)
+        }
+        Commands::Report { vendor, product } => print_report(*vendor, *product),
+        Commands::Uid {
+            vendor,
+            product,
+            address,
+        } => uid_cmd(vendor, product, address),
+        Commands::Uf2 {
+            command:
+                Some(Uf2Commands::Extract {
+                    file,
+                    start_address,
+                    length,
+                    output,
+                    lenient,
+                }),
+            ..
+        } => extract_uf2(file, *start_address, *length, output, *lenient),
+        Commands::Uf2 {
+            command: Some(Uf2Commands::Validate { file }),
+            ..
+        } => validate_uf2(file),
+        Commands::Uf2 {
+            command:
+                Some(Uf2Commands::ToBin {
+                    file,
+                    output,
+                    fill,
+                    max_size,
+                    lenient,
+                }),
+            ..
+        } => uf2_to_bin(file, output, *fill, *max_size, *lenient),
+        Commands::Uf2 {
+            command:
+                Some(Uf2Commands::BinToUf2 {
+                    file,
+                    base_address,
+                    family_id,
+                    output,
+                }),
+            ..
+        } => bin_to_uf2(file, *base_address, *family_id, output),
+        Commands::Uf2 {
+            command: None,
+            file,
+            lenient,
+        } => match file {
+            Some(file) => show_uf2(file, *lenient),
+            None => Ok(()),
+        },
     } {
         eprintln!("Error: {err}");
         ExitCode::FAILURE
@@ -147,7 +786,8 @@ fn get_dfu_device(
     vid: &Option<u16>,
     pid: &Option<u16>,
 ) -> Result<DfuDevice, CliError> {
-    let devices = find_dfu_devices(*vid, *pid)?;
+    let config = config::config();
+    let devices = config.find_devices(*vid, *pid)?;
     if devices.is_empty() {
         return Err(CliError::NoDFUDevice);
     }
@@ -156,7 +796,11 @@ fn get_dfu_device(
         return Err(CliError::ManyDFUDevices);
     }
 
-    Ok(devices.into_iter().next().unwrap())
+    let device = devices.into_iter().next().unwrap();
+    Ok(match config.configuration {
+        Some(configuration) => device.select_configuration(configuration)?,
+        None => device,
+    })
 }
 
 fn read_file(
@@ -164,45 +808,322 @@ fn read_file(
     vid: &Option<u16>,
     pid: &Option<u16>,
     start_address: &Option<u32>,
-    length: &Option<u32>,
+    read_end: ReadEnd,
+    error_fill: Option<u8>,
+    raw_address: bool,
+) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    let (data, result) = match read_end {
+        ReadEnd::Length(length) if raw_address => {
+            upload_raw(device, *start_address, length, &mut TerminalProgress)?
+        }
+        _ => upload(
+            device,
+            *start_address,
+            read_end,
+            error_fill,
+            &mut TerminalProgress,
+        )?,
+    };
+    fs::write(file, data)?;
+    print_upload_result(&result);
+    Ok(())
+}
+
+fn read_as_flashed(
+    file: &Path,
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    original: &Path,
 ) -> Result<(), CliError> {
     let device = get_dfu_device(vid, pid)?;
-    let data = upload(device, *start_address, *length)?;
+    let original = read_firmware(original)?;
+    let data = upload_as_flashed(device, &original, &mut TerminalProgress)?;
     fs::write(file, data)?;
     Ok(())
 }
 
+fn read_string_cmd(
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    start_address: u32,
+    length: u32,
+) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    let string = read_string(device, start_address, length)?;
+    println!("{string}");
+    Ok(())
+}
+
 fn write_file(
-    file: &PathBuf,
+    file: &Path,
     vid: &Option<u16>,
     pid: &Option<u16>,
-    start_address: &Option<u32>,
+    estimate_only: bool,
+    region: Option<&str>,
+    options: WriteOptions,
+) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    // UF2 files are written as they are read, without loading the image
+    if !estimate_only && let Some(reader) = open_uf2(file)? {
+        if region.is_some() {
+            println!("Warning: --region ignored, UF2 files hold addresses");
+        }
+        let result =
+            download_uf2(reader, device, &options, &mut TerminalProgress)?;
+        print_download_result(&result);
+        return Ok(());
+    }
+    let data = read_firmware(file)?;
+    let options = match region {
+        Some(_) if is_uf2_payload(&data) => {
+            println!("Warning: --region ignored, UF2 files hold addresses");
+            options
+        }
+        Some(name) => in_region(&device, name, options)?,
+        None => options,
+    };
+    if estimate_only {
+        let duration = estimate(&data, &device, &options)?;
+        println!("Estimated flashing time: {}s", duration.as_secs().max(1));
+        return Ok(());
+    }
+    let result = download(&data, device, &options, &mut TerminalProgress)?;
+    print_download_result(&result);
+    Ok(())
+}
+
+fn verify_file(
+    file: &Path,
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    start_address: Option<u32>,
+    lenient: bool,
+    hexdiff: Option<usize>,
 ) -> Result<(), CliError> {
     let device = get_dfu_device(vid, pid)?;
-    let data = fs::read(file)?;
-    download(&data, device, *start_address)?;
+    let data = read_firmware(file)?;
+    verify(
+        &data,
+        &device,
+        start_address,
+        decode_mode(lenient),
+        hexdiff,
+        &mut TerminalProgress,
+    )
+}
+
+fn leave_cmd(vid: &Option<u16>, pid: &Option<u16>) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    leave(&device)?;
     Ok(())
 }
 
 fn reboot_cmd(
-    address: &u32,
+    tag_address: &u32,
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    jump_address: &Option<u32>,
+    format: RebootFormat,
+) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    reboot(*tag_address, REBOOT_MAGIC, format, device, *jump_address)?;
+    Ok(())
+}
+
+fn write_tag_cmd(
+    tag_address: &u32,
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    jump_address: &Option<u32>,
+    format: RebootFormat,
+) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    write_tag(*tag_address, REBOOT_MAGIC, format, device, *jump_address)
+}
+
+fn reboot_uf2_cmd(
+    file: &Path,
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    jump_address: &Option<u32>,
+    format: &Option<RebootFormatArg>,
+    flags: &Option<u32>,
+) -> Result<(), CliError> {
+    let data = read_firmware(file)?;
+    let tag = UF2RangeIterator::new(&data)?
+        .find(|addr_range| addr_range.reboot_address.is_some())
+        .ok_or(UF2DecodeError::new("no reboot extension".to_string()))?;
+    let format = reboot_format(*format, flags.or(tag.reboot_flags));
+    let device = get_dfu_device(vid, pid)?;
+    reboot(
+        tag.start_address,
+        &tag.payload,
+        format,
+        device,
+        jump_address.or(tag.reboot_address),
+    )?;
+    Ok(())
+}
+
+fn bench_cmd(
     vid: &Option<u16>,
     pid: &Option<u16>,
     start_address: &Option<u32>,
+    length: &Option<u32>,
+) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    bench(device, *start_address, *length)
+}
+
+fn status_cmd(vid: &Option<u16>, pid: &Option<u16>) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    print_status(device)
+}
+
+// The configured IDs designate the device in DFU mode, not the application
+fn detach_cmd(vid: &Option<u16>, pid: &Option<u16>) -> Result<(), CliError> {
+    let serial = config::config().serial.as_deref();
+    let mut devices = list_runtime_devices(*vid, *pid, serial)?;
+    if devices.len() > 1 {
+        return Err(CliError::ManyDFUDevices);
+    }
+    let device = devices.pop().ok_or(CliError::NoDFUDevice)?;
+    println!(
+        "Detaching {:04x}:{:04x} (interface {})...",
+        device.vendor_id(),
+        device.product_id(),
+        device.interface()
+    );
+    if device.detach()? {
+        println!("Detach requested, the device re-enumerates by itself");
+    } else {
+        println!("Detach requested, device reset");
+    }
+    Ok(())
+}
+
+fn map_cmd(vid: &Option<u16>, pid: &Option<u16>) -> Result<(), CliError> {
+    let device = get_dfu_device(vid, pid)?;
+    print_memory_map(&device)
+}
+
+fn uid_cmd(
+    vid: &Option<u16>,
+    pid: &Option<u16>,
+    address: &Option<u32>,
 ) -> Result<(), CliError> {
     let device = get_dfu_device(vid, pid)?;
-    reboot(*address, device, *start_address)?;
+    print_uid(device, *address)
+}
+
+fn decode_mode(lenient: bool) -> UF2DecodeMode {
+    if lenient {
+        UF2DecodeMode::Lenient
+    } else {
+        UF2DecodeMode::Strict
+    }
+}
+
+fn extract_uf2(
+    file: &Path,
+    start_address: u32,
+    length: Option<u32>,
+    output: &PathBuf,
+    lenient: bool,
+) -> Result<(), CliError> {
+    let data = read_firmware(file)?;
+    let region =
+        extract_region(&data, decode_mode(lenient), start_address, length)?;
+    fs::write(output, &region)?;
+    println!(
+        "Extracted {} bytes from {start_address:#010x} to {}",
+        region.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+fn uf2_to_bin(
+    file: &Path,
+    output: &Path,
+    fill: u8,
+    max_size: u32,
+    lenient: bool,
+) -> Result<(), CliError> {
+    let data = read_firmware(file)?;
+    let image = flatten(&data, decode_mode(lenient), fill, max_size)?;
+    if image.filled as usize > image.data.len() / 2 {
+        println!(
+            "Warning: sparse image, {} of {} bytes are gaps",
+            image.filled,
+            image.data.len()
+        );
+    }
+    fs::write(output, &image.data)?;
+    println!(
+        "Wrote {} bytes from {:#010x} to {}",
+        image.data.len(),
+        image.start_address,
+        output.display()
+    );
+    Ok(())
+}
+
+fn bin_to_uf2(
+    file: &Path,
+    base_address: u32,
+    family_id: Option<u32>,
+    output: &Path,
+) -> Result<(), CliError> {
+    let data = read_firmware(file)?;
+    let encoded = encode(&data, base_address, family_id)?;
+    fs::write(output, &encoded)?;
+    println!(
+        "Wrote {} blocks from {base_address:#010x} to {}",
+        encoded.len() / UF2_BLOCK_SIZE,
+        output.display()
+    );
+    Ok(())
+}
+
+fn validate_uf2(file: &Path) -> Result<(), CliError> {
+    let data = read_firmware(file)?;
+    let report = validate(&data)?;
+    println!("Blocks: {}", report.blocks);
+    if !report.families.is_empty() {
+        let families: Vec<String> = report
+            .families
+            .iter()
+            .map(|family| format!("{family:#010x}"))
+            .collect();
+        println!("Families: {}", families.join(", "));
+    }
+    println!(
+        "Extent: {:#010x} - {:#010x}",
+        report.extent.0,
+        report.extent.1 - 1
+    );
+    if let Some(addr) = report.reboot_address {
+        println!("Reboot address: {addr:#010x}");
+    }
+    if report.md5_verified > 0 {
+        println!("MD5 checksums verified: {}", report.md5_verified);
+    }
+    println!("UF2 file is valid");
     Ok(())
 }
 
-fn show_uf2(file: &PathBuf) -> Result<(), CliError> {
-    let data = fs::read(file)?;
-    if !is_uf2_block(&data) {
+fn show_uf2(file: &Path, lenient: bool) -> Result<(), CliError> {
+    let mode = decode_mode(lenient);
+    let data = read_firmware(file)?;
+    let first_block = &data[0..cmp::min(data.len(), UF2_BLOCK_SIZE)];
+    if !mode.is_valid_block(first_block) {
         return Err(CliError::UF2(UF2DecodeError::new(
             "invalid first block".to_string(),
         )));
     }
-    let block = UF2BlockData::decode(&data[0..UF2_BLOCK_SIZE])?;
+    let block = UF2BlockData::decode_with_mode(first_block, mode)?;
     println!(
         "Device: {}",
         block.get_device_description().unwrap_or_default()
@@ -213,7 +1134,7 @@ fn show_uf2(file: &PathBuf) -> Result<(), CliError> {
     );
 
     println!("Parts:");
-    for addr_range in UF2RangeIterator::new(&data)? {
+    for addr_range in UF2RangeIterator::with_mode(&data, mode)? {
         println!(
             "  - 0x{:08x}: {:7} bytes{}",
             addr_range.start_address,
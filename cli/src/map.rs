@@ -0,0 +1,64 @@
+use dfu::{DfuDevice, DfuMemSegment};
+
+use crate::CliError;
+
+/// Print all memory segments of the device as a table sorted by address
+pub(crate) fn print_memory_map(device: &DfuDevice) -> Result<(), CliError> {
+    let mut rows: Vec<(u8, u8, &str, &DfuMemSegment)> = device
+        .all_segments()
+        .map(|(intf, segment)| {
+            (
+                intf.interface(),
+                intf.alt_setting(),
+                intf.layout().name.as_str(),
+                segment,
+            )
+        })
+        .collect();
+    rows.sort_by_key(|(intf, alt, _, segment)| {
+        (segment.start_addr(), *intf, *alt)
+    });
+
+    let name_width = rows
+        .iter()
+        .map(|(_, _, name, _)| name.len())
+        .max()
+        .unwrap_or_default()
+        .max("Name".len());
+    println!(
+        "Intf/Alt  {:name_width$}  Start       End         Page size  Pages  Access",
+        "Name"
+    );
+    for (intf, alt, name, segment) in rows {
+        println!(
+            "{:>4}/{:<3}  {:name_width$}  0x{:08X}  0x{:08X}  {:>9}  {:>5}  {}",
+            intf,
+            alt,
+            name,
+            segment.start_addr(),
+            segment.end_addr() - 1,
+            segment.page_size(),
+            segment.pages(),
+            access_flags(segment),
+        );
+    }
+    for (intf_a, alt_a, intf_b, alt_b) in device.overlapping_interfaces() {
+        println!(
+            "Warning: {intf_a}/{alt_a} and {intf_b}/{alt_b} overlap, \
+            the first one is used"
+        );
+    }
+    Ok(())
+}
+
+// Fixed width "rwe" flags, '-' when not set
+fn access_flags(segment: &DfuMemSegment) -> String {
+    [
+        (segment.readable(), 'r'),
+        (segment.writable(), 'w'),
+        (segment.erasable(), 'e'),
+    ]
+    .iter()
+    .map(|(set, c)| if *set { *c } else { '-' })
+    .collect()
+}
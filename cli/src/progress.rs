@@ -0,0 +1,52 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use dfu::{DfuStatus, Progress, ProgressSink};
+
+const BAR_WIDTH: u64 = 60;
+// Waits shorter than this are not worth an indicator
+const BUSY_THRESHOLD: Duration = Duration::from_secs(1);
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Renders progress as a single line bar on stdout
+#[derive(Default)]
+pub(crate) struct TerminalProgress;
+
+impl ProgressSink for TerminalProgress {
+    fn update(&mut self, progress: &Progress) {
+        let filled = progress.fraction(BAR_WIDTH) as usize;
+        print!(
+            "\r  {:9} {:3}% [{}]",
+            progress.phase.label(),
+            progress.percentage(),
+            "#".repeat(filled) + &" ".repeat(BAR_WIDTH as usize - filled)
+        );
+        let _ = io::stdout().flush();
+    }
+
+    fn finish(&mut self, _progress: &Progress) {
+        println!();
+    }
+}
+
+/// Discards progress updates
+pub(crate) struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn update(&mut self, _progress: &Progress) {}
+}
+
+/// Animated indicator shown while the device is busy erasing
+/// (ex: full-chip erase taking 30+ seconds)
+pub(crate) fn show_erase_wait(_status: &DfuStatus, elapsed: Duration) {
+    if elapsed < BUSY_THRESHOLD {
+        return;
+    }
+    let spinner = SPINNER[(elapsed.as_millis() / 250) as usize % SPINNER.len()];
+    let line = format!("  Erasing… ({}s) {spinner}", elapsed.as_secs());
+    // overwrite the whole progress bar line
+    print!("\r{line:<width$}", width = BAR_WIDTH as usize + 17);
+    let _ = io::stdout().flush();
+}
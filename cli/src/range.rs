@@ -0,0 +1,67 @@
+use clap_num::maybe_hex;
+
+use crate::parse_length;
+
+/// Address range given as `<start>-<end>` (inclusive end) or
+/// `<start>:<length>` (ex: `0x08000000-0x0801FFFF`, `0x08000000:128K`)
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AddressRange {
+    pub start: u32,
+    /// inclusive
+    pub end: u32,
+}
+
+impl AddressRange {
+    pub fn len(&self) -> u32 {
+        self.end - self.start + 1
+    }
+}
+
+pub(crate) fn parse_range(s: &str) -> Result<AddressRange, String> {
+    let (start, end) = if let Some((start, end)) = s.split_once('-') {
+        (
+            maybe_hex::<u32>(start.trim())?,
+            maybe_hex::<u32>(end.trim())?,
+        )
+    } else if let Some((start, length)) = s.split_once(':') {
+        let start = maybe_hex::<u32>(start.trim())?;
+        let length = parse_length(length.trim())?;
+        if length == 0 {
+            return Err("empty range".into());
+        }
+        let end = start
+            .checked_add(length - 1)
+            .ok_or("range exceeds the address space")?;
+        (start, end)
+    } else {
+        return Err("expected <start>-<end> or <start>:<length>".into());
+    };
+    if start > end {
+        return Err(format!(
+            "start ({start:#010x}) must not be above end ({end:#010x})"
+        ));
+    }
+    Ok(AddressRange { start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let range = parse_range("0x08000000:1").unwrap();
+        assert_eq!(
+            (range.start, range.end, range.len()),
+            (0x08000000, 0x08000000, 1)
+        );
+        let range = parse_range("0x08000000-0x08000000").unwrap();
+        assert_eq!(range.len(), 1);
+        let range = parse_range("0x08000000:131072").unwrap();
+        assert_eq!(range.end, 0x0801ffff);
+        assert!(parse_range("0x08000001-0x08000000").is_err());
+        assert!(parse_range("0x08000000:0").is_err());
+        assert!(parse_range("0xffffffff:2").is_err());
+        assert!(parse_range("0x08000000").is_err());
+    }
+}
@@ -1,61 +1,399 @@
-use std::{
-    cmp,
-    io::{self, Write},
+use std::{cmp, time::Instant};
+
+use dfu::{
+    DfuConnection, DfuDevice, DfuError, Phase, Progress, ProgressSink,
+    UploadResult,
 };
+use uf2::{UF2DecodeMode, UF2RangeIterator, replace_payloads};
 
-use dfu::{DfuDevice, DfuError};
+use crate::{CliError, config::config, progress::NoProgress};
 
-use crate::CliError;
+/// Where reading stops
+#[derive(Clone, Copy)]
+pub(crate) enum ReadEnd {
+    /// explicit number of bytes
+    Length(u32),
+    /// end of the segment containing the start address (or of the next
+    /// segment if the start address is not within a segment)
+    SegmentEnd,
+    /// end of the last segment of the interface
+    InterfaceEnd,
+}
 
 pub(crate) fn upload(
     device: DfuDevice,
     start_address: Option<u32>,
-    length: Option<u32>,
-) -> Result<Vec<u8>, CliError> {
+    read_end: ReadEnd,
+    error_fill: Option<u8>,
+    sink: &mut dyn ProgressSink,
+) -> Result<(Vec<u8>, UploadResult), CliError> {
+    if !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
+
     let start_address =
         start_address.unwrap_or(device.get_default_start_address());
-    let end_address = length.map(|l| start_address + l - 1);
+    let end_address = match read_end {
+        ReadEnd::Length(length) => Some(last_address(start_address, length)?),
+        _ => None,
+    };
+
+    let (connection, intf_segments) =
+        device.connect_for_address(start_address, end_address)?;
+    let connection = config().configure(connection);
+    let end_address = match read_end {
+        ReadEnd::Length(length) => last_address(start_address, length)?,
+        ReadEnd::SegmentEnd => {
+            let segments = intf_segments.segments();
+            segments
+                .iter()
+                .find(|s| s.contains(start_address))
+                .unwrap_or(segments.first())
+                .end_addr()
+                - 1
+        }
+        ReadEnd::InterfaceEnd => intf_segments.end_addr() - 1,
+    };
 
-    let intf = device.find_interface(start_address, end_address)?;
-    let segments = intf.find_segments(start_address, end_address);
-    if segments.is_empty() {
-        return Err(CliError::Dfu(DfuError::NoMemorySegments));
+    config().reset_state(&connection)?;
+
+    println!("Setting start address ({start_address:#010x})...");
+    let started = Instant::now();
+    let total = end_address + 1 - start_address;
+    let mut data: Vec<u8> = Vec::new();
+    // each upload stays within a single segment
+    let spans = intf_segments
+        .interface()
+        .layout()
+        .split_at_segments(start_address, total);
+    let failed = upload_spans_with(
+        &connection,
+        &spans,
+        error_fill,
+        sink,
+        &mut |chunk| data.extend_from_slice(chunk),
+    )?;
+    if !failed.is_empty() {
+        println!("Failed to read {} block(s):", failed.len());
+        for (start, end) in failed {
+            println!("  {start:#010x} - {end:#010x}");
+        }
     }
+    let result = UploadResult {
+        start_address,
+        bytes_read: data.len() as u64,
+        elapsed: started.elapsed(),
+    };
+    Ok((data, result))
+}
 
-    let end_address =
-        end_address.unwrap_or(segments.last().unwrap().end_addr() - 1);
+/// Last address of `length` bytes from `start_address`
+fn last_address(start_address: u32, length: u32) -> Result<u32, CliError> {
+    length
+        .checked_sub(1)
+        .and_then(|n| start_address.checked_add(n))
+        .ok_or(CliError::InvalidLength {
+            start: start_address,
+            length,
+        })
+}
 
-    let connection = device.connect(intf.interface(), intf.alt_setting())?;
-    let transfer_size = connection.transfer_size() as u32;
+/// Upload `length` bytes from `start_address` without checking the
+/// memory segments (ex: peripheral registers), through the first
+/// interface and alternate setting
+pub(crate) fn upload_raw(
+    device: DfuDevice,
+    start_address: Option<u32>,
+    length: u32,
+    sink: &mut dyn ProgressSink,
+) -> Result<(Vec<u8>, UploadResult), CliError> {
+    if !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
 
-    println!("Reseting state...");
-    connection.reset_state()?;
+    let start_address =
+        start_address.unwrap_or(device.get_default_start_address());
+    let end_address = last_address(start_address, length)?;
+    if device
+        .find_interface(start_address, Some(end_address))
+        .is_err()
+    {
+        println!(
+            "Warning: reading outside of the memory segments, \
+            the device may return garbage or stop responding"
+        );
+    }
+    let connection = config().configure(device.connect(0, 0)?);
+    config().reset_state(&connection)?;
 
     println!("Setting start address ({start_address:#010x})...");
-    connection.dfuse_set_address(start_address)?;
-    connection.reset_state()?;
+    let started = Instant::now();
+    let data = upload_range(&connection, start_address, length, sink)?;
+    let result = UploadResult {
+        start_address,
+        bytes_read: data.len() as u64,
+        elapsed: started.elapsed(),
+    };
+    Ok((data, result))
+}
 
-    let total = end_address + 1 - start_address;
-    let mut bytes_uploaded: u32 = 0;
-    let mut block_nr: u16 = 0;
+/// Read `length` bytes from `start_address` on the interface covering
+/// them, each upload staying within a single segment
+pub(crate) fn read_back(
+    device: &DfuDevice,
+    start_address: u32,
+    length: usize,
+    sink: &mut dyn ProgressSink,
+) -> Result<Vec<u8>, DfuError> {
+    let (connection, spans) = connect_spans(device, start_address, length)?;
+    let mut data = Vec::with_capacity(length);
+    upload_spans_with(&connection, &spans, None, sink, &mut |chunk| {
+        data.extend_from_slice(chunk)
+    })?;
+    Ok(data)
+}
 
-    let mut data: Vec<u8> = Vec::new();
-    while total - bytes_uploaded > 0 {
-        let single_xfer_size = cmp::min(total - bytes_uploaded, transfer_size);
-        data.extend(connection.upload(block_nr, single_xfer_size as u16)?);
-        bytes_uploaded += single_xfer_size;
-        block_nr += 1;
-
-        let percentage = (100 * bytes_uploaded) / total;
-        let filled = ((60 * bytes_uploaded) / total) as usize;
-        print!(
-            "\r  Reading {:3}% [{}]",
-            percentage,
-            "#".repeat(filled) + &" ".repeat(60 - filled)
-        );
-        let _ = io::stdout().flush();
+/// Connect to the interface covering `length` bytes from `start_address`
+/// (state reset) and split that range at the segment boundaries, as
+/// `(address, length)` spans
+pub(crate) fn connect_spans(
+    device: &DfuDevice,
+    start_address: u32,
+    length: usize,
+) -> Result<(DfuConnection, Vec<(u32, u32)>), DfuError> {
+    let end_address = start_address + length as u32 - 1;
+    let (connection, intf) =
+        device.connect_for_address(start_address, Some(end_address))?;
+    let connection = config().configure(connection);
+    config().reset_state(&connection)?;
+    let spans = intf
+        .interface()
+        .layout()
+        .split_at_segments(start_address, length as u32);
+    Ok((connection, spans))
+}
+
+/// UF2 file with the structure of `original`, its payloads read back from
+/// the device (only the flashed ranges are read, reboot ranges excluded)
+pub(crate) fn upload_as_flashed(
+    device: DfuDevice,
+    original: &[u8],
+    sink: &mut dyn ProgressSink,
+) -> Result<Vec<u8>, CliError> {
+    if !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
+
+    let mut ranges = Vec::new();
+    for range in UF2RangeIterator::new(original)? {
+        if range.reboot_address.is_some() {
+            continue;
+        }
+        let start = range.start_address;
+        let length = range.payload.len();
+        println!("Reading {length} bytes from {start:#010x}...");
+        ranges.push((start, read_back(&device, start, length, sink)?));
     }
-    println!();
+    Ok(replace_payloads(original, UF2DecodeMode::Strict, &ranges)?)
+}
+
+pub(crate) fn print_upload_result(result: &UploadResult) {
+    println!(
+        "Read {} bytes from {:#010x} in {:.1}s ({:.1} KB/s)",
+        result.bytes_read,
+        result.start_address,
+        result.elapsed.as_secs_f64(),
+        result.kb_per_second(),
+    );
+}
 
+/// String stored at `start_address`, up to the first NUL or erased byte
+pub(crate) fn read_string(
+    device: DfuDevice,
+    start_address: u32,
+    length: u32,
+) -> Result<String, CliError> {
+    let (data, _) = upload(
+        device,
+        Some(start_address),
+        ReadEnd::Length(length),
+        None,
+        &mut NoProgress,
+    )?;
+    let end = data
+        .iter()
+        .position(|&b| b == 0 || b == 0xff)
+        .unwrap_or(data.len());
+    Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
+pub(crate) fn upload_range(
+    connection: &DfuConnection,
+    start_address: u32,
+    total: u32,
+    sink: &mut dyn ProgressSink,
+) -> Result<Vec<u8>, DfuError> {
+    let mut data: Vec<u8> = Vec::new();
+    upload_range_with(
+        connection,
+        start_address,
+        total,
+        None,
+        sink,
+        &mut |chunk| data.extend_from_slice(chunk),
+    )?;
     Ok(data)
 }
+
+/// Upload `total` bytes from `start_address`, passing each chunk to
+/// `on_data` as soon as it is received
+///
+/// With `error_fill`, blocks that cannot be read are replaced with that
+/// byte instead of aborting, and their ranges (inclusive) are returned.
+pub(crate) fn upload_range_with(
+    connection: &DfuConnection,
+    start_address: u32,
+    total: u32,
+    error_fill: Option<u8>,
+    sink: &mut dyn ProgressSink,
+    on_data: &mut dyn FnMut(&[u8]),
+) -> Result<Vec<(u32, u32)>, DfuError> {
+    upload_spans_with(
+        connection,
+        &[(start_address, total)],
+        error_fill,
+        sink,
+        on_data,
+    )
+}
+
+/// Same as [upload_range_with()] for consecutive `(address, length)`
+/// spans (ex: split at segment boundaries), the address pointer is set
+/// at the start of each span
+pub(crate) fn upload_spans_with(
+    connection: &DfuConnection,
+    spans: &[(u32, u32)],
+    error_fill: Option<u8>,
+    sink: &mut dyn ProgressSink,
+    on_data: &mut dyn FnMut(&[u8]),
+) -> Result<Vec<(u32, u32)>, DfuError> {
+    let total: u32 = spans.iter().map(|(_, len)| len).sum();
+    let transfer_size = connection.transfer_size() as u32;
+    let mut progress = Progress::new(Phase::Reading, total as u64);
+    let mut failed = Vec::new();
+
+    for &(start_address, total) in spans {
+        // the previous span leaves the device in dfuUPLOAD-IDLE, where
+        // setting the address stalls
+        connection.reset_state()?;
+        connection.dfuse_set_address(start_address)?;
+        connection.reset_state()?;
+
+        let mut bytes_uploaded: u32 = 0;
+        let mut block_nr: u16 = 0;
+        while total - bytes_uploaded > 0 {
+            let single_xfer_size =
+                cmp::min(total - bytes_uploaded, transfer_size);
+            match connection.upload(block_nr, single_xfer_size as u16) {
+                Ok(chunk) => on_data(&chunk),
+                Err(err) => {
+                    let Some(fill) = error_fill else {
+                        return Err(err);
+                    };
+                    let addr = start_address + bytes_uploaded;
+                    println!("Warning: failed to read {addr:#010x}: {err}");
+                    failed.push((addr, addr + single_xfer_size - 1));
+                    on_data(&vec![fill; single_xfer_size as usize]);
+                    // leave the error state and restore the address pointer
+                    connection.reset_state()?;
+                    connection.dfuse_set_address(start_address)?;
+                    connection.reset_state()?;
+                }
+            }
+            bytes_uploaded += single_xfer_size;
+            block_nr += 1;
+            sink.update(progress.advance(single_xfer_size as u64));
+        }
+    }
+    sink.finish(&progress);
+
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use dfu::ControlTransfer;
+
+    use super::*;
+
+    const DFU_CMD_DOWNLOAD: u8 = 1;
+    const DFU_CMD_UPLOAD: u8 = 2;
+    const DFU_CMD_ABORT: u8 = 6;
+
+    // DNLOAD (set address), UPLOAD and ABORT requests, status polls left out
+    fn commands(connection: &DfuConnection) -> Vec<(u8, u16)> {
+        connection
+            .recorded_transfers()
+            .into_iter()
+            .filter_map(|transfer| match transfer {
+                ControlTransfer::Out { request, value, .. }
+                | ControlTransfer::In { request, value, .. }
+                    if [DFU_CMD_DOWNLOAD, DFU_CMD_UPLOAD, DFU_CMD_ABORT]
+                        .contains(&request) =>
+                {
+                    Some((request, value))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_last_address() {
+        assert!(matches!(last_address(0x08000000, 1), Ok(0x08000000)));
+        assert!(matches!(last_address(0xfffffff0, 16), Ok(0xffffffff)));
+        assert!(last_address(0x08000000, 0).is_err());
+        assert!(last_address(0xfffffff0, 17).is_err());
+    }
+
+    #[test]
+    fn test_upload_spans() {
+        let connection = DfuConnection::recording().with_transfer_size(16);
+        // a previous read left the device in dfuUPLOAD-IDLE
+        connection.read_block(2, 16).unwrap();
+
+        let mut data = Vec::new();
+        let spans = [(0x08000000, 32), (0x08004000, 16)];
+        upload_spans_with(
+            &connection,
+            &spans,
+            None,
+            &mut NoProgress,
+            &mut |c| data.extend_from_slice(c),
+        )
+        .unwrap();
+        assert_eq!(data.len(), 48);
+
+        let (set_address, abort, upload) = (
+            (DFU_CMD_DOWNLOAD, 0),
+            (DFU_CMD_ABORT, 0),
+            (DFU_CMD_UPLOAD, 2),
+        );
+        assert_eq!(
+            commands(&connection)[1..],
+            [
+                // span 1: two blocks
+                abort,
+                set_address,
+                abort,
+                upload,
+                (DFU_CMD_UPLOAD, 3),
+                // span 2
+                abort,
+                set_address,
+                abort,
+                upload,
+            ]
+        );
+    }
+}
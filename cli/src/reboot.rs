@@ -1,24 +1,62 @@
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
-use dfu::{DfuConnection, DfuDevice, DfuError, find_dfu_devices};
+use clap::ValueEnum;
+use dfu::{DfuDevice, REDISCOVER_INTERVAL, REDISCOVER_TIMEOUT, RebootFormat};
 
-use crate::CliError;
+use crate::{CliError, config::config};
 
+/// Where execution resumes when no jump address is given (EdgeTX
+/// firmware start in internal flash)
+const DEFAULT_JUMP_ADDRESS: u32 = 0x08000000;
+
+/// Structure written at the reboot tag address (see [RebootFormat])
+#[derive(Clone, Copy, ValueEnum)]
+pub(crate) enum RebootFormatArg {
+    /// "BDFU" magic value only
+    Tag,
+    /// magic value, flags and jump address (newer bootloaders)
+    Extended,
+}
+
+/// Reboot format selected on the command line, flags alone imply the
+/// extended format
+pub(crate) fn reboot_format(
+    format: Option<RebootFormatArg>,
+    flags: Option<u32>,
+) -> RebootFormat {
+    match format {
+        Some(RebootFormatArg::Tag) => RebootFormat::Tag,
+        Some(RebootFormatArg::Extended) => RebootFormat::Extended {
+            flags: flags.unwrap_or_default(),
+        },
+        None => RebootFormat::from_flags(flags),
+    }
+}
+
+/// Write the reboot tag at `tag_addr` and resume execution at `jump_addr`
+/// (defaults to the EdgeTX firmware start, `0x08000000`)
 pub(crate) fn reboot(
-    addr: u32,
+    tag_addr: u32,
+    tag: &[u8],
+    format: RebootFormat,
     device: DfuDevice,
-    start_addr: Option<u32>,
+    jump_addr: Option<u32>,
 ) -> Result<(), CliError> {
-    let dev_info = device.device_info();
-    let connection = device.connect(0, 0)?;
+    let connection = config().configure(device.connect(0, 0)?);
 
+    let jump_addr = jump_addr.unwrap_or(DEFAULT_JUMP_ADDRESS);
     println!("Rebooting...");
-    connection.reboot(addr, b"BDFU", start_addr.unwrap_or(0x08000000))?;
+    connection.reboot_with_format(tag_addr, tag, jump_addr, format)?;
     drop(connection);
 
     println!("Reconnecting...");
     let start = Instant::now();
-    let connection = reconnect(dev_info.vendor_id(), dev_info.product_id())?;
+    let device = device.rediscover_until(
+        REDISCOVER_TIMEOUT,
+        REDISCOVER_INTERVAL,
+        |dev| dev.locate(jump_addr).is_some(),
+    )?;
+    let connection = config().configure(device.connect(0, 0)?);
     let status = connection.get_status()?;
     println!("Reconnected in {:?}", start.elapsed());
 
@@ -26,15 +64,19 @@ pub(crate) fn reboot(
     Ok(())
 }
 
-fn reconnect(vid: u16, pid: u16) -> Result<DfuConnection, DfuError> {
-    let start = Instant::now();
-    loop {
-        let devices = find_dfu_devices(Some(vid), Some(pid))?;
-        if !devices.is_empty() {
-            return devices[0].connect(0, 0);
-        }
-        if start.elapsed() >= Duration::from_secs(30) {
-            return Err(DfuError::Timeout);
-        }
-    }
+/// Write the reboot tag only, the device reboots into DFU mode on its
+/// next reset instead of right away
+pub(crate) fn write_tag(
+    tag_addr: u32,
+    tag: &[u8],
+    format: RebootFormat,
+    device: DfuDevice,
+    jump_addr: Option<u32>,
+) -> Result<(), CliError> {
+    let connection = config().configure(device.connect(0, 0)?);
+    let jump_addr = jump_addr.unwrap_or(DEFAULT_JUMP_ADDRESS);
+    connection.write_reboot_tag(tag_addr, &format.encode(tag, jump_addr))?;
+    connection.get_status()?.ok()?;
+    println!("Reboot tag written at {tag_addr:#010x}");
+    Ok(())
 }
@@ -0,0 +1,115 @@
+use std::{env, fmt::Display};
+
+use dfu::{DfuDevice, list_dfu_device_infos};
+
+use crate::{
+    CliError, config::config, list::print_segment, status::describe_status,
+};
+
+/// Print everything useful for bug triage in a single text report
+///
+/// Information that cannot be collected is reported as unavailable
+/// instead of aborting the report.
+pub(crate) fn print_report(
+    vid: Option<u16>,
+    pid: Option<u16>,
+) -> Result<(), CliError> {
+    let config = config();
+    println!("rdfu {}", env!("CARGO_PKG_VERSION"));
+    println!("Platform: {} ({})", env::consts::OS, env::consts::ARCH);
+
+    let devices = match list_dfu_device_infos(
+        vid.or(config.vendor),
+        pid.or(config.product),
+        config.serial.as_deref(),
+    ) {
+        Ok(devices) => devices,
+        Err(err) => {
+            unavailable("", "DFU devices", err);
+            return Ok(());
+        }
+    };
+    println!("DFU devices: {}", devices.len());
+
+    for (i, info) in devices.into_iter().enumerate() {
+        println!();
+        println!(
+            "Device #{}: Bus {} Device {:03}: ID {:04x}:{:04x}",
+            i + 1,
+            info.bus_id(),
+            info.device_address(),
+            info.vendor_id(),
+            info.product_id(),
+        );
+        println!(
+            "  Manufacturer: {}",
+            info.manufacturer_string().unwrap_or("-")
+        );
+        println!("  Product:      {}", info.product_string().unwrap_or("-"));
+        println!("  Serial:       {}", info.serial_number().unwrap_or("-"));
+        match DfuDevice::open_with_timeout(info) {
+            Ok(Some(device)) => print_device_report(&device),
+            Ok(None) => println!("  No DFU interface with a memory layout"),
+            Err(err) => unavailable("  ", "device", err),
+        }
+    }
+    Ok(())
+}
+
+fn print_device_report(device: &DfuDevice) {
+    println!(
+        "  Version:      {} (bcdDevice={:#06x}, dfuse={})",
+        device.device_version(),
+        device.bcd_device(),
+        device.is_dfuse()
+    );
+    match device.dfu_descriptor() {
+        Ok(desc) => println!(
+            "  DFU descriptor: attributes={:#04x}, detach_timeout={} ms, \
+            transfer_size={}, version={:#06x}",
+            desc.attributes(),
+            desc.detach_timeout(),
+            desc.transfer_size(),
+            desc.dfu_version(),
+        ),
+        Err(err) => unavailable("  ", "DFU descriptor", err),
+    }
+
+    for interface in device.interfaces() {
+        println!(
+            "  Interface {} (config={}, alt={}): {}",
+            interface.interface(),
+            interface.config(),
+            interface.alt_setting(),
+            interface.layout_string(),
+        );
+        for segment in &interface.layout().segments {
+            print_segment("    ", segment);
+        }
+    }
+    for (interface, alt_setting, layout) in device.unparsed_interfaces() {
+        println!(
+            "  Interface {} (alt={}, unparsed layout): {}",
+            interface, alt_setting, layout,
+        );
+    }
+
+    let status = device
+        .connect(0, 0)
+        .map(|connection| config().configure(connection))
+        .and_then(|connection| connection.get_status());
+    match status {
+        Ok(status) => println!(
+            "  Status: status={}, state={} ({}), poll_timeout={} ms",
+            describe_status(&status),
+            status.state,
+            status.state(),
+            status.poll_timeout,
+        ),
+        Err(err) => unavailable("  ", "status", err),
+    }
+}
+
+fn unavailable(prefix: &str, what: &str, err: impl Display) {
+    println!("{prefix}[unavailable] {what}: {err}");
+}
@@ -0,0 +1,29 @@
+use dfu::{DfuDevice, DfuStatus};
+
+use crate::{CliError, config::config};
+
+pub(crate) fn print_status(device: DfuDevice) -> Result<(), CliError> {
+    let connection = config().configure(device.connect(0, 0)?);
+    let status = connection.get_status()?;
+
+    println!(
+        "Bus {} Device {:03}: ID {:04x}:{:04x}",
+        device.bus_id(),
+        device.device_address(),
+        device.vendor_id(),
+        device.product_id(),
+    );
+    println!("  Status:       {}", describe_status(&status));
+    println!("  State:        {} ({})", status.state, status.state());
+    println!("  Poll timeout: {} ms", status.poll_timeout);
+    Ok(())
+}
+
+/// Status code name and meaning (ex: "errERASE (memory erase function
+/// failed)"), or the raw code if unknown
+pub(crate) fn describe_status(status: &DfuStatus) -> String {
+    match (status.status_name(), status.status_description()) {
+        (Some(name), Some(description)) => format!("{name} ({description})"),
+        _ => format!("unknown (code {})", status.status),
+    }
+}
@@ -0,0 +1,126 @@
+use dfu::{DfuConnection, DfuDevice, DfuError, DfuInterface};
+
+/// Transfer size for an interface, given as `<interface>[:<alt>]=<size>`
+/// (ex: `0:1=256`), all alternate settings if `<alt>` is omitted
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct InterfaceTransferSize {
+    pub interface: u8,
+    pub alt_setting: Option<u8>,
+    pub size: u16,
+}
+
+impl InterfaceTransferSize {
+    fn matches(&self, interface: u8, alt_setting: u8) -> bool {
+        self.interface == interface
+            && self.alt_setting.is_none_or(|alt| alt == alt_setting)
+    }
+}
+
+pub(crate) fn parse_interface_transfer_size(
+    s: &str,
+) -> Result<InterfaceTransferSize, String> {
+    let (target, size) = s
+        .split_once('=')
+        .ok_or("expected <interface>[:<alt>]=<size>")?;
+    let (interface, alt_setting) = match target.split_once(':') {
+        Some((interface, alt)) => (interface, Some(alt)),
+        None => (target, None),
+    };
+    let parse_u8 =
+        |s: &str| s.trim().parse::<u8>().map_err(|e| format!("{s}: {e}"));
+    let size = size.trim().parse::<u16>().map_err(|e| format!("{e}"))?;
+    if size == 0 {
+        return Err("transfer size must not be 0".into());
+    }
+    Ok(InterfaceTransferSize {
+        interface: parse_u8(interface)?,
+        alt_setting: alt_setting.map(parse_u8).transpose()?,
+        size,
+    })
+}
+
+/// Apply the transfer size given for `intf` (an exact alternate setting
+/// takes precedence), which must not exceed the size reported by the
+/// interface
+pub(crate) fn configure_transfer_size(
+    device: &DfuDevice,
+    connection: DfuConnection,
+    intf: &DfuInterface,
+    sizes: &[InterfaceTransferSize],
+) -> Result<DfuConnection, DfuError> {
+    let Some(size) =
+        requested_size(sizes, intf.interface(), intf.alt_setting())
+    else {
+        return Ok(connection);
+    };
+    let reported = device
+        .interface_dfu_descriptor(intf.interface(), intf.alt_setting())?
+        .transfer_size();
+    if reported > 0 && size > reported {
+        return Err(DfuError::TransferTooLarge {
+            size: size as usize,
+            max: reported,
+        });
+    }
+    println!(
+        "Using {size} bytes transfers for interface {}:{}",
+        intf.interface(),
+        intf.alt_setting()
+    );
+    Ok(connection.with_transfer_size(size))
+}
+
+// An exact alternate setting takes precedence over the whole interface
+fn requested_size(
+    sizes: &[InterfaceTransferSize],
+    interface: u8,
+    alt_setting: u8,
+) -> Option<u16> {
+    sizes
+        .iter()
+        .filter(|s| s.matches(interface, alt_setting))
+        .max_by_key(|s| s.alt_setting.is_some())
+        .map(|s| s.size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interface_transfer_size() {
+        let size = parse_interface_transfer_size("0:1=256").unwrap();
+        assert_eq!(
+            (size.interface, size.alt_setting, size.size),
+            (0, Some(1), 256)
+        );
+        let size = parse_interface_transfer_size(" 2 = 1024 ").unwrap();
+        assert_eq!(
+            (size.interface, size.alt_setting, size.size),
+            (2, None, 1024)
+        );
+
+        assert!(parse_interface_transfer_size("0:1").is_err());
+        assert!(parse_interface_transfer_size("0=0").is_err());
+        assert!(parse_interface_transfer_size("0=65536").is_err());
+        assert!(parse_interface_transfer_size("256=64").is_err());
+        assert!(parse_interface_transfer_size("0:x=64").is_err());
+        assert!(parse_interface_transfer_size("=64").is_err());
+    }
+
+    #[test]
+    fn test_requested_size() {
+        let sizes = [
+            parse_interface_transfer_size("0:1=256").unwrap(),
+            parse_interface_transfer_size("0=1024").unwrap(),
+            parse_interface_transfer_size("1:0=512").unwrap(),
+        ];
+        // the exact alternate setting wins, whatever the order
+        assert_eq!(requested_size(&sizes, 0, 1), Some(256));
+        assert_eq!(requested_size(&sizes, 0, 0), Some(1024));
+        assert_eq!(requested_size(&sizes, 1, 0), Some(512));
+        assert_eq!(requested_size(&sizes, 1, 1), None);
+        assert_eq!(requested_size(&sizes, 2, 0), None);
+        assert_eq!(requested_size(&[], 0, 0), None);
+    }
+}
@@ -0,0 +1,16 @@
+use dfu::DfuDevice;
+
+use crate::CliError;
+
+pub(crate) fn print_uid(
+    device: DfuDevice,
+    address: Option<u32>,
+) -> Result<(), CliError> {
+    let uid = match address {
+        Some(address) => device.read_uid_at(address)?,
+        None => device.read_uid()?,
+    };
+    let hex: String = uid.iter().map(|b| format!("{b:02x}")).collect();
+    println!("{hex}");
+    Ok(())
+}
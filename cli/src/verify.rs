@@ -0,0 +1,196 @@
+use dfu::{DfuDevice, DfuError, Phase, Progress, ProgressSink};
+use uf2::{UF2DecodeMode, UF2RangeIterator, is_uf2_payload};
+
+use crate::{
+    CliError,
+    read::{connect_spans, read_back},
+    write::RangePayload,
+};
+
+// Bytes per hex diff line (lines are aligned on this size)
+const DIFF_WINDOW: u32 = 16;
+
+// Bytes of the image compared at once (ranges are read as they are verified)
+const VERIFY_CHUNK: u32 = 64 * 1024;
+
+/// Compare a raw binary or UF2 image with the device content
+///
+/// With `hexdiff`, up to `max_diffs` differing lines are printed,
+/// otherwise only the first differing address.
+pub(crate) fn verify(
+    data: &[u8],
+    device: &DfuDevice,
+    start_address: Option<u32>,
+    mode: UF2DecodeMode,
+    hexdiff: Option<usize>,
+    sink: &mut dyn ProgressSink,
+) -> Result<(), CliError> {
+    if !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
+
+    let ranges: Vec<(u32, Vec<u8>)> = if is_uf2_payload(data) {
+        UF2RangeIterator::with_mode(data, mode)?
+            .filter(|r| r.reboot_address.is_none())
+            .map(|r| (r.start_address, r.payload))
+            .collect()
+    } else {
+        let start_address =
+            start_address.unwrap_or(device.get_default_start_address());
+        vec![(start_address, data.to_vec())]
+    };
+
+    let mut first_mismatch = None;
+    let mut diff_lines = Vec::new();
+    let mut differing_bytes = 0;
+    for (start_address, expected) in &ranges {
+        let actual = read_back(device, *start_address, expected.len(), sink)?;
+        for (addr, expected, actual, differing) in
+            diff_lines_of(*start_address, expected, &actual)
+        {
+            first_mismatch.get_or_insert(addr);
+            differing_bytes += differing;
+            diff_lines.push((addr, hex_bytes(expected), hex_bytes(actual)));
+        }
+    }
+
+    let Some(address) = first_mismatch else {
+        println!("Device content matches the image");
+        return Ok(());
+    };
+    if let Some(max_diffs) = hexdiff {
+        for (addr, expected, actual) in diff_lines.iter().take(max_diffs) {
+            println!("{addr:#010x}  expected: {expected}");
+            println!("            actual:   {actual}");
+        }
+        if diff_lines.len() > max_diffs {
+            println!("... {} more lines differ", diff_lines.len() - max_diffs);
+        }
+        println!(
+            "{differing_bytes} bytes differ in {} lines",
+            diff_lines.len()
+        );
+    }
+    Err(CliError::VerifyMismatch { address })
+}
+
+/// Read back the ranges just written (`(address, data)`, reboot tags and
+/// gaps excluded) and compare them with the image
+///
+/// The first differing range is reported along with the device
+/// description of the image, if any.
+pub(crate) fn verify_written(
+    device: &DfuDevice,
+    ranges: &[(u32, &[u8])],
+    description: Option<&str>,
+    sink: &mut dyn ProgressSink,
+) -> Result<(), CliError> {
+    println!("Verifying {} range(s)...", ranges.len());
+    for (i, &(start_address, mut expected)) in ranges.iter().enumerate() {
+        verify_written_range(
+            device,
+            (i, ranges.len()),
+            start_address,
+            &mut expected,
+            description,
+            sink,
+        )?;
+    }
+    println!("Device content matches the image");
+    Ok(())
+}
+
+/// Same as [verify_written()] for range `index` of `count`, `expected`
+/// being read as it is compared
+pub(crate) fn verify_written_range(
+    device: &DfuDevice,
+    (index, count): (usize, usize),
+    start_address: u32,
+    expected: &mut dyn RangePayload,
+    description: Option<&str>,
+    sink: &mut dyn ProgressSink,
+) -> Result<(), CliError> {
+    let length = expected.remaining();
+    let address = match verify_range(device, start_address, expected, sink) {
+        Ok(()) => return Ok(()),
+        Err(CliError::Dfu(DfuError::VerifyMismatch { addr })) => addr,
+        Err(err) => return Err(err),
+    };
+    let end_address = start_address + length as u32 - 1;
+    println!(
+        "Range {} of {count} ({start_address:#010x} - {end_address:#010x}) \
+        differs at {address:#010x}",
+        index + 1,
+    );
+    if let Some(description) = description {
+        println!("Image: {description}");
+    }
+    Err(CliError::VerifyMismatch { address })
+}
+
+/// Compare `expected` with the content at `start_address` on the
+/// interface covering it, each upload staying within a single segment
+/// and at most [VERIFY_CHUNK] bytes of the image being held at once
+fn verify_range(
+    device: &DfuDevice,
+    start_address: u32,
+    expected: &mut dyn RangePayload,
+    sink: &mut dyn ProgressSink,
+) -> Result<(), CliError> {
+    let length = expected.remaining();
+    let (connection, spans) = connect_spans(device, start_address, length)?;
+    let mut progress = Progress::new(Phase::Verifying, length as u64);
+    let mut chunk = Vec::new();
+    for (span_addr, span_length) in spans {
+        let mut offset = 0;
+        while offset < span_length {
+            let chunk_length = VERIFY_CHUNK.min(span_length - offset);
+            chunk.clear();
+            expected.read(chunk_length as usize, &mut chunk)?;
+            let mut compared = 0;
+            connection.verify_with_progress(
+                span_addr + offset,
+                &chunk,
+                &mut |done, _| {
+                    sink.update(progress.advance((done - compared) as u64));
+                    compared = done;
+                },
+            )?;
+            offset += chunk_length;
+        }
+    }
+    sink.finish(&progress);
+    Ok(())
+}
+
+/// Differing lines aligned on [DIFF_WINDOW]: address, expected bytes,
+/// actual bytes and number of differing bytes
+fn diff_lines_of<'a>(
+    start_address: u32,
+    expected: &'a [u8],
+    actual: &'a [u8],
+) -> Vec<(u32, &'a [u8], &'a [u8], usize)> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < expected.len() {
+        let addr = start_address + offset as u32;
+        let line_end = (addr / DIFF_WINDOW + 1) * DIFF_WINDOW;
+        let end = expected.len().min(offset + (line_end - addr) as usize);
+        let (expected, actual) = (&expected[offset..end], &actual[offset..end]);
+        let differing =
+            expected.iter().zip(actual).filter(|(e, a)| e != a).count();
+        if differing > 0 {
+            lines.push((addr, expected, actual, differing));
+        }
+        offset = end;
+    }
+    lines
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
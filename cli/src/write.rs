@@ -1,132 +1,1036 @@
 use std::{
-    io::{self, Write},
+    io::{Cursor, Read, Seek},
+    iter::Peekable,
     time::{Duration, Instant},
 };
 
-use dfu::{DfuDevice, DfuError, find_dfu_devices};
-use uf2::{UF2RangeIterator, is_uf2_payload};
+use clap::ValueEnum;
+use sha2::digest::DynDigest;
 
-use crate::CliError;
+use dfu::{
+    DfuConnection, DfuDevice, DfuError, DfuMemSegment, DownloadResult, Phase,
+    Progress, ProgressSink, REDISCOVER_INTERVAL, REDISCOVER_TIMEOUT,
+    RebootFormat,
+};
+use uf2::{
+    UF2BlockData, UF2BlockReader, UF2DecodeError, UF2DecodeMode,
+    UF2DeviceDescription, UF2RangeIterator, is_uf2_payload,
+};
+
+use crate::{
+    CliError,
+    checksum::{Checksum, to_hex},
+    config::config,
+    progress::{NoProgress, show_erase_wait},
+    read::{upload_range_with, upload_spans_with},
+    transfer::{InterfaceTransferSize, configure_transfer_size},
+    verify::{verify_written, verify_written_range},
+};
+
+/// When pages are erased while writing a range
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub(crate) enum EraseStrategy {
+    /// erase all pages before writing
+    #[default]
+    AllUpFront,
+    /// erase the pages touched by each chunk right before writing it
+    PerChunk,
+}
+
+#[derive(Default)]
+pub(crate) struct WriteOptions {
+    pub start_address: Option<u32>,
+    /// last address (inclusive) a raw image may be written to
+    pub end_address: Option<u32>,
+    pub mode: UF2DecodeMode,
+    pub no_leave: bool,
+    /// expected target (defaults to the device's product string)
+    pub target: Option<String>,
+    pub ignore_target_mismatch: bool,
+    /// fill byte used to pad each range up to the end of its last page
+    /// (padded bytes are written and are part of the expected image)
+    pub pad: Option<u8>,
+    /// read back the written ranges and compare their checksum
+    pub checksum: Option<Checksum>,
+    pub erase_strategy: EraseStrategy,
+    /// skip the pages already holding the intended data (requires upload)
+    pub only_changed: bool,
+    /// transfer sizes overriding the reported one for some interfaces
+    pub transfer_sizes: Vec<InterfaceTransferSize>,
+    /// keep writing the remaining UF2 ranges when one fails
+    pub keep_going: bool,
+    /// read back and compare all written ranges before leaving DFU mode
+    pub verify: bool,
+}
+
+/// Restrict a raw image to the memory region (layout) named `name`: it
+/// is written at the region's start and must fit in its first contiguous
+/// range of segments
+pub(crate) fn in_region(
+    device: &DfuDevice,
+    name: &str,
+    options: WriteOptions,
+) -> Result<WriteOptions, CliError> {
+    let layout = device
+        .find_interface_by_name(name)
+        .ok_or_else(|| CliError::UnknownRegion(name.into()))?
+        .layout();
+    let start = layout.segments.first().start_addr();
+    let (_, end) = layout.contiguous_range(start).unwrap_or((start, start));
+    println!(
+        "Writing to {} ({start:#010x} - {:#010x})",
+        layout.name,
+        end - 1
+    );
+    Ok(WriteOptions {
+        start_address: Some(start),
+        end_address: Some(end - 1),
+        ..options
+    })
+}
 
 pub(crate) fn download(
     data: &[u8],
     device: DfuDevice,
-    start_address: Option<u32>,
+    options: &WriteOptions,
+    sink: &mut dyn ProgressSink,
+) -> Result<DownloadResult, CliError> {
+    if is_uf2_payload(data) {
+        return download_uf2(Cursor::new(data), device, options, sink);
+    }
+    if !device.can_download()? {
+        return Err(CliError::Dfu(DfuError::DownloadNotSupported));
+    }
+    if options.verify && !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
+
+    let start = options
+        .start_address
+        .unwrap_or(device.get_default_start_address());
+    let end = raw_image_end(data, start)?;
+    if let Some(end_address) = options.end_address
+        && end > end_address
+    {
+        return Err(CliError::ImageOutOfBounds {
+            start,
+            end: end + 1,
+        });
+    }
+
+    let mut written = WrittenImage::new(options.checksum);
+    reset_state(&device)?;
+    let result = write_range(
+        &device,
+        start,
+        &mut &data[..],
+        options,
+        &mut written,
+        sink,
+    )?;
+    if options.verify {
+        verify_written(&device, &[(start, data)], None, sink)?;
+    }
+    finish(device, written, options, sink)?;
+    Ok(result)
+}
+
+/// Record a range which failed to be written and bring the device back
+/// to `dfuIDLE` for the next one, even with `--no-reset-state` (this is
+/// error recovery, not the initial reset)
+///
+/// The summary is printed if the device cannot be recovered.
+fn skip_failed_range(
+    connection: &DfuConnection,
+    outcomes: &mut Vec<(u32, u32, Option<DfuError>)>,
+    (start, end): (u32, u32),
+    err: DfuError,
 ) -> Result<(), CliError> {
+    println!("Failed to write {start:#010x}: {err}");
+    outcomes.push((start, end, Some(err)));
+    connection.reset_state().map_err(|err| {
+        print_outcomes(outcomes);
+        err.into()
+    })
+}
+
+/// Summary of a `keep_going` write: which ranges were written
+fn print_outcomes(outcomes: &[(u32, u32, Option<DfuError>)]) {
+    println!("Summary:");
+    for (start, end, err) in outcomes {
+        match err {
+            None => println!("  {start:#010x} - {end:#010x}: written"),
+            Some(err) => {
+                println!("  {start:#010x} - {end:#010x}: FAILED ({err})")
+            }
+        }
+    }
+}
+
+fn finish(
+    device: DfuDevice,
+    written: WrittenImage,
+    options: &WriteOptions,
+    sink: &mut dyn ProgressSink,
+) -> Result<(), CliError> {
+    written.compare_checksum(&device, sink)?;
+    if options.no_leave {
+        println!("Staying in DFU mode");
+        return Ok(());
+    }
+    Ok(leave(&device)?)
+}
+
+/// Range of a UF2 file (`end` exclusive), reboot ranges keep their
+/// payload (the reboot tag)
+struct StreamRange {
+    start: u32,
+    end: u32,
+    reboot: Option<(Vec<u8>, u32, RebootFormat)>,
+}
+
+/// Same as [download()] for a UF2 file read block by block: payloads are
+/// written as they are read instead of being loaded in memory first
+///
+/// The file is read twice: once to find the ranges (split like
+/// [UF2RangeIterator] does), once to write them. With `only_changed`, each
+/// range is loaded to be compared with the device, `verify` reads the
+/// file a third time, range by range.
+pub(crate) fn download_uf2<R: Read + Seek>(
+    mut reader: R,
+    device: DfuDevice,
+    options: &WriteOptions,
+    sink: &mut dyn ProgressSink,
+) -> Result<DownloadResult, CliError> {
+    if !device.can_download()? {
+        return Err(CliError::Dfu(DfuError::DownloadNotSupported));
+    }
+    if options.verify && !device.can_upload()? {
+        return Err(CliError::Dfu(DfuError::UploadNotSupported));
+    }
+
+    let (ranges, description) =
+        scan_ranges(UF2BlockReader::new(&mut reader, options.mode))?;
+    let (start, end) = ranges
+        .iter()
+        .filter(|r| r.reboot.is_none())
+        .map(|r| (r.start, r.end))
+        .reduce(|(start, end), r| (start.min(r.0), end.max(r.1)))
+        .ok_or(UF2DecodeError::new("no flash blocks".to_string()))?;
+    check_flash_extent(&device, start, end)?;
+    if !options.ignore_target_mismatch {
+        check_description(description.clone(), options.target.as_deref())?;
+    }
+
+    // ranges written, reboots excluded
+    let mut result = DownloadResult::default();
     let mut device = device;
+    let mut written = WrittenImage::new(options.checksum);
+    // flash ranges (`end` inclusive) and their error, if any
+    let mut outcomes = Vec::new();
     reset_state(&device)?;
-    if !is_uf2_payload(data) {
-        download_range(data, &device, start_address)?;
-    } else {
-        for addr_range in UF2RangeIterator::new(data)? {
-            if let Some(reboot_addr) = addr_range.reboot_address {
-                device = reboot(
-                    &device,
-                    addr_range.start_address,
-                    &addr_range.payload,
-                    reboot_addr,
-                )?;
-            } else {
-                download_range(
-                    &addr_range.payload,
-                    &device,
-                    Some(addr_range.start_address),
-                )?;
+    reader.rewind()?;
+    let mut blocks = UF2BlockReader::new(&mut reader, options.mode);
+    for (i, range) in ranges.iter().enumerate() {
+        let Some((payload, reboot_addr, format)) = &range.reboot else {
+            let mut payload =
+                BlockPayload::new(&mut blocks, range.end - range.start);
+            match write_range(
+                &device,
+                range.start,
+                &mut payload,
+                options,
+                &mut written,
+                sink,
+            ) {
+                Ok(range_result) => {
+                    result.add(&range_result);
+                    outcomes.push((range.start, range.end - 1, None));
+                }
+                Err(CliError::Dfu(err)) if options.keep_going => {
+                    payload.skip_rest()?;
+                    let connection = config().configure(device.connect(0, 0)?);
+                    skip_failed_range(
+                        &connection,
+                        &mut outcomes,
+                        (range.start, range.end - 1),
+                        err,
+                    )?;
+                }
+                Err(err) => return Err(err),
             }
+            continue;
+        };
+        skip_blocks(&mut blocks, range.end - range.start)?;
+        let next_ranges: Vec<(u32, u32)> = ranges[i + 1..]
+            .iter()
+            .filter(|r| r.reboot.is_none())
+            .map(|r| (r.start, r.end - 1))
+            .collect();
+        device = reboot(
+            &device,
+            range.start,
+            payload,
+            *reboot_addr,
+            *format,
+            &next_ranges,
+        )?;
+    }
+    if options.keep_going && outcomes.iter().any(|(.., err)| err.is_some()) {
+        print_outcomes(&outcomes);
+        return Err(CliError::PartialWrite {
+            failed: outcomes.iter().filter(|(.., err)| err.is_some()).count(),
+            total: outcomes.len(),
+        });
+    }
+    if options.verify {
+        // ranges as in the image (without padding), read block by block
+        let count = ranges.iter().filter(|r| r.reboot.is_none()).count();
+        println!("Verifying {count} range(s)...");
+        reader.rewind()?;
+        let mut blocks = UF2BlockReader::new(&mut reader, options.mode);
+        let mut index = 0;
+        for range in &ranges {
+            let length = range.end - range.start;
+            if range.reboot.is_some() {
+                skip_blocks(&mut blocks, length)?;
+                continue;
+            }
+            verify_written_range(
+                &device,
+                (index, count),
+                range.start,
+                &mut BlockPayload::new(&mut blocks, length),
+                description.as_deref(),
+                sink,
+            )?;
+            index += 1;
         }
+        println!("Device content matches the image");
     }
-    Ok(leave(&device)?)
+    finish(device, written, options, sink)?;
+    Ok(result)
 }
 
-pub(crate) fn reset_state(device: &DfuDevice) -> Result<(), DfuError> {
-    println!("Resetting device state...");
-    let connection = device.connect(0, 0)?;
-    connection.reset_state()
+// Ranges and device description (of the first block)
+fn scan_ranges(
+    blocks: impl Iterator<Item = Result<UF2BlockData, UF2DecodeError>>,
+) -> Result<(Vec<StreamRange>, Option<String>), UF2DecodeError> {
+    let mut ranges: Vec<StreamRange> = Vec::new();
+    let mut description = None;
+    let mut family_id = None;
+    for (i, block) in blocks.enumerate() {
+        let block = block?;
+        if i == 0 {
+            description = block.get_device_description();
+        }
+        let len = block.payload.len() as u32;
+        let end = block.flash_address.checked_add(len).ok_or_else(|| {
+            UF2DecodeError::new(format!(
+                "block at {:#010x} exceeds the address space",
+                block.flash_address
+            ))
+        })?;
+        match ranges.last_mut() {
+            Some(range)
+                if range.end == block.flash_address
+                    && family_id == block.family_id() =>
+            {
+                range.end = end;
+                if let Some((payload, _, _)) = range.reboot.as_mut() {
+                    payload.extend(&block.payload);
+                }
+            }
+            _ => {
+                family_id = block.family_id();
+                let reboot = block.get_reboot().map(|reboot| {
+                    (
+                        block.payload.clone(),
+                        reboot.address,
+                        RebootFormat::from_flags(reboot.flags),
+                    )
+                });
+                ranges.push(StreamRange {
+                    start: block.flash_address,
+                    end,
+                    reboot,
+                });
+            }
+        }
+    }
+    ranges.retain(|r| r.end > r.start);
+    Ok((ranges, description))
+}
+
+fn next_block(
+    blocks: &mut impl Iterator<Item = Result<UF2BlockData, UF2DecodeError>>,
+) -> Result<UF2BlockData, UF2DecodeError> {
+    blocks
+        .next()
+        .unwrap_or(Err(UF2DecodeError::new("unexpected end of file".into())))
+}
+
+fn skip_blocks(
+    blocks: &mut impl Iterator<Item = Result<UF2BlockData, UF2DecodeError>>,
+    length: u32,
+) -> Result<(), UF2DecodeError> {
+    let mut remaining = length as usize;
+    while remaining > 0 {
+        let block = next_block(blocks)?;
+        remaining = remaining.saturating_sub(block.payload.len());
+    }
+    Ok(())
+}
+
+/// Payload of a range, read in order as it is written
+pub(crate) trait RangePayload {
+    /// Append the next `len` bytes of the range to `buf`
+    fn read(&mut self, len: usize, buf: &mut Vec<u8>) -> Result<(), CliError>;
+
+    /// Bytes left to read
+    fn remaining(&self) -> usize;
+}
+
+impl RangePayload for &[u8] {
+    fn read(&mut self, len: usize, buf: &mut Vec<u8>) -> Result<(), CliError> {
+        let (head, tail) = self.split_at(len);
+        buf.extend_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Payload of a UF2 range, read block by block
+struct BlockPayload<'a, I> {
+    blocks: &'a mut I,
+    /// bytes of the range not read from the blocks yet
+    remaining: usize,
+    /// rest of the last block read
+    pending: Vec<u8>,
+}
+
+impl<'a, I> BlockPayload<'a, I>
+where
+    I: Iterator<Item = Result<UF2BlockData, UF2DecodeError>>,
+{
+    fn new(blocks: &'a mut I, length: u32) -> Self {
+        BlockPayload {
+            blocks,
+            remaining: length as usize,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Skip the blocks left (ex: the range could not be written)
+    fn skip_rest(&mut self) -> Result<(), UF2DecodeError> {
+        self.pending.clear();
+        skip_blocks(self.blocks, self.remaining as u32)?;
+        self.remaining = 0;
+        Ok(())
+    }
+}
+
+impl<I> RangePayload for BlockPayload<'_, I>
+where
+    I: Iterator<Item = Result<UF2BlockData, UF2DecodeError>>,
+{
+    fn read(&mut self, len: usize, buf: &mut Vec<u8>) -> Result<(), CliError> {
+        while self.pending.len() < len {
+            let block = next_block(self.blocks)?;
+            let payload =
+                &block.payload[..block.payload.len().min(self.remaining)];
+            self.remaining -= payload.len();
+            self.pending.extend_from_slice(payload);
+        }
+        buf.extend(self.pending.drain(..len));
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        self.remaining + self.pending.len()
+    }
+}
+
+/// Payload followed by `padding` bytes of `fill`
+struct Padded<'a> {
+    payload: &'a mut dyn RangePayload,
+    padding: usize,
+    fill: u8,
+}
+
+impl RangePayload for Padded<'_> {
+    fn read(&mut self, len: usize, buf: &mut Vec<u8>) -> Result<(), CliError> {
+        let from_payload = len.min(self.payload.remaining());
+        self.payload.read(from_payload, buf)?;
+        self.padding -= len - from_payload;
+        buf.resize(buf.len() + len - from_payload, self.fill);
+        Ok(())
+    }
+
+    fn remaining(&self) -> usize {
+        self.payload.remaining() + self.padding
+    }
+}
+
+/// Last address of a raw image written at `start`
+fn raw_image_end(data: &[u8], start: u32) -> Result<u32, CliError> {
+    if data.is_empty() {
+        return Err(CliError::EmptyImage);
+    }
+    u32::try_from(data.len() - 1)
+        .ok()
+        .and_then(|n| start.checked_add(n))
+        .ok_or(CliError::ImageOutOfBounds {
+            start,
+            end: u32::MAX,
+        })
 }
 
-fn download_range(
+/// Rough time needed to flash `data`, reboot ranges excluded
+pub(crate) fn estimate(
     data: &[u8],
     device: &DfuDevice,
-    start_address: Option<u32>,
-) -> Result<(), DfuError> {
-    let start_address =
-        start_address.unwrap_or(device.get_default_start_address());
-    let end_address = start_address + (data.len() as u32) - 1;
-
-    let intf = device.find_interface(start_address, Some(end_address))?;
-    let connection = device.connect(intf.interface(), intf.alt_setting())?;
-
-    // erase first
-    let erase_pages = intf.get_erase_pages(start_address, end_address);
-    let pages = erase_pages.len();
-
-    for (page, page_addr) in erase_pages.into_iter().enumerate() {
-        print!(
-            "\r  Erasing page {:2} of {:2} @ 0x{:08x}",
-            page + 1,
-            pages,
-            page_addr,
-        );
-        let _ = io::stdout().flush();
-        if let Err(err) = connection.dfuse_page_erase(page_addr) {
-            println!(" ❌");
-            return Err(err);
+    options: &WriteOptions,
+) -> Result<Duration, CliError> {
+    if !is_uf2_payload(data) {
+        let start = options
+            .start_address
+            .unwrap_or(device.get_default_start_address());
+        let end = raw_image_end(data, start)?;
+        return Ok(device.estimate_flash_time(start, end)?);
+    }
+    let mut total = Duration::ZERO;
+    for addr_range in UF2RangeIterator::with_mode(data, options.mode)? {
+        if addr_range.reboot_address.is_none() && !addr_range.payload.is_empty()
+        {
+            let start = addr_range.start_address;
+            let end = start + addr_range.payload.len() as u32 - 1;
+            total += device.estimate_flash_time(start, end)?;
         }
     }
-    println!();
+    Ok(total)
+}
+
+// Only checked against an explicit target: the bootloader product string
+// does not name the radio. Files without device description cannot be
+// checked.
+fn check_description(
+    description: Option<String>,
+    target: Option<&str>,
+) -> Result<(), CliError> {
+    let (Some(description), Some(expected)) = (description, target) else {
+        return Ok(());
+    };
+    if UF2DeviceDescription::parse(&description).matches_target(expected) {
+        Ok(())
+    } else {
+        Err(CliError::TargetMismatch {
+            expected: expected.into(),
+            found: description,
+        })
+    }
+}
+
+// `end` is exclusive
+fn check_flash_extent(
+    device: &DfuDevice,
+    start: u32,
+    end: u32,
+) -> Result<(), CliError> {
+    let segments: Vec<&DfuMemSegment> =
+        device.all_segments().map(|(_, s)| s).collect();
+    let fits = segments
+        .iter()
+        .any(|s| s.start_addr() <= start && start < s.end_addr())
+        && segments
+            .iter()
+            .any(|s| s.start_addr() < end && end <= s.end_addr());
+    if fits {
+        Ok(())
+    } else {
+        Err(CliError::ImageOutOfBounds { start, end })
+    }
+}
+
+pub(crate) fn reset_state(device: &DfuDevice) -> Result<(), DfuError> {
+    if config().no_reset_state {
+        return Ok(());
+    }
+    let connection = config().configure(device.connect(0, 0)?);
+    config().reset_state(&connection)
+}
 
-    let mut addr = start_address;
-    let mut bytes_downloaded: usize = 0;
-    let transfer_size = connection.transfer_size();
+/// Erase and write `payload` from `start_address`
+///
+/// The payload is read as it is written, unless only the changed pages
+/// are written: it is then loaded to be compared with the device first.
+fn write_range(
+    device: &DfuDevice,
+    start_address: u32,
+    payload: &mut dyn RangePayload,
+    options: &WriteOptions,
+    written: &mut WrittenImage,
+    sink: &mut dyn ProgressSink,
+) -> Result<DownloadResult, CliError> {
+    let started = Instant::now();
+    let length = payload.remaining() as u32;
+    let end_address = start_address + length - 1;
+    let (connection, intf) =
+        device.connect_for_address(start_address, Some(end_address))?;
+    let connection = config().configure(connection);
+    let connection = configure_transfer_size(
+        device,
+        connection,
+        intf.interface(),
+        &options.transfer_sizes,
+    )?
+    .with_poll_observer(show_erase_wait);
+    intf.check_writable_range(start_address, end_address)?;
 
-    for chunk in data.chunks(transfer_size as usize) {
-        connection.download(addr, chunk)?;
-        addr += chunk.len() as u32;
-        bytes_downloaded += chunk.len();
+    // whole pages are erased
+    let aligned = intf
+        .interface()
+        .layout()
+        .align_range_to_pages(start_address, end_address);
+    warn_unaligned(aligned, (start_address, end_address));
+    // padded bytes are written and are part of the expected image
+    let padding = match options.pad {
+        Some(_) if aligned.1 > end_address => aligned.1 - end_address,
+        _ => 0,
+    };
+    if padding > 0 {
+        println!("Padding image with {padding} bytes");
+    }
+    let mut payload = Padded {
+        payload,
+        padding: padding as usize,
+        fill: options.pad.unwrap_or_default(),
+    };
+    let data_end = start_address + length + padding;
+    let mut erase_pages = intf.get_erase_pages(aligned.0, aligned.1);
+    let mut spans = vec![(start_address, data_end)];
 
-        let percentage = (100 * bytes_downloaded) / data.len();
-        let filled = (60 * bytes_downloaded) / data.len();
-        print!(
-            "\r  Flashing {:3}% [{}]",
-            percentage,
-            "#".repeat(filled) + &" ".repeat(60 - filled)
+    let mut loaded = None;
+    if options.only_changed {
+        if device.can_upload()? {
+            let mut data =
+                Vec::with_capacity((data_end - start_address) as usize);
+            payload.read(data.capacity(), &mut data)?;
+            let page_ends = erase_pages
+                .iter()
+                .skip(1)
+                .copied()
+                .chain([aligned.1.wrapping_add(1)]);
+            let pages: Vec<(u32, u32)> =
+                erase_pages.iter().copied().zip(page_ends).collect();
+            let changed =
+                changed_pages(&connection, &pages, start_address, &data, sink)?;
+            println!(
+                "{} of {} pages unchanged",
+                pages.len() - changed.len(),
+                pages.len()
+            );
+            erase_pages = changed.iter().map(|&(page, _)| page).collect();
+            spans = changed
+                .iter()
+                .map(|&(page, end)| {
+                    (page.max(start_address), end.min(data_end))
+                })
+                .collect();
+            loaded = Some(data);
+        } else {
+            println!("Warning: device cannot upload, writing all pages");
+        }
+    }
+    let payload: &mut dyn RangePayload = match &loaded {
+        Some(data) => &mut data.as_slice(),
+        None => &mut payload,
+    };
+    let mut pending_pages = erase_pages.iter().copied().peekable();
+
+    if let EraseStrategy::AllUpFront = options.erase_strategy {
+        erase_all(&connection, &mut pending_pages, sink)?;
+    }
+
+    let transfer_size = connection.transfer_size() as u32;
+    let total = spans.iter().map(|(start, end)| (end - start) as u64).sum();
+    let mut progress = Progress::new(Phase::Writing, total);
+    // the whole payload goes through the checksum, unchanged pages included
+    written.push_range(start_address, data_end - start_address);
+    let mut chunk = Vec::with_capacity(transfer_size as usize);
+    let mut read = |len: u32, chunk: &mut Vec<u8>| {
+        chunk.clear();
+        payload.read(len as usize, chunk)?;
+        written.update(chunk);
+        Ok::<_, CliError>(())
+    };
+    let mut pos = start_address;
+    for (mut addr, span_end) in spans {
+        read(addr - pos, &mut chunk)?;
+        while addr < span_end {
+            read(transfer_size.min(span_end - addr), &mut chunk)?;
+            write_chunk(&connection, &mut pending_pages, addr, &chunk)?;
+            addr += chunk.len() as u32;
+            sink.update(progress.advance(chunk.len() as u64));
+        }
+        pos = span_end;
+    }
+    read(data_end - pos, &mut chunk)?;
+    // some devices only report write errors on the next status query
+    connection.get_status()?.ok()?;
+    connection.complete_download();
+    sink.finish(&progress);
+    Ok(DownloadResult {
+        bytes_written: total,
+        pages_erased: erase_pages.len() as u64,
+        elapsed: started.elapsed(),
+    })
+}
+
+pub(crate) fn print_download_result(result: &DownloadResult) {
+    println!(
+        "Wrote {} bytes ({} pages erased) in {:.1}s ({:.1} KB/s)",
+        result.bytes_written,
+        result.pages_erased,
+        result.elapsed.as_secs_f64(),
+        result.kb_per_second(),
+    );
+}
+
+fn warn_unaligned(aligned: (u32, u32), range: (u32, u32)) {
+    if aligned != range {
+        println!(
+            "Warning: range is not page aligned, \
+            {:#010x} - {:#010x} will be erased",
+            aligned.0, aligned.1
         );
-        let _ = io::stdout().flush();
     }
-    println!();
+}
 
+fn erase_all(
+    connection: &DfuConnection,
+    pages: &mut Peekable<impl ExactSizeIterator<Item = u32>>,
+    sink: &mut dyn ProgressSink,
+) -> Result<(), DfuError> {
+    let mut progress = Progress::new(Phase::Erasing, pages.len() as u64);
+    for page_addr in pages.by_ref() {
+        connection.dfuse_page_erase(page_addr)?;
+        sink.update(progress.advance(1));
+    }
+    sink.finish(&progress);
     Ok(())
 }
 
+// Erases the pages touched by `chunk` not erased yet (per chunk strategy)
+// before writing it
+fn write_chunk(
+    connection: &DfuConnection,
+    pending_pages: &mut Peekable<impl Iterator<Item = u32>>,
+    addr: u32,
+    chunk: &[u8],
+) -> Result<(), DfuError> {
+    let chunk_end = addr + chunk.len() as u32 - 1;
+    while let Some(page_addr) = pending_pages.next_if(|&p| p <= chunk_end) {
+        connection.dfuse_page_erase(page_addr)?;
+    }
+    connection.download(addr, chunk)
+}
+
+/// Pages (`end` exclusive) whose current content differs from `data`
+///
+/// Only the part of each page covered by `data` is compared. Changed
+/// pages are then erased as a whole, so the rest of a partially covered
+/// page is lost, as when writing without `only_changed`. The device is
+/// left idle, ready for the erase.
+fn changed_pages(
+    connection: &DfuConnection,
+    pages: &[(u32, u32)],
+    start_address: u32,
+    data: &[u8],
+    sink: &mut dyn ProgressSink,
+) -> Result<Vec<(u32, u32)>, DfuError> {
+    let data_end = start_address + data.len() as u32;
+    let mut progress = Progress::new(Phase::Verifying, data.len() as u64);
+    let mut changed = Vec::new();
+    for &(page, page_end) in pages {
+        let (start, end) = (page.max(start_address), page_end.min(data_end));
+        let expected = &data
+            [(start - start_address) as usize..(end - start_address) as usize];
+        let mut current = Vec::with_capacity(expected.len());
+        upload_range_with(
+            connection,
+            start,
+            end - start,
+            None,
+            &mut NoProgress,
+            &mut |chunk| current.extend_from_slice(chunk),
+        )?;
+        if current != expected {
+            changed.push((page, page_end));
+        }
+        sink.update(progress.advance(expected.len() as u64));
+    }
+    // erasing from dfuUPLOAD-IDLE stalls
+    connection.reset_state()?;
+    sink.finish(&progress);
+    Ok(changed)
+}
+
+/// Ranges written to the device along with the checksum of their content
+struct WrittenImage {
+    checksum: Option<Checksum>,
+    hasher: Option<Box<dyn DynDigest>>,
+    ranges: Vec<(u32, u32)>,
+}
+
+impl WrittenImage {
+    fn new(checksum: Option<Checksum>) -> Self {
+        WrittenImage {
+            checksum,
+            hasher: checksum.map(|c| c.hasher()),
+            ranges: Vec::new(),
+        }
+    }
+
+    fn push_range(&mut self, start_address: u32, length: u32) {
+        if self.hasher.is_some() {
+            self.ranges.push((start_address, length));
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(data);
+        }
+    }
+
+    /// Read back the written ranges, streaming them through the hasher
+    fn compare_checksum(
+        self,
+        device: &DfuDevice,
+        sink: &mut dyn ProgressSink,
+    ) -> Result<(), CliError> {
+        let (Some(checksum), Some(hasher)) = (self.checksum, self.hasher)
+        else {
+            return Ok(());
+        };
+        let mut device_hasher = checksum.hasher();
+        for (start, len) in self.ranges {
+            let (connection, intf) =
+                device.connect_for_address(start, Some(start + len - 1))?;
+            let connection = config().configure(connection);
+            connection.reset_state()?;
+            let spans = intf.interface().layout().split_at_segments(start, len);
+            upload_spans_with(&connection, &spans, None, sink, &mut |chunk| {
+                device_hasher.update(chunk)
+            })?;
+        }
+
+        let image = to_hex(&hasher.finalize());
+        let read_back = to_hex(&device_hasher.finalize());
+        println!("{} (image):  {image}", checksum.label());
+        println!("{} (device): {read_back}", checksum.label());
+        if image == read_back {
+            println!("Checksums match");
+            Ok(())
+        } else {
+            Err(CliError::ChecksumMismatch)
+        }
+    }
+}
+
 fn reboot(
     device: &DfuDevice,
     addr: u32,
     payload: &[u8],
     reboot_addr: u32,
+    format: RebootFormat,
+    // start and end (inclusive) of the flash ranges written afterwards
+    next_ranges: &[(u32, u32)],
 ) -> Result<DfuDevice, DfuError> {
-    let connection = device.connect(0, 0)?;
-    connection.reboot(addr, payload, reboot_addr)?;
+    let connection = config().configure(device.connect(0, 0)?);
+    connection.reboot_with_format(addr, payload, reboot_addr, format)?;
     drop(connection);
 
+    // the reconnected device must be able to receive the next ranges
+    let accepts_next_ranges = |dev: &DfuDevice| {
+        next_ranges
+            .iter()
+            .all(|&(start, end)| dev.find_interface(start, Some(end)).is_ok())
+    };
     println!("Waiting for device to reconnect...");
-    let start = Instant::now();
-    loop {
-        let devices = find_dfu_devices(
-            Some(device.vendor_id()),
-            Some(device.product_id()),
-        )?;
-        if !devices.is_empty() {
-            println!("Device reconnected");
-            return Ok(devices.into_iter().next().unwrap());
-        }
-        if start.elapsed() >= Duration::from_secs(30) {
-            return Err(DfuError::Timeout);
-        }
-    }
+    let device = device.rediscover_until(
+        REDISCOVER_TIMEOUT,
+        REDISCOVER_INTERVAL,
+        accepts_next_ranges,
+    )?;
+    println!("Device reconnected");
+    Ok(device)
 }
 
-fn leave(device: &DfuDevice) -> Result<(), DfuError> {
+pub(crate) fn leave(device: &DfuDevice) -> Result<(), DfuError> {
     println!("Leaving DFU...");
-    let connection = device.connect(0, 0)?;
+    let connection = config().configure(device.connect(0, 0)?);
     connection.leave()
 }
+
+#[cfg(test)]
+mod tests {
+    use dfu::ControlTransfer;
+
+    use super::*;
+
+    const DFU_CMD_UPLOAD: u8 = 2;
+
+    #[test]
+    fn test_check_description() {
+        let description = || Some("EdgeTX on Radiomaster TX16S".to_string());
+        // nothing to check against without an explicit target
+        assert!(check_description(description(), None).is_ok());
+        assert!(check_description(None, Some("TX16S")).is_ok());
+        assert!(check_description(description(), Some("tx16s")).is_ok());
+        assert!(matches!(
+            check_description(description(), Some("TX12")),
+            Err(CliError::TargetMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_scan_ranges() {
+        let mut data = uf2::encode(&[0; 512], 0x08000000, None).ok().unwrap();
+        data.extend(uf2::encode(&[0; 16], 0x08001000, None).ok().unwrap());
+        let blocks =
+            UF2BlockReader::new(data.as_slice(), UF2DecodeMode::Strict);
+        let (ranges, _) = scan_ranges(blocks).ok().unwrap();
+        let ranges: Vec<_> = ranges.iter().map(|r| (r.start, r.end)).collect();
+        assert_eq!(
+            ranges,
+            [(0x08000000, 0x08000200), (0x08001000, 0x08001010)]
+        );
+
+        // no wrapping around the end of the address space
+        let data = uf2::encode(&[0; 16], 0xfffffff0, None).ok().unwrap();
+        let blocks =
+            UF2BlockReader::new(data.as_slice(), UF2DecodeMode::Strict);
+        assert!(scan_ranges(blocks).is_err());
+    }
+
+    #[test]
+    fn test_block_payload() {
+        let data: Vec<u8> = (0..=255).cycle().take(600).collect();
+        let encoded = uf2::encode(&data, 0x08000000, None).ok().unwrap();
+        let mut blocks =
+            UF2BlockReader::new(Cursor::new(encoded), UF2DecodeMode::Strict);
+
+        // reads are not aligned on blocks
+        let mut payload = BlockPayload::new(&mut blocks, 600);
+        let mut buf = Vec::new();
+        payload.read(300, &mut buf).ok().unwrap();
+        assert_eq!(payload.remaining(), 300);
+        payload.read(300, &mut buf).ok().unwrap();
+        assert_eq!(payload.remaining(), 0);
+        assert_eq!(buf, data);
+
+        // the rest of a failed range is skipped
+        let encoded = uf2::encode(&data, 0x08000000, None).ok().unwrap();
+        let mut blocks =
+            UF2BlockReader::new(Cursor::new(encoded), UF2DecodeMode::Strict);
+        let mut payload = BlockPayload::new(&mut blocks, 512);
+        payload.read(100, &mut Vec::new()).ok().unwrap();
+        payload.skip_rest().ok().unwrap();
+        let mut payload = BlockPayload::new(&mut blocks, 88);
+        let mut buf = Vec::new();
+        payload.read(88, &mut buf).ok().unwrap();
+        assert_eq!(buf, data[512..]);
+    }
+
+    #[test]
+    fn test_padded() {
+        let mut data: &[u8] = &[1, 2, 3];
+        let mut payload = Padded {
+            payload: &mut data,
+            padding: 3,
+            fill: 0xff,
+        };
+        assert_eq!(payload.remaining(), 6);
+        let mut buf = Vec::new();
+        payload.read(2, &mut buf).ok().unwrap();
+        payload.read(4, &mut buf).ok().unwrap();
+        assert_eq!(buf, [1, 2, 3, 0xff, 0xff, 0xff]);
+        assert_eq!(payload.remaining(), 0);
+    }
+
+    #[test]
+    fn test_raw_image_end() {
+        assert!(matches!(raw_image_end(&[0; 4], 0x08000000), Ok(0x08000003)));
+        assert!(matches!(raw_image_end(&[0; 16], 0xfffffff0), Ok(u32::MAX)));
+        assert!(matches!(
+            raw_image_end(&[], 0x08000000),
+            Err(CliError::EmptyImage)
+        ));
+        assert!(matches!(
+            raw_image_end(&[0; 17], 0xfffffff0),
+            Err(CliError::ImageOutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_changed_pages() {
+        let connection = DfuConnection::recording();
+        let pages = [(0x08000000, 0x08000400), (0x08000400, 0x08000800)];
+        // the recorder reads back zeroes
+        let mut data = vec![0; 0x600];
+        data[0x500] = 0xff;
+        let changed = changed_pages(
+            &connection,
+            &pages,
+            0x08000000,
+            &data,
+            &mut NoProgress,
+        )
+        .unwrap();
+        assert_eq!(changed, vec![(0x08000400, 0x08000800)]);
+
+        // one upload per page (only the covered part), none of them
+        // stalled: the recorder stalls requests sent in the wrong state
+        let uploads: Vec<u16> = connection
+            .recorded_transfers()
+            .into_iter()
+            .filter_map(|t| match t {
+                ControlTransfer::In {
+                    request: DFU_CMD_UPLOAD,
+                    length,
+                    ..
+                } => Some(length),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(uploads, vec![0x400, 0x200]);
+        // the device is idle again: the erase is accepted
+        connection.dfuse_page_erase(0x08000400).unwrap();
+    }
+
+    #[test]
+    fn test_skip_failed_range() {
+        let connection = DfuConnection::recording();
+        // setting the address in dfuUPLOAD-IDLE stalls: dfuERROR
+        connection.read_block(2, 16).unwrap();
+        let err = connection.set_address(0x08000000).unwrap_err();
+
+        let mut outcomes = Vec::new();
+        let range = (0x08000000, 0x080000ff);
+        assert!(
+            skip_failed_range(&connection, &mut outcomes, range, err).is_ok()
+        );
+        assert!(matches!(
+            outcomes[..],
+            [(0x08000000, 0x080000ff, Some(DfuError::Transfer(_)))]
+        ));
+        // back to dfuIDLE: the next range can be written
+        connection.download(0x08000100, &[0; 16]).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt::Display;
+
+pub(crate) const DFUSE_CMD_GET: u8 = 0x00;
+pub(crate) const DFUSE_CMD_ADDR: u8 = 0x21;
+pub(crate) const DFUSE_CMD_ERASE: u8 = 0x41;
+pub(crate) const DFUSE_CMD_READ_UNPROTECT: u8 = 0x92;
+
+/// DfuSe special command, as listed by the device in response to the
+/// "Get" command (see [crate::DfuConnection::dfuse_get_commands()])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DfuseCommand {
+    GetCommands,
+    SetAddress,
+    /// Page erase, or mass erase when sent without an address
+    Erase,
+    ReadUnprotect,
+    Unknown(u8),
+}
+
+impl From<u8> for DfuseCommand {
+    fn from(code: u8) -> Self {
+        match code {
+            DFUSE_CMD_GET => DfuseCommand::GetCommands,
+            DFUSE_CMD_ADDR => DfuseCommand::SetAddress,
+            DFUSE_CMD_ERASE => DfuseCommand::Erase,
+            DFUSE_CMD_READ_UNPROTECT => DfuseCommand::ReadUnprotect,
+            code => DfuseCommand::Unknown(code),
+        }
+    }
+}
+
+impl DfuseCommand {
+    pub fn code(&self) -> u8 {
+        match self {
+            DfuseCommand::GetCommands => DFUSE_CMD_GET,
+            DfuseCommand::SetAddress => DFUSE_CMD_ADDR,
+            DfuseCommand::Erase => DFUSE_CMD_ERASE,
+            DfuseCommand::ReadUnprotect => DFUSE_CMD_READ_UNPROTECT,
+            DfuseCommand::Unknown(code) => *code,
+        }
+    }
+}
+
+impl Display for DfuseCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DfuseCommand::GetCommands => write!(f, "Get commands"),
+            DfuseCommand::SetAddress => write!(f, "Set address pointer"),
+            DfuseCommand::Erase => write!(f, "Erase (page and mass erase)"),
+            DfuseCommand::ReadUnprotect => write!(f, "Read unprotect"),
+            DfuseCommand::Unknown(code) => write!(f, "Unknown ({code:#04x})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dfuse_command() {
+        let commands: Vec<DfuseCommand> = [0x00, 0x21, 0x41, 0x92, 0x33]
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                DfuseCommand::GetCommands,
+                DfuseCommand::SetAddress,
+                DfuseCommand::Erase,
+                DfuseCommand::ReadUnprotect,
+                DfuseCommand::Unknown(0x33),
+            ]
+        );
+        for command in commands {
+            assert_eq!(DfuseCommand::from(command.code()), command);
+        }
+        assert_eq!(DfuseCommand::Unknown(0x33).to_string(), "Unknown (0x33)");
+    }
+}
@@ -1,40 +1,81 @@
-use std::time::Instant;
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
 
 use nusb::{
     MaybeFuture,
     transfer::{ControlIn, ControlOut, ControlType, Recipient},
 };
 
-use crate::DEFAULT_TIMEOUT;
+use crate::command::*;
 use crate::error::*;
+#[cfg(any(test, feature = "recording"))]
+use crate::recording::*;
+use crate::{DEFAULT_TIMEOUT, DfuDescriptor, RebootFormat};
+#[cfg(any(test, feature = "recording"))]
+use std::sync::Arc;
 
-const DFU_CMD_DOWNLOAD: u8 = 1;
-const DFU_CMD_UPLOAD: u8 = 2;
-const DFU_CMD_GETSTATUS: u8 = 3;
-const DFU_CMD_CLRSTATUS: u8 = 4;
-// const DFU_CMD_GETSTATE: u8 = 5;
-const DFU_CMD_ABORT: u8 = 6;
+const DFU_CMD_DETACH: u8 = 0;
+pub(crate) const DFU_CMD_DOWNLOAD: u8 = 1;
+pub(crate) const DFU_CMD_UPLOAD: u8 = 2;
+pub(crate) const DFU_CMD_GETSTATUS: u8 = 3;
+pub(crate) const DFU_CMD_CLRSTATUS: u8 = 4;
+pub(crate) const DFU_CMD_GETSTATE: u8 = 5;
+pub(crate) const DFU_CMD_ABORT: u8 = 6;
 
 const DFU_STATE_LEN: u16 = 6;
 
-const DFUSE_CMD_ADDR: u8 = 0x21;
-const DFUSE_CMD_ERASE: u8 = 0x41;
+// Upper bound on GETSTATUS requests while waiting for the device
+const POLL_MAX_ITERATIONS: u32 = 100_000;
+// Waiting for a busy device is limited to this many control transfer
+// timeouts
+const POLL_DEADLINE_FACTOR: u32 = 20;
+// Shorter timeouts (ex: zero) would expire before the first transfer
+const MIN_TIMEOUT: Duration = Duration::from_millis(1);
+// The deadline is extended to this many times the longest poll timeout
+// reported by the device (ex: mass erase)
+const POLL_TIMEOUT_DEADLINE_FACTOR: u32 = 4;
+
+const USB_REQ_CLEAR_FEATURE: u8 = 1;
+const USB_FEATURE_ENDPOINT_HALT: u16 = 0;
 
-// const DFU_STATE_APP_IDLE: u8 = 0x00;
-// const DFU_STATE_APP_DETACH: u8 = 0x01;
-const DFU_STATE_DFU_IDLE: u8 = 0x02;
-// const DFU_STATE_DFU_DOWNLOAD_SYNC: u8 = 0x03;
+const DFU_STATE_APP_IDLE: u8 = 0x00;
+const DFU_STATE_APP_DETACH: u8 = 0x01;
+pub(crate) const DFU_STATE_DFU_IDLE: u8 = 0x02;
+const DFU_STATE_DFU_DOWNLOAD_SYNC: u8 = 0x03;
 const DFU_STATE_DFU_DOWNLOAD_BUSY: u8 = 0x04;
-// const DFU_STATE_DFU_DOWNLOAD_IDLE: u8 = 0x05;
-// const DFU_STATE_DFU_MANIFEST_SYNC: u8 = 0x06;
-// const DFU_STATE_DFU_MANIFEST: u8 = 0x07;
-// const DFU_STATE_DFU_MANIFEST_WAIT_RESET: u8 = 0x08;
-// const DFU_STATE_DFU_UPLOAD_IDLE: u8 = 0x09;
-// const DFU_STATE_DFU_ERROR: u8 = 0x0a;
+pub(crate) const DFU_STATE_DFU_DOWNLOAD_IDLE: u8 = 0x05;
+const DFU_STATE_DFU_MANIFEST_SYNC: u8 = 0x06;
+const DFU_STATE_DFU_MANIFEST: u8 = 0x07;
+const DFU_STATE_DFU_MANIFEST_WAIT_RESET: u8 = 0x08;
+pub(crate) const DFU_STATE_DFU_UPLOAD_IDLE: u8 = 0x09;
+pub(crate) const DFU_STATE_DFU_ERROR: u8 = 0x0a;
 
 pub struct DfuConnection {
-    interface: nusb::Interface,
+    transport: Transport,
     xfer_size: u16,
+    // the device did not report a transfer size
+    xfer_size_fallback: bool,
+    timeout: Duration,
+    // clear-halt before the next transfer (see `with_clear_halt()`)
+    clear_halt: AtomicBool,
+    // a download or upload was started and not terminated (aborted on drop)
+    transfer_pending: AtomicBool,
+    on_poll: Option<Box<PollObserver>>,
+}
+
+/// Called with the status and the time elapsed on each poll while
+/// waiting for the device to complete a command (ex: erase)
+pub type PollObserver = dyn Fn(&DfuStatus, Duration) + Send + Sync;
+
+enum Transport {
+    // the device is kept to reset it (see `DfuConnection::detach()`)
+    Usb(nusb::Device, nusb::Interface),
+    #[cfg(any(test, feature = "recording"))]
+    Recording(Arc<Recorder>),
 }
 
 #[allow(dead_code)]
@@ -42,18 +83,95 @@ pub struct DfuConnection {
 pub struct DfuStatus {
     pub status: u8,
     pub poll_timeout: u32,
+    /// Raw state (`bState`), see [DfuStatus::state()]
     pub state: u8,
 }
 
+/// Device state (`bState`), as described in section 6.1.2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DfuState {
+    AppIdle,
+    AppDetach,
+    DfuIdle,
+    DownloadSync,
+    DownloadBusy,
+    DownloadIdle,
+    ManifestSync,
+    Manifest,
+    ManifestWaitReset,
+    UploadIdle,
+    Error,
+    Unknown(u8),
+}
+
+impl DfuState {
+    pub fn from_u8(state: u8) -> Self {
+        match state {
+            DFU_STATE_APP_IDLE => DfuState::AppIdle,
+            DFU_STATE_APP_DETACH => DfuState::AppDetach,
+            DFU_STATE_DFU_IDLE => DfuState::DfuIdle,
+            DFU_STATE_DFU_DOWNLOAD_SYNC => DfuState::DownloadSync,
+            DFU_STATE_DFU_DOWNLOAD_BUSY => DfuState::DownloadBusy,
+            DFU_STATE_DFU_DOWNLOAD_IDLE => DfuState::DownloadIdle,
+            DFU_STATE_DFU_MANIFEST_SYNC => DfuState::ManifestSync,
+            DFU_STATE_DFU_MANIFEST => DfuState::Manifest,
+            DFU_STATE_DFU_MANIFEST_WAIT_RESET => DfuState::ManifestWaitReset,
+            DFU_STATE_DFU_UPLOAD_IDLE => DfuState::UploadIdle,
+            DFU_STATE_DFU_ERROR => DfuState::Error,
+            state => DfuState::Unknown(state),
+        }
+    }
+}
+
+impl Display for DfuState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DfuState::AppIdle => "appIDLE",
+            DfuState::AppDetach => "appDETACH",
+            DfuState::DfuIdle => "dfuIDLE",
+            DfuState::DownloadSync => "dfuDNLOAD-SYNC",
+            DfuState::DownloadBusy => "dfuDNBUSY",
+            DfuState::DownloadIdle => "dfuDNLOAD-IDLE",
+            DfuState::ManifestSync => "dfuMANIFEST-SYNC",
+            DfuState::Manifest => "dfuMANIFEST",
+            DfuState::ManifestWaitReset => "dfuMANIFEST-WAIT-RESET",
+            DfuState::UploadIdle => "dfuUPLOAD-IDLE",
+            DfuState::Error => "dfuERROR",
+            DfuState::Unknown(state) => {
+                return write!(f, "unknown ({state:#04x})");
+            }
+        };
+        write!(f, "{name}")
+    }
+}
+
 impl DfuStatus {
-    fn from_raw(data: &[u8]) -> Self {
-        DfuStatus {
+    fn from_raw(data: &[u8]) -> Result<Self, DfuError> {
+        if data.len() < DFU_STATE_LEN as usize {
+            return Err(DfuError::ShortStatus(data.len()));
+        }
+        Ok(DfuStatus {
             status: data[0],
             poll_timeout: (data[3] as u32) << 16
                 | (data[2] as u32) << 8
                 | (data[1] as u32),
             state: data[4],
-        }
+        })
+    }
+
+    pub fn state(&self) -> DfuState {
+        DfuState::from_u8(self.state)
+    }
+
+    /// Name of the status code in the DFU specification (ex: "errERASE"),
+    /// `None` for unknown codes
+    pub fn status_name(&self) -> Option<&'static str> {
+        status_description(self.status).map(|(name, _)| name)
+    }
+
+    /// Meaning of the status code (ex: "memory erase function failed")
+    pub fn status_description(&self) -> Option<&'static str> {
+        status_description(self.status).map(|(_, description)| description)
     }
 
     pub fn ok(&self) -> Result<(), DfuError> {
@@ -63,10 +181,17 @@ impl DfuStatus {
     pub fn ret<T>(&self, t: T) -> Result<T, DfuError> {
         if self.status != 0 {
             Err(DfuError::from(self))
+        } else if self.is_runtime_mode() {
+            Err(DfuError::LeftDfuMode)
         } else {
             Ok(t)
         }
     }
+
+    /// `appIDLE` or `appDETACH`: the device is not in DFU mode anymore
+    pub fn is_runtime_mode(&self) -> bool {
+        self.state <= DFU_STATE_APP_DETACH
+    }
 }
 
 impl From<&DfuStatus> for DfuError {
@@ -76,22 +201,129 @@ impl From<&DfuStatus> for DfuError {
 }
 
 impl DfuConnection {
-    pub(crate) fn new(interface: nusb::Interface, xfer_size: u16) -> Self {
+    pub(crate) fn new(
+        device: nusb::Device,
+        interface: nusb::Interface,
+        xfer_size: u16,
+    ) -> Self {
         DfuConnection {
-            interface,
+            transport: Transport::Usb(device, interface),
             xfer_size: if xfer_size > 0 {
                 xfer_size
             } else {
+                log::warn!(
+                    "Transfer size not reported by the device, using {} bytes",
+                    crate::DEFAULT_TRANSFER_SIZE
+                );
                 crate::DEFAULT_TRANSFER_SIZE
             },
+            xfer_size_fallback: xfer_size == 0,
+            timeout: DEFAULT_TIMEOUT,
+            clear_halt: AtomicBool::new(false),
+            transfer_pending: AtomicBool::new(false),
+            on_poll: None,
+        }
+    }
+
+    /// Connection recording every control transfer instead of
+    /// sending it to a device (see [ControlTransfer])
+    #[cfg(any(test, feature = "recording"))]
+    pub fn recording() -> Self {
+        Self::recording_on(Arc::default())
+    }
+
+    // recording shared with other connections (ex: to record a drop)
+    #[cfg(any(test, feature = "recording"))]
+    fn recording_on(recorder: Arc<Recorder>) -> Self {
+        DfuConnection {
+            transport: Transport::Recording(recorder),
+            xfer_size: crate::DEFAULT_TRANSFER_SIZE,
+            xfer_size_fallback: false,
+            timeout: DEFAULT_TIMEOUT,
+            clear_halt: AtomicBool::new(false),
+            transfer_pending: AtomicBool::new(false),
+            on_poll: None,
+        }
+    }
+
+    /// Control transfers recorded so far (empty if not recording)
+    #[cfg(any(test, feature = "recording"))]
+    pub fn recorded_transfers(&self) -> Vec<ControlTransfer> {
+        match &self.transport {
+            Transport::Recording(recorder) => recorder.transfers(),
+            _ => Vec::new(),
         }
     }
 
+    /// Override the transfer size reported by the device
+    pub fn with_transfer_size(mut self, xfer_size: u16) -> Self {
+        if xfer_size > 0 {
+            self.xfer_size = xfer_size;
+        }
+        self
+    }
+
+    /// Transfer size used if the device does not report one, instead of
+    /// the default 2048 bytes (which minimal bootloaders may not accept)
+    pub fn with_fallback_transfer_size(mut self, xfer_size: u16) -> Self {
+        if self.xfer_size_fallback && xfer_size > 0 {
+            self.xfer_size = xfer_size;
+        }
+        self
+    }
+
+    /// Override the timeout used for each control transfer
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Change the timeout used for each control transfer (5 seconds by
+    /// default, 1 ms at least), waiting for a busy device is limited to 20
+    /// times this
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout.max(MIN_TIMEOUT);
+    }
+
+    /// Clear a halt condition on the control endpoint before the
+    /// first transfer (for devices coming up with a stalled endpoint)
+    pub fn with_clear_halt(self, clear_halt: bool) -> Self {
+        self.clear_halt.store(clear_halt, Ordering::Relaxed);
+        self
+    }
+
+    /// Observe each status poll while the device is busy, so that
+    /// long operations (ex: mass erase) can be reported to the user
+    pub fn with_poll_observer(
+        mut self,
+        on_poll: impl Fn(&DfuStatus, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_poll = Some(Box::new(on_poll));
+        self
+    }
+
     pub fn transfer_size(&self) -> u16 {
         self.xfer_size
     }
 
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Send a standard `CLEAR_FEATURE(ENDPOINT_HALT)` request to the
+    /// control endpoint
+    pub fn clear_halt(&self) -> Result<(), DfuError> {
+        self.clear_halt.store(false, Ordering::Relaxed);
+        self.transport.clear_halt(self.timeout)
+    }
+
     pub fn reset_state(&self) -> Result<(), DfuError> {
+        // an idle device has no error status: skip GETSTATUS (falls back
+        // to it if GETSTATE is not supported)
+        if let Ok(DFU_STATE_DFU_IDLE) = self.get_state() {
+            self.transfer_pending.store(false, Ordering::Relaxed);
+            return Ok(());
+        }
         let mut st = self.get_status()?;
         if st.status != 0 {
             self.clear_status()?;
@@ -101,13 +333,20 @@ impl DfuConnection {
             self.abort()?;
             st = self.get_status()?;
         }
+        self.transfer_pending.store(false, Ordering::Relaxed);
         st.ok()
     }
 
     pub fn get_status(&self) -> Result<DfuStatus, DfuError> {
         let data = self.dfu_cmd_in(DFU_CMD_GETSTATUS, 0, DFU_STATE_LEN)?;
-        let st = DfuStatus::from_raw(&data);
-        Ok(st)
+        DfuStatus::from_raw(&data)
+    }
+
+    /// Current state (`bState`), without the side effects of GETSTATUS
+    /// (status and poll timeout are left untouched)
+    pub fn get_state(&self) -> Result<u8, DfuError> {
+        let data = self.dfu_cmd_in(DFU_CMD_GETSTATE, 0, 1)?;
+        data.first().copied().ok_or(DfuError::ShortStatus(0))
     }
 
     pub fn clear_status(&self) -> Result<(), DfuError> {
@@ -115,12 +354,127 @@ impl DfuConnection {
     }
 
     pub fn abort(&self) -> Result<(), DfuError> {
-        self.dfu_cmd_out(DFU_CMD_ABORT, 0, &[])
+        self.dfu_cmd_out(DFU_CMD_ABORT, 0, &[])?;
+        self.transfer_pending.store(false, Ordering::Relaxed);
+        Ok(())
     }
 
+    /// Ask a device in runtime mode (`appIDLE`) to switch to DFU mode
+    ///
+    /// Devices which do not detach by themselves (see
+    /// [DfuDescriptor::will_detach()]) are reset right after `DFU_DETACH`,
+    /// within the detach timeout. Either way, the device then re-enumerates
+    /// in DFU mode.
+    pub fn detach(&self, desc: &DfuDescriptor) -> Result<(), DfuError> {
+        self.dfu_cmd_out(DFU_CMD_DETACH, desc.detach_timeout(), &[])
+            .or_else(ignore_disconnect)?;
+        if desc.will_detach() {
+            return Ok(());
+        }
+        self.transport.reset().or_else(ignore_disconnect)
+    }
+
+    /// Write `data` (up to the transfer size) at `addr`
+    ///
+    /// The address pointer is set before each block, which is then sent
+    /// as transaction 2: DfuSe writes block `n` at
+    /// `address pointer + (n - 2) * transfer size`, so block 2 always lands
+    /// at `addr`, whatever the size of the previous blocks.
     pub fn download(&self, addr: u32, data: &[u8]) -> Result<(), DfuError> {
+        if data.len() > self.xfer_size as usize {
+            return Err(DfuError::TransferTooLarge {
+                size: data.len(),
+                max: self.xfer_size,
+            });
+        }
+        self.set_address(addr)?;
+        self.write_block(2, data)
+    }
+
+    /// Mark the current download as complete: the connection can then be
+    /// dropped without aborting it, leaving the device in `dfuDNLOAD-IDLE`
+    /// for a later [DfuConnection::leave()]
+    pub fn complete_download(&self) {
+        self.transfer_pending.store(false, Ordering::Relaxed);
+    }
+
+    /// Write `data` of any length at `addr`, split in transfer size
+    /// chunks (see [DfuConnection::download()])
+    ///
+    /// `progress` is called with the number of bytes written so far and
+    /// the total after each chunk. Pages must have been erased before.
+    pub fn download_with_progress(
+        &self,
+        addr: u32,
+        data: &[u8],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<(), DfuError> {
+        check_range(addr, data.len())?;
+        let mut done = 0;
+        for chunk in data.chunks(self.xfer_size as usize) {
+            self.download(addr + done as u32, chunk)?;
+            done += chunk.len();
+            progress(done, data.len());
+        }
+        Ok(())
+    }
+
+    /// Read back `expected.len()` bytes from `addr` in transfer size
+    /// chunks and compare them with `expected`
+    ///
+    /// Fails with [DfuError::VerifyMismatch] on the first differing
+    /// address. The upload is aborted afterwards, leaving the device idle.
+    pub fn verify(&self, addr: u32, expected: &[u8]) -> Result<(), DfuError> {
+        self.verify_with_progress(addr, expected, &mut |_, _| {})
+    }
+
+    /// Same as [DfuConnection::verify()], `progress` being called with the
+    /// number of bytes compared so far and the total after each chunk
+    pub fn verify_with_progress(
+        &self,
+        addr: u32,
+        expected: &[u8],
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<(), DfuError> {
+        check_range(addr, expected.len())?;
+        self.start_upload_at(addr)?;
+
+        let mut done = 0;
+        let mut block_nr = 0;
+        for chunk in expected.chunks(self.xfer_size as usize) {
+            // transaction numbers (block + 2) are 16 bits: the address
+            // pointer is moved forward before they wrap
+            if block_nr > u16::MAX - 2 {
+                self.start_upload_at(addr + done as u32)?;
+                block_nr = 0;
+            }
+            let actual = self.upload(block_nr, chunk.len() as u16)?;
+            block_nr += 1;
+            let mismatch = chunk
+                .iter()
+                .zip(&actual)
+                .position(|(expected, actual)| expected != actual);
+            if let Some(offset) = mismatch {
+                self.abort()?;
+                return Err(DfuError::VerifyMismatch {
+                    addr: addr + (done + offset) as u32,
+                });
+            }
+            if actual.len() < chunk.len() {
+                self.abort()?;
+                return Err(DfuError::ShortUpload(actual.len()));
+            }
+            done += chunk.len();
+            progress(done, expected.len());
+        }
+        self.abort()
+    }
+
+    // setting the address stalls in dfuUPLOAD-IDLE or dfuERROR
+    fn start_upload_at(&self, addr: u32) -> Result<(), DfuError> {
+        self.reset_state()?;
         self.dfuse_set_address(addr)?;
-        self.dfu_dnload(2, data)
+        self.reset_state()
     }
 
     pub fn upload(
@@ -128,51 +482,115 @@ impl DfuConnection {
         block_nr: u16,
         length: u16,
     ) -> Result<Vec<u8>, DfuError> {
-        self.dfu_upload(2 + block_nr, length)
+        self.read_block(2 + block_nr, length)
+    }
+
+    /// Set the DfuSe address pointer used by the following transactions
+    /// (see [DfuConnection::write_block()])
+    pub fn set_address(&self, addr: u32) -> Result<(), DfuError> {
+        self.dfu_dnload(0, &encode_set_address(addr))
+    }
+
+    /// Send a single `DFU_DNLOAD` transaction and wait for the device
+    /// to be idle again
+    ///
+    /// DfuSe transaction numbers (`wValue`):
+    /// - `0`: command (set address, erase, leave...), encoded in `data`
+    /// - `1`: reserved
+    /// - `n >= 2`: data block written at
+    ///   `address pointer + (n - 2) * transfer size`
+    ///
+    /// Unlike [DfuConnection::download()], neither the address pointer
+    /// nor the transaction number are managed.
+    pub fn write_block(
+        &self,
+        transaction: u16,
+        data: &[u8],
+    ) -> Result<(), DfuError> {
+        if data.len() > self.xfer_size as usize {
+            return Err(DfuError::TransferTooLarge {
+                size: data.len(),
+                max: self.xfer_size,
+            });
+        }
+        self.dfu_dnload(transaction, data)
+    }
+
+    /// Send a single `DFU_UPLOAD` transaction
+    ///
+    /// Transaction numbers follow [DfuConnection::write_block()]: block
+    /// `n >= 2` is read from `address pointer + (n - 2) * transfer size`,
+    /// and `0` returns the list of supported DfuSe commands.
+    pub fn read_block(
+        &self,
+        transaction: u16,
+        length: u16,
+    ) -> Result<Vec<u8>, DfuError> {
+        self.dfu_upload(transaction, length)
     }
 
+    /// Write `data` (the reboot tag) at `addr`, then set the address
+    /// pointer to `reboot_addr` and leave DFU mode: execution resumes at
+    /// `reboot_addr`. The tag address is usually in RAM while the jump
+    /// address is the firmware start in flash.
     pub fn reboot(
         &self,
         addr: u32,
         data: &[u8],
         reboot_addr: u32,
     ) -> Result<(), DfuError> {
-        self.download(addr, data)?;
+        self.write_reboot_tag(addr, data)?;
         self.dfuse_set_address(reboot_addr)?;
-        let _ = self.dfu_dnload(0, &[]);
-        Ok(())
+        self.dfu_dnload(0, &[]).or_else(ignore_reset)
+    }
+
+    /// Write the reboot tag without jumping: the bootloader stays in
+    /// DFU mode on the next reset (see [DfuConnection::reboot()])
+    pub fn write_reboot_tag(
+        &self,
+        addr: u32,
+        data: &[u8],
+    ) -> Result<(), DfuError> {
+        self.download(addr, data)
+    }
+
+    /// Same as [DfuConnection::reboot()], `magic` is first encoded
+    /// according to the bootloader reboot `format`
+    pub fn reboot_with_format(
+        &self,
+        addr: u32,
+        magic: &[u8],
+        reboot_addr: u32,
+        format: RebootFormat,
+    ) -> Result<(), DfuError> {
+        self.reboot(addr, &format.encode(magic, reboot_addr), reboot_addr)
     }
 
     pub fn leave(&self) -> Result<(), DfuError> {
-        let _ = self.dfu_dnload(0, &[]);
-        Ok(())
+        self.dfu_dnload(0, &[]).or_else(ignore_reset)
+    }
+
+    /// DfuSe special commands supported by the device ("Get" command)
+    ///
+    /// The device must be in `dfuIDLE`, the upload is aborted afterwards.
+    /// Plain DFU devices usually stall the request.
+    pub fn dfuse_get_commands(&self) -> Result<Vec<DfuseCommand>, DfuError> {
+        let data = self.read_block(0, self.xfer_size)?;
+        self.abort()?;
+        Ok(data.into_iter().map(DfuseCommand::from).collect())
     }
 
     pub fn dfuse_page_erase(&self, addr: u32) -> Result<(), DfuError> {
-        let erase_cmd: Vec<u8> = vec![
-            DFUSE_CMD_ERASE,
-            addr as u8,
-            (addr >> 8) as u8,
-            (addr >> 16) as u8,
-            (addr >> 24) as u8,
-        ];
-        self.dfu_dnload(0, &erase_cmd)
+        self.dfu_dnload(0, &encode_page_erase(addr))
     }
 
     pub fn dfuse_leave(&self, addr: u32) -> Result<(), DfuError> {
         self.dfuse_set_address(addr)?;
-        self.dfu_dnload(0, &[])
+        self.dfu_dnload(0, &[]).or_else(ignore_reset)
     }
 
     pub fn dfuse_set_address(&self, addr: u32) -> Result<(), DfuError> {
-        let addr_cmd: Vec<u8> = vec![
-            DFUSE_CMD_ADDR,
-            addr as u8,
-            (addr >> 8) as u8,
-            (addr >> 16) as u8,
-            (addr >> 24) as u8,
-        ];
-        self.dfu_dnload(0, &addr_cmd)
+        self.set_address(addr)
     }
 
     fn dfu_dnload(
@@ -180,8 +598,19 @@ impl DfuConnection {
         transaction: u16,
         data: &[u8],
     ) -> Result<(), DfuError> {
-        self.dfu_cmd_out(DFU_CMD_DOWNLOAD, transaction, data)?;
-        self.poll_until_idle()
+        // a zero-length download terminates the transfer
+        self.transfer_pending
+            .store(!data.is_empty(), Ordering::Relaxed);
+        let result = self
+            .dfu_cmd_out(DFU_CMD_DOWNLOAD, transaction, data)
+            .and_then(|_| self.poll_until_idle());
+        // disconnecting is only expected when terminating the transfer
+        match result {
+            Err(DfuError::Disconnected) if !data.is_empty() => {
+                Err(DfuError::LeftDfuMode)
+            }
+            result => result,
+        }
     }
 
     fn dfu_upload(
@@ -189,20 +618,36 @@ impl DfuConnection {
         transaction: u16,
         length: u16,
     ) -> Result<Vec<u8>, DfuError> {
+        self.transfer_pending.store(true, Ordering::Relaxed);
         self.dfu_cmd_in(DFU_CMD_UPLOAD, transaction, length)
+            .map_err(|err| match err {
+                DfuError::Disconnected => DfuError::LeftDfuMode,
+                err => err,
+            })
     }
 
     fn poll_until_idle(&self) -> Result<(), DfuError> {
         let start = Instant::now();
-        loop {
+        let mut max_duration = self.timeout * POLL_DEADLINE_FACTOR;
+        for _ in 0..POLL_MAX_ITERATIONS {
+            let elapsed = start.elapsed();
+            if elapsed >= max_duration {
+                return Err(DfuError::Timeout);
+            }
             match self.get_status() {
                 Ok(st) => {
+                    if let Some(on_poll) = &self.on_poll {
+                        on_poll(&st, start.elapsed());
+                    }
                     if st.state != DFU_STATE_DFU_DOWNLOAD_BUSY {
                         return st.ok();
                     }
-                    if start.elapsed() >= DEFAULT_TIMEOUT * 20 {
-                        return Err(DfuError::Timeout);
-                    }
+                    // slow memories (ex: external SPI flash) report how long
+                    // the host should wait before polling again
+                    let poll_timeout =
+                        Duration::from_millis(st.poll_timeout as u64);
+                    max_duration = poll_deadline(max_duration, poll_timeout);
+                    thread::sleep(poll_timeout.min(max_duration - elapsed));
                 }
                 Err(DfuError::Transfer(
                     nusb::transfer::TransferError::Cancelled,
@@ -217,6 +662,7 @@ impl DfuConnection {
                 }
             }
         }
+        Err(DfuError::Timeout)
     }
 
     fn dfu_cmd_out(
@@ -225,21 +671,8 @@ impl DfuConnection {
         value: u16,
         data: &[u8],
     ) -> Result<(), DfuError> {
-        let index = self.interface.interface_number() as u16;
-        Ok(self
-            .interface
-            .control_out(
-                ControlOut {
-                    control_type: ControlType::Class,
-                    recipient: Recipient::Interface,
-                    request: req,
-                    value,
-                    index,
-                    data,
-                },
-                DEFAULT_TIMEOUT,
-            )
-            .wait()?)
+        self.clear_halt_if_pending()?;
+        self.transport.control_out(req, value, data, self.timeout)
     }
 
     fn dfu_cmd_in(
@@ -248,20 +681,795 @@ impl DfuConnection {
         value: u16,
         length: u16,
     ) -> Result<Vec<u8>, DfuError> {
-        let index = self.interface.interface_number() as u16;
-        Ok(self
-            .interface
-            .control_in(
-                ControlIn {
-                    control_type: ControlType::Class,
-                    recipient: Recipient::Interface,
-                    request: req,
+        self.clear_halt_if_pending()?;
+        self.transport.control_in(req, value, length, self.timeout)
+    }
+
+    fn clear_halt_if_pending(&self) -> Result<(), DfuError> {
+        if self.clear_halt.load(Ordering::Relaxed) {
+            self.clear_halt()?;
+        }
+        Ok(())
+    }
+}
+
+/// A transfer left unterminated (ex: a download dropped before `leave`)
+/// is aborted, so that the device does not stay in `dfuDNLOAD-IDLE` or
+/// `dfuUPLOAD-IDLE`. Errors are ignored.
+impl Drop for DfuConnection {
+    fn drop(&mut self) {
+        if self.transfer_pending.load(Ordering::Relaxed) {
+            log::debug!("Aborting unterminated transfer");
+            let _ = self.abort();
+        }
+    }
+}
+
+impl Transport {
+    fn reset(&self) -> Result<(), DfuError> {
+        match self {
+            Transport::Usb(device, _) => Ok(device.reset().wait()?),
+            #[cfg(any(test, feature = "recording"))]
+            Transport::Recording(recorder) => {
+                recorder.reset();
+                Ok(())
+            }
+        }
+    }
+
+    fn clear_halt(&self, timeout: Duration) -> Result<(), DfuError> {
+        match self {
+            Transport::Usb(_, interface) => Ok(interface
+                .control_out(
+                    ControlOut {
+                        control_type: ControlType::Standard,
+                        recipient: Recipient::Endpoint,
+                        request: USB_REQ_CLEAR_FEATURE,
+                        value: USB_FEATURE_ENDPOINT_HALT,
+                        index: 0,
+                        data: &[],
+                    },
+                    timeout,
+                )
+                .wait()?),
+            #[cfg(any(test, feature = "recording"))]
+            Transport::Recording(recorder) => {
+                recorder.clear_halt(0);
+                Ok(())
+            }
+        }
+    }
+
+    fn control_out(
+        &self,
+        req: u8,
+        value: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> Result<(), DfuError> {
+        match self {
+            Transport::Usb(_, interface) => {
+                let index = interface.interface_number() as u16;
+                Ok(interface
+                    .control_out(
+                        ControlOut {
+                            control_type: ControlType::Class,
+                            recipient: Recipient::Interface,
+                            request: req,
+                            value,
+                            index,
+                            data,
+                        },
+                        timeout,
+                    )
+                    .wait()?)
+            }
+            #[cfg(any(test, feature = "recording"))]
+            Transport::Recording(recorder) => {
+                recorder.control_out(req, value, data)
+            }
+        }
+    }
+
+    fn control_in(
+        &self,
+        req: u8,
+        value: u16,
+        length: u16,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, DfuError> {
+        match self {
+            Transport::Usb(_, interface) => {
+                let index = interface.interface_number() as u16;
+                Ok(interface
+                    .control_in(
+                        ControlIn {
+                            control_type: ControlType::Class,
+                            recipient: Recipient::Interface,
+                            request: req,
+                            value,
+                            index,
+                            length,
+                        },
+                        timeout,
+                    )
+                    .wait()?)
+            }
+            #[cfg(any(test, feature = "recording"))]
+            Transport::Recording(recorder) => {
+                recorder.control_in(req, value, length)
+            }
+        }
+    }
+}
+
+// Honoring the device's back-off must not cause a timeout by itself
+fn poll_deadline(max_duration: Duration, poll_timeout: Duration) -> Duration {
+    max_duration.max(poll_timeout * POLL_TIMEOUT_DEADLINE_FACTOR)
+}
+
+/// DfuSe command followed by its address argument (little-endian,
+/// regardless of the host's endianness)
+fn encode_dfuse_command(cmd: u8, addr: u32) -> [u8; 5] {
+    let [a0, a1, a2, a3] = addr.to_le_bytes();
+    [cmd, a0, a1, a2, a3]
+}
+
+fn encode_set_address(addr: u32) -> [u8; 5] {
+    encode_dfuse_command(DFUSE_CMD_ADDR, addr)
+}
+
+fn encode_page_erase(addr: u32) -> [u8; 5] {
+    encode_dfuse_command(DFUSE_CMD_ERASE, addr)
+}
+
+// The device is expected to go away when leaving DFU mode
+fn ignore_disconnect(err: DfuError) -> Result<(), DfuError> {
+    match err {
+        DfuError::Disconnected => Ok(()),
+        err => Err(err),
+    }
+}
+
+// `len` bytes from `addr` must fit in the address space
+fn check_range(addr: u32, len: usize) -> Result<(), DfuError> {
+    let fits = len == 0
+        || u32::try_from(len - 1).is_ok_and(|n| addr.checked_add(n).is_some());
+    if fits {
+        Ok(())
+    } else {
+        Err(DfuError::InvalidRange {
+            start: addr,
+            length: len as u64,
+        })
+    }
+}
+
+// Once the transfer is terminated, the device may reset without
+// answering (ex: stall, fault), or answer from runtime mode
+fn ignore_reset(err: DfuError) -> Result<(), DfuError> {
+    use nusb::transfer::TransferError;
+
+    match err {
+        DfuError::Disconnected
+        | DfuError::LeftDfuMode
+        | DfuError::Usb(_)
+        | DfuError::Transfer(
+            TransferError::Stall
+            | TransferError::Fault
+            | TransferError::Unknown(_),
+        ) => Ok(()),
+        err => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, atomic::AtomicUsize};
+
+    use super::*;
+
+    fn getstatus() -> ControlTransfer {
+        ControlTransfer::In {
+            request: DFU_CMD_GETSTATUS,
+            value: 0,
+            length: DFU_STATE_LEN,
+        }
+    }
+
+    fn abort() -> ControlTransfer {
+        ControlTransfer::Out {
+            request: DFU_CMD_ABORT,
+            value: 0,
+            data: vec![],
+        }
+    }
+
+    fn dnload(value: u16, data: &[u8]) -> ControlTransfer {
+        ControlTransfer::Out {
+            request: DFU_CMD_DOWNLOAD,
+            value,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_encode_dfuse_commands() {
+        assert_eq!(
+            encode_set_address(0x0800_4000),
+            [0x21, 0x00, 0x40, 0x00, 0x08]
+        );
+        assert_eq!(
+            encode_set_address(0x1234_5678),
+            [0x21, 0x78, 0x56, 0x34, 0x12]
+        );
+        assert_eq!(
+            encode_page_erase(0x9001_0000),
+            [0x41, 0x00, 0x00, 0x01, 0x90]
+        );
+    }
+
+    #[test]
+    fn test_runtime_mode_status() {
+        let st = DfuStatus::from_raw(&[0, 0, 0, 0, 0x00, 0]).unwrap();
+        assert!(st.is_runtime_mode());
+        assert!(matches!(st.ok(), Err(DfuError::LeftDfuMode)));
+
+        // errors are reported first
+        let st = DfuStatus::from_raw(&[0x0a, 0, 0, 0, 0x01, 0]).unwrap();
+        assert!(matches!(st.ok(), Err(DfuError::Status(0x0a))));
+
+        let st = DfuStatus::from_raw(&[0, 0, 0, 0, 0x05, 0]).unwrap();
+        assert!(!st.is_runtime_mode());
+        assert!(st.ok().is_ok());
+    }
+
+    #[test]
+    fn test_dfu_state() {
+        let st = DfuStatus::from_raw(&[0, 0, 0, 0, 0x02, 0]).unwrap();
+        assert_eq!(st.state(), DfuState::DfuIdle);
+        assert_eq!(st.state, 0x02);
+        assert_eq!(DfuState::from_u8(0x04), DfuState::DownloadBusy);
+        assert_eq!(DfuState::from_u8(0x0a), DfuState::Error);
+        assert_eq!(DfuState::from_u8(0x0b), DfuState::Unknown(0x0b));
+        assert_eq!(
+            DfuState::from_u8(0x08).to_string(),
+            "dfuMANIFEST-WAIT-RESET"
+        );
+        assert_eq!(DfuState::Unknown(0x0b).to_string(), "unknown (0x0b)");
+    }
+
+    #[test]
+    fn test_write_drop_leave() {
+        let recorder = Arc::new(Recorder::default());
+
+        // completed download: the device is left in dfuDNLOAD-IDLE
+        let connection = DfuConnection::recording_on(recorder.clone());
+        connection.download(0x08000000, &[1, 2, 3, 4]).unwrap();
+        connection.complete_download();
+        drop(connection);
+        DfuConnection::recording_on(recorder.clone())
+            .leave()
+            .unwrap();
+        assert_eq!(
+            recorder.transfers(),
+            vec![
+                dnload(0, &[DFUSE_CMD_ADDR, 0x00, 0x00, 0x00, 0x08]),
+                getstatus(),
+                dnload(2, &[1, 2, 3, 4]),
+                getstatus(),
+                dnload(0, &[]),
+                getstatus(),
+            ]
+        );
+
+        // interrupted download: aborted on drop
+        let recorder = Arc::new(Recorder::default());
+        let connection = DfuConnection::recording_on(recorder.clone());
+        connection.download(0x08000000, &[1, 2, 3, 4]).unwrap();
+        drop(connection);
+        assert_eq!(recorder.transfers().last(), Some(&abort()));
+    }
+
+    #[test]
+    fn test_leave_reset() {
+        use nusb::transfer::TransferError;
+
+        // reset while answering the zero-length download
+        let recorder = Arc::new(Recorder::default());
+        let connection = DfuConnection::recording_on(recorder.clone());
+        connection.download(0x08000000, &[1, 2, 3, 4]).unwrap();
+        recorder.fail_next(DFU_CMD_DOWNLOAD, TransferError::Stall);
+        connection.leave().unwrap();
+
+        // back in appIDLE after manifesting
+        let recorder = Arc::new(Recorder::default());
+        recorder.set_manifest_state(DFU_STATE_APP_IDLE);
+        let connection = DfuConnection::recording_on(recorder.clone());
+        connection.download(0x08000000, &[1, 2, 3, 4]).unwrap();
+        connection.leave().unwrap();
+        assert_eq!(recorder.transfers().last(), Some(&getstatus()));
+
+        // other errors are reported
+        let recorder = Arc::new(Recorder::default());
+        let connection = DfuConnection::recording_on(recorder.clone());
+        recorder.fail_next(DFU_CMD_DOWNLOAD, TransferError::InvalidArgument);
+        assert!(connection.leave().is_err());
+    }
+
+    #[test]
+    fn test_pending_transfer() {
+        let connection = DfuConnection::recording();
+        assert!(!connection.transfer_pending.load(Ordering::Relaxed));
+        connection.download(0x08000000, &[0; 16]).unwrap();
+        assert!(connection.transfer_pending.load(Ordering::Relaxed));
+        connection.leave().unwrap();
+        assert!(!connection.transfer_pending.load(Ordering::Relaxed));
+        connection.upload(0, 16).unwrap();
+        assert!(connection.transfer_pending.load(Ordering::Relaxed));
+        connection.abort().unwrap();
+        assert!(!connection.transfer_pending.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut connection = DfuConnection::recording();
+        assert_eq!(connection.timeout(), DEFAULT_TIMEOUT);
+        connection.set_timeout(Duration::from_secs(30));
+        assert_eq!(connection.timeout(), Duration::from_secs(30));
+        let connection = connection.with_timeout(Duration::from_millis(500));
+        assert_eq!(connection.timeout(), Duration::from_millis(500));
+        let connection = connection.with_timeout(Duration::ZERO);
+        assert_eq!(connection.timeout(), MIN_TIMEOUT);
+    }
+
+    #[test]
+    fn test_poll_deadline() {
+        let base = Duration::from_secs(100);
+        assert_eq!(poll_deadline(base, Duration::from_secs(8)), base);
+        assert_eq!(
+            poll_deadline(base, Duration::from_secs(60)),
+            Duration::from_secs(240)
+        );
+    }
+
+    #[test]
+    fn test_poll_timeout() {
+        let recorder = Arc::new(Recorder::default());
+        recorder.set_state(DFU_STATE_DFU_DOWNLOAD_BUSY);
+        let connection = DfuConnection::recording_on(recorder)
+            .with_timeout(Duration::from_millis(1));
+        let start = Instant::now();
+        assert!(matches!(
+            connection.poll_until_idle(),
+            Err(DfuError::Timeout)
+        ));
+        // 20 times the control transfer timeout, not the default one
+        assert!(start.elapsed() < DEFAULT_TIMEOUT);
+    }
+
+    #[test]
+    fn test_status_from_raw() {
+        let st = DfuStatus::from_raw(&[0, 0x10, 0x27, 0, 0x04, 0]).unwrap();
+        assert_eq!(st.status, 0);
+        assert_eq!(st.poll_timeout, 10000);
+        assert_eq!(st.state, DFU_STATE_DFU_DOWNLOAD_BUSY);
+        assert_eq!(st.status_name(), Some("OK"));
+
+        let st = DfuStatus::from_raw(&[0x04, 0, 0, 0, 0x0a, 0]).unwrap();
+        assert_eq!(st.status_name(), Some("errERASE"));
+        assert_eq!(
+            st.status_description(),
+            Some("memory erase function failed")
+        );
+        let st = DfuStatus::from_raw(&[0x42, 0, 0, 0, 0x0a, 0]).unwrap();
+        assert_eq!(st.status_name(), None);
+
+        assert!(matches!(
+            DfuStatus::from_raw(&[0, 0, 0, 0]),
+            Err(DfuError::ShortStatus(4))
+        ));
+        assert!(matches!(
+            DfuStatus::from_raw(&[]),
+            Err(DfuError::ShortStatus(0))
+        ));
+    }
+
+    #[test]
+    fn test_download_sequence() {
+        let connection = DfuConnection::recording();
+        connection.download(0x08000000, &[1, 2, 3, 4]).ok().unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                dnload(0, &[DFUSE_CMD_ADDR, 0x00, 0x00, 0x00, 0x08]),
+                getstatus(),
+                dnload(2, &[1, 2, 3, 4]),
+                getstatus(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_chunk_download_sequence() {
+        let connection = DfuConnection::recording().with_transfer_size(256);
+        let data = [0xaa; 600];
+        let mut addr = 0x08000000;
+        for chunk in data.chunks(connection.transfer_size() as usize) {
+            connection.download(addr, chunk).unwrap();
+            addr += chunk.len() as u32;
+        }
+
+        let mut expected = Vec::new();
+        for (addr, len) in
+            [(0x08000000u32, 256), (0x08000100, 256), (0x08000200, 88)]
+        {
+            let mut set_address = vec![DFUSE_CMD_ADDR];
+            set_address.extend_from_slice(&addr.to_le_bytes());
+            expected.extend([
+                dnload(0, &set_address),
+                getstatus(),
+                dnload(2, &data[..len]),
+                getstatus(),
+            ]);
+        }
+        assert_eq!(connection.recorded_transfers(), expected);
+    }
+
+    #[test]
+    fn test_download_with_progress() {
+        let connection = DfuConnection::recording().with_transfer_size(256);
+        let data = [0x55; 600];
+        let mut calls = Vec::new();
+        connection
+            .download_with_progress(0x08000000, &data, &mut |done, total| {
+                calls.push((done, total))
+            })
+            .unwrap();
+        assert_eq!(calls, vec![(256, 600), (512, 600), (600, 600)]);
+
+        let transfers = connection.recorded_transfers();
+        assert_eq!(transfers.len(), 12);
+        assert_eq!(
+            transfers[8],
+            dnload(0, &[DFUSE_CMD_ADDR, 0x00, 0x02, 0x00, 0x08])
+        );
+        assert_eq!(transfers[10], dnload(2, &data[512..]));
+
+        // no chunk, no call
+        connection
+            .download_with_progress(0x08000000, &[], &mut |_, _| {
+                panic!("unexpected progress")
+            })
+            .unwrap();
+
+        // nothing is sent past the end of the address space
+        let connection = DfuConnection::recording().with_transfer_size(256);
+        assert!(matches!(
+            connection.download_with_progress(
+                0xffffff00,
+                &data,
+                &mut |_, _| {}
+            ),
+            Err(DfuError::InvalidRange {
+                start: 0xffffff00,
+                length: 600
+            })
+        ));
+        assert!(connection.recorded_transfers().is_empty());
+        connection
+            .download_with_progress(0xffffff00, &data[..256], &mut |_, _| {})
+            .unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        let connection = DfuConnection::recording().with_transfer_size(256);
+        let mut calls = Vec::new();
+        connection
+            .verify_with_progress(0x08000000, &[0; 600], &mut |done, total| {
+                calls.push((done, total))
+            })
+            .unwrap();
+        assert_eq!(calls, vec![(256, 600), (512, 600), (600, 600)]);
+
+        // idle: the address is set right after GETSTATE
+        let transfers = connection.recorded_transfers();
+        assert_eq!(
+            transfers[1],
+            dnload(0, &[DFUSE_CMD_ADDR, 0x00, 0x00, 0x00, 0x08])
+        );
+        let uploads: Vec<(u16, u16)> = transfers
+            .iter()
+            .filter_map(|transfer| match transfer {
+                ControlTransfer::In {
+                    request: DFU_CMD_UPLOAD,
                     value,
-                    index,
                     length,
+                } => Some((*value, *length)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(uploads, vec![(2, 256), (3, 256), (4, 88)]);
+        assert_eq!(transfers.last(), Some(&abort()));
+
+        // the recorder reads back zeroes
+        let mut expected = [0; 600];
+        expected[300] = 0xff;
+        assert!(matches!(
+            connection.verify(0x08000000, &expected),
+            Err(DfuError::VerifyMismatch { addr: 0x0800012c })
+        ));
+
+        // from dfuUPLOAD-IDLE (ex: after a read)
+        connection.read_block(2, 16).unwrap();
+        connection.verify(0x08000000, &[0; 16]).unwrap();
+    }
+
+    #[test]
+    fn test_verify_many_blocks() {
+        let connection = DfuConnection::recording().with_transfer_size(1);
+        connection.verify(0x08000000, &[0; 65536]).unwrap();
+        // the address pointer moves forward before block numbers wrap
+        let transfers = connection.recorded_transfers();
+        let set_addr = dnload(0, &[DFUSE_CMD_ADDR, 0xfe, 0xff, 0x00, 0x08]);
+        let pos = transfers.iter().position(|t| *t == set_addr).unwrap();
+        let uploads = |transfers: &[ControlTransfer]| -> Vec<u16> {
+            transfers
+                .iter()
+                .filter_map(|transfer| match transfer {
+                    ControlTransfer::In {
+                        request: DFU_CMD_UPLOAD,
+                        value,
+                        ..
+                    } => Some(*value),
+                    _ => None,
+                })
+                .collect()
+        };
+        let before = uploads(&transfers[..pos]);
+        assert_eq!((before.len(), before.last()), (65534, Some(&u16::MAX)));
+        assert_eq!(uploads(&transfers[pos..]), [2, 3]);
+    }
+
+    #[test]
+    fn test_download_transfer_size() {
+        let connection = DfuConnection::recording().with_transfer_size(256);
+        assert!(connection.download(0x08000000, &[0; 256]).is_ok());
+        assert!(matches!(
+            connection.download(0x08000000, &[0; 257]),
+            Err(DfuError::TransferTooLarge {
+                size: 257,
+                max: 256
+            })
+        ));
+        // nothing sent for the oversized transfer
+        assert_eq!(connection.recorded_transfers().len(), 4);
+    }
+
+    #[test]
+    fn test_erase_sequence() {
+        let connection = DfuConnection::recording();
+        connection.dfuse_page_erase(0x08004000).ok().unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                dnload(0, &[DFUSE_CMD_ERASE, 0x00, 0x40, 0x00, 0x08]),
+                getstatus(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_poll_observer() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let connection = DfuConnection::recording().with_poll_observer({
+            let polls = polls.clone();
+            move |st, _| {
+                assert_eq!(st.state, DFU_STATE_DFU_DOWNLOAD_IDLE);
+                polls.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        connection.dfuse_page_erase(0x08004000).unwrap();
+        assert_eq!(polls.load(Ordering::Relaxed), 1);
+
+        // plain status requests are not polls
+        connection.get_status().unwrap();
+        assert_eq!(polls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_clear_halt_before_first_transfer() {
+        let connection = DfuConnection::recording().with_clear_halt(true);
+        connection.get_status().unwrap();
+        connection.get_status().unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                ControlTransfer::ClearHalt { endpoint: 0 },
+                getstatus(),
+                getstatus(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_primitives() {
+        let connection = DfuConnection::recording();
+        connection.set_address(0x08000000).unwrap();
+        connection.write_block(3, &[1, 2]).unwrap();
+        connection.abort().unwrap();
+        connection.read_block(5, 16).unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                dnload(0, &[DFUSE_CMD_ADDR, 0x00, 0x00, 0x00, 0x08]),
+                getstatus(),
+                dnload(3, &[1, 2]),
+                getstatus(),
+                abort(),
+                ControlTransfer::In {
+                    request: DFU_CMD_UPLOAD,
+                    value: 5,
+                    length: 16,
                 },
-                DEFAULT_TIMEOUT,
-            )
-            .wait()?)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_state() {
+        let connection = DfuConnection::recording();
+        assert_eq!(connection.get_state().unwrap(), DFU_STATE_DFU_IDLE);
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![ControlTransfer::In {
+                request: DFU_CMD_GETSTATE,
+                value: 0,
+                length: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reset_state_idle() {
+        let connection = DfuConnection::recording();
+        connection.transfer_pending.store(true, Ordering::Relaxed);
+        connection.reset_state().unwrap();
+        // idle according to GETSTATE: neither GETSTATUS nor ABORT
+        assert_eq!(
+            connection.recorded_transfers(),
+            [ControlTransfer::In {
+                request: DFU_CMD_GETSTATE,
+                value: 0,
+                length: 1,
+            }]
+        );
+        // not aborted on drop either
+        assert!(!connection.transfer_pending.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_reset_state() {
+        let getstate = ControlTransfer::In {
+            request: DFU_CMD_GETSTATE,
+            value: 0,
+            length: 1,
+        };
+
+        // dfuUPLOAD-IDLE: aborted
+        let connection = DfuConnection::recording();
+        connection.read_block(2, 16).unwrap();
+        connection.reset_state().unwrap();
+        assert_eq!(
+            connection.recorded_transfers()[1..],
+            [getstate.clone(), getstatus(), abort(), getstatus()]
+        );
+
+        // dfuERROR after a download stalled in dfuUPLOAD-IDLE: cleared
+        let connection = DfuConnection::recording();
+        connection.read_block(2, 16).unwrap();
+        assert!(connection.set_address(0x08000000).is_err());
+        connection.reset_state().unwrap();
+        let clrstatus = ControlTransfer::Out {
+            request: DFU_CMD_CLRSTATUS,
+            value: 0,
+            data: vec![],
+        };
+        assert_eq!(
+            connection.recorded_transfers()[2..],
+            [getstate, getstatus(), clrstatus, getstatus()]
+        );
+    }
+
+    #[test]
+    fn test_detach() {
+        let detach = ControlTransfer::Out {
+            request: DFU_CMD_DETACH,
+            value: 0xff,
+            data: vec![],
+        };
+
+        // device detaching by itself: no reset
+        let connection = DfuConnection::recording();
+        let desc = DfuDescriptor::new(&[9, 0x21, 0x0b, 0xff, 0, 0, 8, 0x1a, 1]);
+        connection.detach(&desc).unwrap();
+        assert_eq!(connection.recorded_transfers(), vec![detach.clone()]);
+
+        // the host must reset the device
+        let connection = DfuConnection::recording();
+        let desc = DfuDescriptor::new(&[9, 0x21, 0x03, 0xff, 0, 0, 8, 0x1a, 1]);
+        connection.detach(&desc).unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![detach, ControlTransfer::UsbReset]
+        );
+    }
+
+    #[test]
+    fn test_dfuse_get_commands() {
+        let connection = DfuConnection::recording().with_transfer_size(4);
+        let commands = connection.dfuse_get_commands().unwrap();
+        assert_eq!(commands, vec![DfuseCommand::GetCommands; 4]);
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                ControlTransfer::In {
+                    request: DFU_CMD_UPLOAD,
+                    value: 0,
+                    length: 4,
+                },
+                abort(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_reboot_tag_sequence() {
+        let connection = DfuConnection::recording();
+        connection.write_reboot_tag(0x2001fff0, b"BDFU").unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                dnload(0, &[DFUSE_CMD_ADDR, 0xf0, 0xff, 0x01, 0x20]),
+                getstatus(),
+                dnload(2, b"BDFU"),
+                getstatus(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reboot_sequence() {
+        let connection = DfuConnection::recording();
+        connection
+            .reboot(0x2001fff0, b"BDFU", 0x08000000)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            connection.recorded_transfers(),
+            vec![
+                dnload(0, &[DFUSE_CMD_ADDR, 0xf0, 0xff, 0x01, 0x20]),
+                getstatus(),
+                dnload(2, b"BDFU"),
+                getstatus(),
+                dnload(0, &[DFUSE_CMD_ADDR, 0x00, 0x00, 0x00, 0x08]),
+                getstatus(),
+                dnload(0, &[]),
+                getstatus(),
+            ]
+        );
+
+        // the bootloader jumps right away and reports appDETACH
+        let recorder = Arc::new(Recorder::default());
+        recorder.set_manifest_state(DFU_STATE_APP_DETACH);
+        DfuConnection::recording_on(recorder.clone())
+            .reboot(0x2001fff0, b"BDFU", 0x08000000)
+            .unwrap();
+        let recorder = Arc::new(Recorder::default());
+        recorder.set_manifest_state(DFU_STATE_APP_IDLE);
+        DfuConnection::recording_on(recorder.clone())
+            .dfuse_leave(0x08000000)
+            .unwrap();
     }
 }
@@ -3,6 +3,18 @@ pub(crate) const DFU_DESC_LEN: usize = 9;
 
 pub const DFUSE_VERSION_NUMBER: u16 = 0x11A;
 
+pub(crate) fn is_dfu_descriptor(desc: &nusb::descriptors::Descriptor) -> bool {
+    desc.descriptor_len() == DFU_DESC_LEN
+        && desc.descriptor_type() == DFU_DESC_TYPE
+}
+
+/// DfuSe devices should report version 1.1a, but some STM32 bootloaders
+/// report 1.1 or 1.0 while still supporting the DfuSe extensions. These are
+/// detected by their `@`-prefixed memory layout strings.
+pub(crate) fn is_dfuse(dfu_version: u16, has_dfuse_layout: bool) -> bool {
+    dfu_version == DFUSE_VERSION_NUMBER || has_dfuse_layout
+}
+
 /// DFU functional descriptor
 ///
 /// Represents the DFU functional descriptor as described in section 4.1.3.
@@ -30,6 +42,17 @@ impl DfuDescriptor {
         }
     }
 
+    /// Decode `desc` if it is a DFU functional descriptor
+    pub fn parse(desc: &nusb::descriptors::Descriptor) -> Option<Self> {
+        is_dfu_descriptor(desc).then(|| Self::new(desc))
+    }
+
+    /// Raw attributes bitmap (`bmAttributes`)
+    #[doc(alias = "bmAttributes")]
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+
     /// Download capable (`bitCanDnload`)
     #[doc(alias = "bitCanDnload")]
     pub fn can_download(&self) -> bool {
@@ -81,3 +104,63 @@ impl DfuDescriptor {
         self.dfu_version
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nusb::descriptors::Descriptor;
+
+    use super::*;
+
+    #[test]
+    fn test_is_dfuse() {
+        assert!(is_dfuse(DFUSE_VERSION_NUMBER, true));
+        assert!(is_dfuse(DFUSE_VERSION_NUMBER, false));
+        assert!(is_dfuse(0x0110, true));
+        assert!(is_dfuse(0x0100, true));
+        assert!(!is_dfuse(0x0110, false));
+        assert!(!is_dfuse(0x0100, false));
+        assert!(!is_dfuse(0x0000, false));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let desc = DfuDescriptor::new(&[
+            9, 0x21, 0x0b, 0xff, 0x00, 0x00, 0x08, 0x1a, 0x01,
+        ]);
+        assert_eq!(desc.attributes(), 0x0b);
+        assert!(desc.can_download());
+        assert!(desc.can_upload());
+        assert!(!desc.manifestation_tolerant());
+        assert!(desc.will_detach());
+        assert_eq!(desc.detach_timeout(), 0xff);
+        assert_eq!(desc.transfer_size(), 2048);
+        assert_eq!(desc.dfu_version(), DFUSE_VERSION_NUMBER);
+
+        for attributes in 0..16u8 {
+            let desc =
+                DfuDescriptor::new(&[9, 0x21, attributes, 0, 0, 0, 0, 0, 0]);
+            assert_eq!(desc.can_download(), attributes & 1 != 0);
+            assert_eq!(desc.can_upload(), attributes & 2 != 0);
+            assert_eq!(desc.manifestation_tolerant(), attributes & 4 != 0);
+            assert_eq!(desc.will_detach(), attributes & 8 != 0);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let raw = [9, 0x21, 0x0b, 0xff, 0x00, 0x00, 0x08, 0x1a, 0x01];
+        let desc = Descriptor::new(&raw).unwrap();
+        let dfu_desc = DfuDescriptor::parse(&desc).unwrap();
+        assert_eq!(dfu_desc.transfer_size(), 2048);
+
+        // interface descriptor
+        let raw = [9, 0x04, 0, 0, 0, 0xfe, 0x01, 0x02, 0x04];
+        let desc = Descriptor::new(&raw).unwrap();
+        assert!(DfuDescriptor::parse(&desc).is_none());
+
+        // DFU type, but truncated
+        let raw = [7, 0x21, 0x0b, 0xff, 0x00, 0x00, 0x08];
+        let desc = Descriptor::new(&raw).unwrap();
+        assert!(DfuDescriptor::parse(&desc).is_none());
+    }
+}
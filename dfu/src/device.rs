@@ -1,54 +1,135 @@
+use std::{
+    hash::{Hash, Hasher},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use nonempty::NonEmpty;
 use nusb::{self, MaybeFuture};
 
-use crate::{DfuConnection, DfuError, descriptor::*, interface::*};
+use crate::{
+    DfuConnection, DfuError, DfuMemSegment, FlashRates, UID_LEN,
+    descriptor::*,
+    interface::*,
+    memory::{flash_size, main_flash_start_address},
+    uid::uid_address,
+};
+
+/// Capabilities of a device (see [DfuDevice::summary()])
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceSummary {
+    /// DFU interfaces and alternate settings combined
+    pub interfaces: usize,
+    /// Some segment is readable / writable / erasable
+    pub readable: bool,
+    pub writable: bool,
+    pub erasable: bool,
+    /// Total size of the flash memories (layouts named like "... Flash")
+    pub flash_size: u64,
+    pub dfuse: bool,
+}
 
-const DFU_CLASS: u8 = 0xFE;
-const DFU_SUBCLASS: u8 = 0x1;
+pub(crate) const DFU_CLASS: u8 = 0xFE;
+pub(crate) const DFU_SUBCLASS: u8 = 0x1;
+pub(crate) const DFU_PROTOCOL_RUNTIME: u8 = 0x1;
 
 /// DFU device representation
 pub struct DfuDevice {
     dev: nusb::DeviceInfo,
     interfaces: Vec<DfuInterface>,
+    unparsed_interfaces: Vec<(u8, u8, String)>,
+    segment_index: SegmentIndex,
+}
+
+/// Identifies a device by the physical port it is connected to
+///
+/// Unlike [nusb::DeviceId], the key remains the same when the device
+/// re-enumerates (ex: after rebooting into a new bootloader).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeviceKey {
+    vendor_id: u16,
+    product_id: u16,
+    bus_id: String,
+    port_chain: Vec<u8>,
 }
 
 impl DfuDevice {
-    fn from_device_info(
+    /// Build a DFU device from an already enumerated USB device
+    ///
+    /// Returns `None` if the device does not expose any DFU mode
+    /// interface. Interfaces whose memory layout cannot be parsed are
+    /// listed by [DfuDevice::unparsed_interfaces()], the device being kept
+    /// even if none can be parsed. Unlike [find_dfu_devices()], no vendor /
+    /// product ID filter is applied.
+    pub fn try_from_device_info(
         device: nusb::DeviceInfo,
     ) -> Result<Option<Self>, DfuError> {
         let open_dev: nusb::Device = device.open().wait()?;
-        let dfu_interfaces: Vec<DfuInterface> = open_dev
-            .configurations()
-            .flat_map(|configuration| {
-                let open_dev = open_dev.clone();
-                let config = configuration.configuration_value();
-                configuration.interface_alt_settings().filter_map(
-                    move |alt_setting| {
-                        if alt_setting.class() != DFU_CLASS
-                            || alt_setting.subclass() != DFU_SUBCLASS
-                        {
-                            None
-                        } else {
-                            DfuInterface::new(
-                                &open_dev,
-                                config,
-                                alt_setting.interface_number(),
-                                alt_setting.alternate_setting(),
-                                alt_setting.string_index()?,
-                            )
-                        }
-                    },
-                )
-            })
-            .collect();
+        let mut dfu_interfaces = Vec::new();
+        let mut unparsed_interfaces = Vec::new();
+        for configuration in open_dev.configurations() {
+            let config = configuration.configuration_value();
+            for alt_setting in configuration.interface_alt_settings() {
+                if alt_setting.class() != DFU_CLASS
+                    || alt_setting.subclass() != DFU_SUBCLASS
+                {
+                    continue;
+                }
+                let Some(intf_str) =
+                    alt_setting.string_index().and_then(|idx| {
+                        get_string_descriptor(
+                            &open_dev,
+                            idx,
+                            crate::DEFAULT_TIMEOUT,
+                        )
+                    })
+                else {
+                    continue;
+                };
+                let interface = alt_setting.interface_number();
+                let alt = alt_setting.alternate_setting();
+                match DfuInterface::new(config, interface, alt, &intf_str) {
+                    Some(intf) => dfu_interfaces.push(intf),
+                    // handled by `DfuRuntimeDevice`
+                    None if alt_setting.protocol() == DFU_PROTOCOL_RUNTIME => {}
+                    None => {
+                        log::debug!(
+                            "Cannot parse memory layout of interface \
+                            {interface}, alt {alt}: {intf_str}"
+                        );
+                        unparsed_interfaces.push((interface, alt, intf_str));
+                    }
+                }
+            }
+        }
 
-        if dfu_interfaces.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(DfuDevice {
-                dev: device,
-                interfaces: dfu_interfaces,
-            }))
+        if dfu_interfaces.is_empty() && unparsed_interfaces.is_empty() {
+            return Ok(None);
         }
+        // overlapping interfaces are reported by `overlapping_interfaces()`
+        Ok(Some(DfuDevice {
+            dev: device,
+            segment_index: SegmentIndex::new(&dfu_interfaces),
+            interfaces: dfu_interfaces,
+            unparsed_interfaces,
+        }))
+    }
+
+    /// Same as [DfuDevice::try_from_device_info()], fails with
+    /// [DfuError::Timeout] if the device does not answer in time
+    ///
+    /// Opening a wedged device may block forever: the device is opened
+    /// in a separate thread which is left behind on timeout.
+    pub fn open_with_timeout(
+        device: nusb::DeviceInfo,
+    ) -> Result<Option<Self>, DfuError> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(DfuDevice::try_from_device_info(device));
+        });
+        rx.recv_timeout(crate::OPEN_TIMEOUT)
+            .unwrap_or(Err(DfuError::Timeout))
     }
 
     pub fn device_info(&self) -> &nusb::DeviceInfo {
@@ -59,6 +140,15 @@ impl DfuDevice {
         self.dev.id()
     }
 
+    pub fn key(&self) -> DeviceKey {
+        DeviceKey {
+            vendor_id: self.dev.vendor_id(),
+            product_id: self.dev.product_id(),
+            bus_id: self.dev.bus_id().into(),
+            port_chain: self.dev.port_chain().into(),
+        }
+    }
+
     pub fn bus_id(&self) -> &str {
         self.dev.bus_id()
     }
@@ -75,12 +165,40 @@ impl DfuDevice {
         self.dev.product_id()
     }
 
+    pub fn manufacturer_string(&self) -> Option<&str> {
+        self.dev.manufacturer_string()
+    }
+
     pub fn product_string(&self) -> Option<&str> {
         self.dev.product_string()
     }
 
+    pub fn serial_number(&self) -> Option<&str> {
+        self.dev.serial_number()
+    }
+
+    /// Device release number (`bcdDevice`), which encodes the
+    /// bootloader version on STM32 parts
+    pub fn bcd_device(&self) -> u16 {
+        self.dev.device_version()
+    }
+
     pub fn device_version(&self) -> String {
-        bcd_version_string(self.dev.device_version())
+        bcd_version_string(self.bcd_device())
+    }
+
+    /// Capabilities at a glance, computed from the memory layouts and
+    /// the DFU functional descriptor
+    pub fn summary(&self) -> DeviceSummary {
+        let segments = || self.all_segments().map(|(_, s)| s);
+        DeviceSummary {
+            interfaces: self.interfaces.len(),
+            readable: segments().any(|s| s.readable()),
+            writable: segments().any(|s| s.writable()),
+            erasable: segments().any(|s| s.erasable()),
+            flash_size: flash_size(self.interfaces.iter().map(|i| i.layout())),
+            dfuse: self.is_dfuse(),
+        }
     }
 
     /// DFU interfaces and alternate settings combined
@@ -88,29 +206,131 @@ impl DfuDevice {
         &self.interfaces
     }
 
+    /// Every memory segment along with its interface, in interface and
+    /// layout order
+    pub fn all_segments(
+        &self,
+    ) -> impl Iterator<Item = (&DfuInterface, &DfuMemSegment)> {
+        self.interfaces.iter().flat_map(|intf| {
+            intf.layout().segments.iter().map(move |s| (intf, s))
+        })
+    }
+
+    /// Interface number, alternate setting and memory segment owning
+    /// `addr` (binary search in an index built once, overlapping segments
+    /// are searched linearly)
+    pub fn locate(&self, addr: u32) -> Option<(u8, u8, &DfuMemSegment)> {
+        let (i, j) = self.segment_index.locate(addr)?;
+        let intf = &self.interfaces[i];
+        let segment = intf.layout().segments.get(j)?;
+        Some((intf.interface(), intf.alt_setting(), segment))
+    }
+
+    /// Only keep the DFU interfaces of the USB configuration `config`
+    /// (for devices exposing DFU in several configurations)
+    pub fn select_configuration(
+        mut self,
+        config: u8,
+    ) -> Result<Self, DfuError> {
+        self.interfaces.retain(|intf| intf.config() == config);
+        if self.interfaces.is_empty() {
+            return Err(DfuError::InvalidConfiguration(config));
+        }
+        self.segment_index = SegmentIndex::new(&self.interfaces);
+        Ok(self)
+    }
+
+    /// DFU interfaces (interface number, alternate setting and raw string)
+    /// whose memory layout could not be parsed
+    pub fn unparsed_interfaces(&self) -> &Vec<(u8, u8, String)> {
+        &self.unparsed_interfaces
+    }
+
+    /// Pairs of interfaces (interface number and alternate setting of
+    /// each) exposing overlapping address ranges
+    ///
+    /// [DfuDevice::find_interface()] picks the first one matching, which
+    /// may not be the expected one.
+    pub fn overlapping_interfaces(&self) -> Vec<(u8, u8, u8, u8)> {
+        self.segment_index
+            .overlaps()
+            .into_iter()
+            .map(|(a, b)| {
+                let (a, b) = (&self.interfaces[a], &self.interfaces[b]);
+                (
+                    a.interface(),
+                    a.alt_setting(),
+                    b.interface(),
+                    b.alt_setting(),
+                )
+            })
+            .collect()
+    }
+
     pub(crate) fn open(&self) -> Result<nusb::Device, DfuError> {
         Ok(self.dev.open().wait()?)
     }
 
     pub fn is_dfuse(&self) -> bool {
-        self.dfu_descriptor().ok().unwrap_or_default().dfu_version()
-            == DFUSE_VERSION_NUMBER
+        is_dfuse(
+            self.dfu_descriptor().ok().unwrap_or_default().dfu_version(),
+            self.interfaces.iter().any(|intf| intf.has_dfuse_layout()),
+        )
     }
 
     /// Query the DFU descriptor for this device. If no descriptor can be found,
     /// [DfuDescriptor::default()] is returned.
     pub fn dfu_descriptor(&self) -> Result<DfuDescriptor, DfuError> {
+        Ok(self.find_dfu_descriptor()?.unwrap_or_default())
+    }
+
+    /// Device supports reading memory (assumed if no DFU descriptor is found)
+    pub fn can_upload(&self) -> Result<bool, DfuError> {
+        Ok(self
+            .find_dfu_descriptor()?
+            .is_none_or(|desc| desc.can_upload()))
+    }
+
+    /// Device supports writing memory (assumed if no DFU descriptor is found)
+    pub fn can_download(&self) -> Result<bool, DfuError> {
+        Ok(self
+            .find_dfu_descriptor()?
+            .is_none_or(|desc| desc.can_download()))
+    }
+
+    /// DFU descriptor of an interface alternate setting, falling back to
+    /// [DfuDevice::dfu_descriptor()] if it has none
+    pub fn interface_dfu_descriptor(
+        &self,
+        interface: u8,
+        alt_setting: u8,
+    ) -> Result<DfuDescriptor, DfuError> {
+        let open_dev = self.open()?;
+        let desc = open_dev.configurations().find_map(|config| {
+            config
+                .interface_alt_settings()
+                .filter(|alt| {
+                    alt.interface_number() == interface
+                        && alt.alternate_setting() == alt_setting
+                })
+                .find_map(|alt| alt.descriptors().find(is_dfu_descriptor))
+        });
+        match desc {
+            Some(desc) => Ok(DfuDescriptor::new(&desc)),
+            None => self.dfu_descriptor(),
+        }
+    }
+
+    fn find_dfu_descriptor(&self) -> Result<Option<DfuDescriptor>, DfuError> {
         let open_dev = self.open()?;
-        Ok(
-            match open_dev.configurations().find_map(|config| {
+        Ok(open_dev
+            .configurations()
+            .find_map(|config| {
                 config.interface_alt_settings().find_map(|alt_setting| {
                     alt_setting.descriptors().find(is_dfu_descriptor)
                 })
-            }) {
-                Some(dfu_desc) => DfuDescriptor::new(&dfu_desc),
-                None => DfuDescriptor::default(),
-            },
-        )
+            })
+            .map(|dfu_desc| DfuDescriptor::new(&dfu_desc)))
     }
 
     /// Find a matching interface and alternate setting
@@ -128,12 +348,231 @@ impl DfuDevice {
                 && start_address >= segments.first().unwrap().start_addr()
                 && end_address.is_none_or(|addr| addr <= segments.last().unwrap().end_addr())
             })
-            .ok_or(DfuError::NoMemorySegments)
+            .ok_or_else(|| self.out_of_range_error(start_address, end_address))
+    }
+
+    /// Find the interface whose memory layout is named `name` (ignoring
+    /// case and surrounding spaces, ex: "internal flash")
+    pub fn find_interface_by_name(&self, name: &str) -> Option<&DfuInterface> {
+        let name = name.trim();
+        self.interfaces
+            .iter()
+            .find(|intf| intf.layout().name.trim().eq_ignore_ascii_case(name))
+    }
+
+    // Distinguishes a range starting outside of any segment from a range
+    // only exceeding the segments containing its start
+    fn out_of_range_error(
+        &self,
+        start_address: u32,
+        end_address: Option<u32>,
+    ) -> DfuError {
+        let available = self
+            .interfaces
+            .iter()
+            .filter_map(|intf| intf.layout().contiguous_range(start_address))
+            .max_by_key(|(start, end)| end - start);
+        match (available, end_address) {
+            (Some((start, end)), Some(requested_end)) => {
+                DfuError::AddressOutOfRange {
+                    requested: (start_address, requested_end),
+                    available: (start, end - 1),
+                }
+            }
+            _ => DfuError::NoMemorySegments,
+        }
+    }
+
+    /// Find a matching interface and connect to it
+    ///
+    /// Returns the connection along with the interface and the memory
+    /// segments covering the requested address range.
+    pub fn connect_for_address(
+        &self,
+        start_address: u32,
+        end_address: Option<u32>,
+    ) -> Result<(DfuConnection, DfuInterfaceSegments), DfuError> {
+        let intf = self.find_interface(start_address, end_address)?;
+        let segments =
+            NonEmpty::from_vec(intf.find_segments(start_address, end_address))
+                .ok_or(DfuError::NoMemorySegments)?;
+        let connection = self.connect(intf.interface(), intf.alt_setting())?;
+        Ok((
+            connection,
+            DfuInterfaceSegments::new(intf.clone(), segments),
+        ))
+    }
+
+    /// Look for this device again (ex: after a reboot)
+    ///
+    /// A device with the same serial number is preferred, otherwise the
+    /// device connected to the same physical port (see [DeviceKey]) is
+    /// returned. Without serial number, the first device with the same
+    /// vendor / product IDs is the last resort (ex: re-enumerated on
+    /// another port).
+    pub fn rediscover(&self) -> Result<Option<DfuDevice>, DfuError> {
+        let devices = find_dfu_devices(
+            Some(self.vendor_id()),
+            Some(self.product_id()),
+            None,
+        )?;
+        Ok(self.find_same_device(devices))
+    }
+
+    fn find_same_device(
+        &self,
+        mut devices: Vec<DfuDevice>,
+    ) -> Option<DfuDevice> {
+        let candidates: Vec<(Option<&str>, DeviceKey)> = devices
+            .iter()
+            .map(|dev| (dev.serial_number(), dev.key()))
+            .collect();
+        let index =
+            same_device_index(self.serial_number(), &self.key(), &candidates)?;
+        Some(devices.swap_remove(index))
+    }
+
+    /// Wait for this device to come back, looking for it every `interval`
+    /// until `timeout` elapses ([DfuError::Timeout]).
+    pub fn rediscover_with(
+        &self,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<DfuDevice, DfuError> {
+        self.rediscover_until(timeout, interval, |_| true)
+    }
+
+    /// Same as [DfuDevice::rediscover_with()], the device is only returned
+    /// once `accept` returns true (ex: it exposes the expected interfaces)
+    ///
+    /// The device is enumerated again on each attempt: a device found too
+    /// early (ex: incomplete interfaces, unreadable descriptors) or failing
+    /// to open while it is still initializing is retried until `timeout`.
+    pub fn rediscover_until(
+        &self,
+        timeout: Duration,
+        interval: Duration,
+        accept: impl Fn(&DfuDevice) -> bool,
+    ) -> Result<DfuDevice, DfuError> {
+        let start = Instant::now();
+        loop {
+            match self.rediscover() {
+                Ok(Some(device))
+                    if device.find_dfu_descriptor().is_ok()
+                        && accept(&device) =>
+                {
+                    return Ok(device);
+                }
+                Ok(Some(_)) => {
+                    log::debug!("Device found without expected interfaces")
+                }
+                Ok(None) => {}
+                Err(err) => log::debug!("Device not ready yet: {err}"),
+            }
+            if start.elapsed() >= timeout {
+                return Err(DfuError::Timeout);
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    /// Rough estimate of the time needed to erase and write the range
+    /// `start_address` - `end_address` (inclusive)
+    pub fn estimate_flash_time(
+        &self,
+        start_address: u32,
+        end_address: u32,
+    ) -> Result<Duration, DfuError> {
+        self.estimate_flash_time_with(
+            start_address,
+            end_address,
+            &FlashRates::default(),
+        )
+    }
+
+    /// Same as [DfuDevice::estimate_flash_time()] with custom timings
+    pub fn estimate_flash_time_with(
+        &self,
+        start_address: u32,
+        end_address: u32,
+        rates: &FlashRates,
+    ) -> Result<Duration, DfuError> {
+        // empty or the whole address space (4 GiB do not fit in u32)
+        let bytes = end_address
+            .checked_sub(start_address)
+            .and_then(|len| len.checked_add(1))
+            .ok_or(DfuError::InvalidRange {
+                start: start_address,
+                length: (end_address as u64 + 1)
+                    .saturating_sub(start_address as u64),
+            })?;
+        let layout = self
+            .find_interface(start_address, Some(end_address))?
+            .layout();
+        let (erase_start, erase_end) =
+            layout.align_range_to_pages(start_address, end_address);
+        let page_sizes = layout
+            .get_erase_pages(erase_start, erase_end)
+            .into_iter()
+            .filter_map(|addr| {
+                layout.segments.iter().find(|s| s.contains(addr))
+            })
+            .map(|s| s.page_size());
+        let transfer_size = match self.dfu_descriptor()?.transfer_size() {
+            0 => crate::DEFAULT_TRANSFER_SIZE,
+            size => size,
+        };
+        Ok(rates.estimate(page_sizes, bytes, transfer_size))
     }
 
-    /// Return the start address of the first alternate setting
+    /// Read the STM32 unique device ID
+    ///
+    /// The UID address is derived from the family detected from the
+    /// memory layout, see [DfuDevice::read_uid_at()] for other devices.
+    pub fn read_uid(&self) -> Result<[u8; UID_LEN], DfuError> {
+        let segment_starts = self.all_segments().map(|(_, s)| s.start_addr());
+        let address =
+            uid_address(segment_starts).ok_or(DfuError::UnknownUidAddress)?;
+        self.read_uid_at(address)
+    }
+
+    /// Read the unique device ID at `address`
+    ///
+    /// The first interface is used if no interface covers `address`.
+    pub fn read_uid_at(&self, address: u32) -> Result<[u8; UID_LEN], DfuError> {
+        let end_address = address.checked_add(UID_LEN as u32 - 1).ok_or(
+            DfuError::InvalidRange {
+                start: address,
+                length: UID_LEN as u64,
+            },
+        )?;
+        let connection = match self.find_interface(address, Some(end_address)) {
+            Ok(intf) => self.connect(intf.interface(), intf.alt_setting())?,
+            Err(_) => self.connect(0, 0)?,
+        };
+        connection.reset_state()?;
+        connection.dfuse_set_address(address)?;
+        connection.reset_state()?;
+        let data = connection.upload(0, UID_LEN as u16)?;
+        data.as_slice()
+            .try_into()
+            .map_err(|_| DfuError::ShortUpload(data.len()))
+    }
+
+    /// Return the start address of the main flash
+    ///
+    /// This is the first writable segment of the first flash memory
+    /// (ex: "Internal Flash"), or the start address of the first alternate
+    /// setting if no such memory is found. Without any parsable memory
+    /// layout, `0` is returned (accesses then fail with
+    /// [DfuError::NoMemorySegments]).
     pub fn get_default_start_address(&self) -> u32 {
-        self.interfaces[0].layout().segments[0].start_addr()
+        main_flash_start_address(self.interfaces.iter().map(|i| i.layout()))
+            .or_else(|| {
+                let intf = self.interfaces.first()?;
+                Some(intf.layout().segments.first().start_addr())
+            })
+            .unwrap_or(0)
     }
 
     /// Connect to the DFU interface
@@ -143,18 +582,73 @@ impl DfuDevice {
         &self,
         interface: u8,
         alt_setting: u8,
+    ) -> Result<DfuConnection, DfuError> {
+        // the configuration may have been reset (ex: after re-enumeration)
+        let config = self
+            .interfaces
+            .iter()
+            .find(|i| {
+                i.interface() == interface && i.alt_setting() == alt_setting
+            })
+            .map(|i| i.config());
+        self.connect_with(config, interface, alt_setting)
+    }
+
+    /// Same as [DfuDevice::connect()] in the USB configuration `config`,
+    /// which is activated first if needed
+    pub fn connect_in_config(
+        &self,
+        config: u8,
+        interface: u8,
+        alt_setting: u8,
+    ) -> Result<DfuConnection, DfuError> {
+        self.connect_with(Some(config), interface, alt_setting)
+    }
+
+    fn connect_with(
+        &self,
+        config: Option<u8>,
+        interface: u8,
+        alt_setting: u8,
     ) -> Result<DfuConnection, DfuError> {
         let xfer_size = self.dfu_descriptor()?.transfer_size();
         let dev = self.open()?;
+
+        if let Some(config) = config {
+            if !dev
+                .configurations()
+                .any(|c| c.configuration_value() == config)
+            {
+                return Err(DfuError::InvalidConfiguration(config));
+            }
+            let active = dev
+                .active_configuration()
+                .map(|c| c.configuration_value())
+                .ok();
+            if active != Some(config) {
+                dev.set_configuration(config).wait()?;
+            }
+        }
+
         let interface = dev.claim_interface(interface).wait()?;
         interface.set_alt_setting(alt_setting).wait()?;
-        Ok(DfuConnection::new(interface, xfer_size))
+        Ok(DfuConnection::new(dev, interface, xfer_size))
     }
 }
 
-fn is_dfu_descriptor(desc: &nusb::descriptors::Descriptor) -> bool {
-    desc.descriptor_len() == DFU_DESC_LEN
-        && desc.descriptor_type() == DFU_DESC_TYPE
+// Devices are equal if they designate the same enumerated device
+impl PartialEq for DfuDevice {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Eq for DfuDevice {}
+
+impl Hash for DfuDevice {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
 }
 
 fn is_dfu_device(dev: &nusb::DeviceInfo) -> bool {
@@ -162,25 +656,85 @@ fn is_dfu_device(dev: &nusb::DeviceInfo) -> bool {
         .any(|i| i.class() == DFU_CLASS && i.subclass() == DFU_SUBCLASS)
 }
 
+/// Open all DFU devices matching the given IDs and serial number
+///
+/// The serial number comparison is case-insensitive.
 pub fn find_dfu_devices(
     vid: Option<u16>,
     pid: Option<u16>,
+    serial: Option<String>,
+) -> Result<Vec<DfuDevice>, DfuError> {
+    let devices = list_dfu_device_infos(vid, pid, serial.as_deref())?;
+    let mut dfu_devices = Vec::with_capacity(devices.len());
+    for device in devices {
+        let id = format!(
+            "{:04x}:{:04x} (bus {}, address {})",
+            device.vendor_id(),
+            device.product_id(),
+            device.bus_id(),
+            device.device_address()
+        );
+        match DfuDevice::open_with_timeout(device) {
+            Ok(Some(dfu_device)) => dfu_devices.push(dfu_device),
+            Ok(None) => {}
+            Err(DfuError::Timeout) => {
+                log::warn!("Timeout while opening device {id}, skipping");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(dfu_devices)
+}
+
+/// Poll [find_dfu_devices] every `interval` until at least one matching
+/// device shows up, or `timeout` elapses ([DfuError::Timeout]).
+///
+/// Enumeration errors are tolerated while waiting.
+pub fn wait_for_dfu_devices(
+    vid: Option<u16>,
+    pid: Option<u16>,
+    serial: Option<String>,
+    timeout: Duration,
+    interval: Duration,
 ) -> Result<Vec<DfuDevice>, DfuError> {
-    let devices: Vec<nusb::DeviceInfo> = nusb::list_devices()
+    let start = Instant::now();
+    loop {
+        match find_dfu_devices(vid, pid, serial.clone()) {
+            Ok(devices) if !devices.is_empty() => return Ok(devices),
+            Ok(_) => {}
+            Err(err) => log::debug!("Device not ready yet: {err}"),
+        }
+        if start.elapsed() >= timeout {
+            return Err(DfuError::Timeout);
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// USB devices exposing a DFU interface, without opening them
+pub fn list_dfu_device_infos(
+    vid: Option<u16>,
+    pid: Option<u16>,
+    serial: Option<&str>,
+) -> Result<Vec<nusb::DeviceInfo>, DfuError> {
+    Ok(nusb::list_devices()
         .wait()?
         .filter(|dev| {
             vid.is_none_or(|id| dev.vendor_id() == id)
                 && pid.is_none_or(|id| dev.product_id() == id)
+                && serial_matches(dev.serial_number(), serial)
         })
         .filter(is_dfu_device)
-        .collect();
-    let mut dfu_devices = Vec::with_capacity(devices.len());
-    for device in devices {
-        if let Some(dfu_device) = DfuDevice::from_device_info(device)? {
-            dfu_devices.push(dfu_device);
-        }
-    }
-    Ok(dfu_devices)
+        .collect())
+}
+
+pub(crate) fn serial_matches(
+    device_serial: Option<&str>,
+    serial: Option<&str>,
+) -> bool {
+    serial.is_none_or(|serial| {
+        device_serial.is_some_and(|s| s.eq_ignore_ascii_case(serial))
+    })
 }
 
 fn bcd_version_string(bcd_version: u16) -> String {
@@ -189,10 +743,59 @@ fn bcd_version_string(bcd_version: u16) -> String {
     format!("{}.{:02}", major, minor)
 }
 
+// Index of the device to reconnect to among the `candidates` (serial
+// number and key of each), see [DfuDevice::rediscover()]
+fn same_device_index(
+    serial: Option<&str>,
+    key: &DeviceKey,
+    candidates: &[(Option<&str>, DeviceKey)],
+) -> Option<usize> {
+    let serial = serial.filter(|s| !s.is_empty());
+    let same_serial = || {
+        serial.and_then(|serial| {
+            candidates.iter().position(|(s, _)| *s == Some(serial))
+        })
+    };
+    let same_port = || candidates.iter().position(|(_, k)| k == key);
+    let first = || (serial.is_none() && !candidates.is_empty()).then_some(0);
+    same_serial().or_else(same_port).or_else(first)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_serial_matches() {
+        assert!(serial_matches(Some("3276365B3034"), None));
+        assert!(serial_matches(None, None));
+        assert!(serial_matches(Some("3276365B3034"), Some("3276365b3034")));
+        assert!(!serial_matches(Some("3276365B3034"), Some("3276365B")));
+        assert!(!serial_matches(None, Some("3276365B3034")));
+    }
+
+    #[test]
+    fn test_same_device_index() {
+        let key = |port: u8| DeviceKey {
+            vendor_id: 0x0483,
+            product_id: 0xdf11,
+            bus_id: "1".into(),
+            port_chain: vec![port],
+        };
+        let candidates = [(Some("A"), key(2)), (Some("B"), key(1))];
+        // same serial number first, then same port
+        assert_eq!(same_device_index(Some("B"), &key(2), &candidates), Some(1));
+        assert_eq!(same_device_index(Some("C"), &key(2), &candidates), Some(0));
+        assert_eq!(same_device_index(Some("C"), &key(3), &candidates), None);
+
+        // without serial number, the first device on another port
+        let candidates = [(None, key(4)), (None, key(5))];
+        assert_eq!(same_device_index(None, &key(5), &candidates), Some(1));
+        assert_eq!(same_device_index(None, &key(3), &candidates), Some(0));
+        assert_eq!(same_device_index(Some(""), &key(3), &candidates), Some(0));
+        assert_eq!(same_device_index(None, &key(3), &[]), None);
+    }
+
     #[test]
     fn test_bcd_string() {
         assert_eq!(bcd_version_string(0x0200), "2.00");
@@ -3,21 +3,89 @@ pub enum DfuError {
     Usb(nusb::Error),
     Transfer(nusb::transfer::TransferError),
     Status(u8),
+    ShortStatus(usize),
     UnalignedAddress,
     InvalidInterface,
+    InvalidConfiguration(u8),
     NoMemorySegments,
+    /// Start address of a segment which cannot be written
+    NotWritable(u32),
+    /// Start address of a segment which cannot be erased
+    NotErasable(u32),
+    /// The requested range (inclusive) starts within the device memory
+    /// but exceeds the available range (inclusive)
+    AddressOutOfRange {
+        requested: (u32, u32),
+        available: (u32, u32),
+    },
+    /// `length` bytes from `start` are empty or exceed the 32-bit address
+    /// space
+    InvalidRange {
+        start: u32,
+        length: u64,
+    },
     Timeout,
+    Disconnected,
+    /// The device went back to runtime mode or disconnected during a
+    /// transfer (ex: watchdog reset of the bootloader)
+    LeftDfuMode,
+    UploadNotSupported,
+    DownloadNotSupported,
+    UnknownUidAddress,
+    ShortUpload(usize),
+    TransferTooLarge {
+        size: usize,
+        max: u16,
+    },
+    /// First address whose content differs from the expected data
+    VerifyMismatch {
+        addr: u32,
+    },
 }
 
 impl std::error::Error for DfuError {}
 
+/// Name and description of a status code (`bStatus`), as listed in
+/// section 6.1.2 of the DFU specification
+pub(crate) fn status_description(
+    code: u8,
+) -> Option<(&'static str, &'static str)> {
+    Some(match code {
+        0x00 => ("OK", "no error condition is present"),
+        0x01 => ("errTARGET", "file is not targeted for use by this device"),
+        0x02 => ("errFILE", "file fails a vendor-specific verification test"),
+        0x03 => ("errWRITE", "device is unable to write memory"),
+        0x04 => ("errERASE", "memory erase function failed"),
+        0x05 => ("errCHECK_ERASED", "memory erase check failed"),
+        0x06 => ("errPROG", "program memory function failed"),
+        0x07 => ("errVERIFY", "programmed memory failed verification"),
+        0x08 => ("errADDRESS", "received address is out of range"),
+        0x09 => ("errNOTDONE", "download ended before all data was received"),
+        0x0a => ("errFIRMWARE", "device's firmware is corrupt"),
+        0x0b => ("errVENDOR", "vendor-specific error"),
+        0x0c => ("errUSBR", "device detected unexpected USB reset signaling"),
+        0x0d => ("errPOR", "device detected unexpected power on reset"),
+        0x0e => ("errUNKNOWN", "something went wrong, cause unknown"),
+        0x0f => ("errSTALLEDPKT", "device stalled an unexpected request"),
+        _ => return None,
+    })
+}
+
 impl std::fmt::Display for DfuError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             DfuError::Usb(err) => write!(f, "USB error: {}", err),
             DfuError::Transfer(err) => write!(f, "Transfer error: {}", err),
-            DfuError::Status(code) => {
-                write!(f, "DFU status error: code {}", code)
+            DfuError::Status(code) => match status_description(*code) {
+                Some((name, description)) => write!(
+                    f,
+                    "DFU status error: {} ({}, code {})",
+                    name, description, code
+                ),
+                None => write!(f, "DFU status error: code {}", code),
+            },
+            DfuError::ShortStatus(len) => {
+                write!(f, "Short DFU status response ({} bytes)", len)
             }
             DfuError::UnalignedAddress => {
                 write!(f, "Unaligned page address")
@@ -25,24 +93,138 @@ impl std::fmt::Display for DfuError {
             DfuError::InvalidInterface => {
                 write!(f, "Invalid interface")
             }
+            DfuError::InvalidConfiguration(config) => {
+                write!(
+                    f,
+                    "Configuration {} not found or without DFU interface",
+                    config
+                )
+            }
             DfuError::NoMemorySegments => {
                 write!(f, "No compatible memory segments")
             }
+            DfuError::NotWritable(addr) => {
+                write!(f, "Segment at {:#010x} is not writable", addr)
+            }
+            DfuError::NotErasable(addr) => {
+                write!(f, "Segment at {:#010x} is not erasable", addr)
+            }
+            DfuError::AddressOutOfRange {
+                requested,
+                available,
+            } => {
+                write!(
+                    f,
+                    "Address range {:#010x} - {:#010x} exceeds the device \
+                    memory, valid range is {:#010x} - {:#010x}",
+                    requested.0, requested.1, available.0, available.1
+                )
+            }
+            DfuError::InvalidRange { start, length } => {
+                write!(
+                    f,
+                    "Invalid address range: {} bytes from {:#010x}",
+                    length, start
+                )
+            }
             DfuError::Timeout => {
                 write!(f, "Timeout")
             }
+            DfuError::Disconnected => {
+                write!(f, "Device disconnected")
+            }
+            DfuError::LeftDfuMode => {
+                write!(
+                    f,
+                    "Device left DFU mode during the transfer, \
+                    put it back in DFU mode and retry"
+                )
+            }
+            DfuError::UploadNotSupported => {
+                write!(f, "Device does not support upload/read")
+            }
+            DfuError::DownloadNotSupported => {
+                write!(f, "Device does not support download/write")
+            }
+            DfuError::UnknownUidAddress => {
+                write!(f, "Unknown unique ID address for this device")
+            }
+            DfuError::ShortUpload(len) => {
+                write!(f, "Short upload ({} bytes)", len)
+            }
+            DfuError::TransferTooLarge { size, max } => {
+                write!(
+                    f,
+                    "Transfer too large ({} bytes, max {} bytes)",
+                    size, max
+                )
+            }
+            DfuError::VerifyMismatch { addr } => {
+                write!(f, "Verification failed at {:#010x}", addr)
+            }
         }
     }
 }
 
 impl From<nusb::Error> for DfuError {
     fn from(err: nusb::Error) -> Self {
-        DfuError::Usb(err)
+        match err.kind() {
+            nusb::ErrorKind::Disconnected => DfuError::Disconnected,
+            _ => DfuError::Usb(err),
+        }
     }
 }
 
 impl From<nusb::transfer::TransferError> for DfuError {
     fn from(err: nusb::transfer::TransferError) -> Self {
-        DfuError::Transfer(err)
+        match err {
+            nusb::transfer::TransferError::Disconnected => {
+                DfuError::Disconnected
+            }
+            _ => DfuError::Transfer(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_display() {
+        let names = [
+            "OK",
+            "errTARGET",
+            "errFILE",
+            "errWRITE",
+            "errERASE",
+            "errCHECK_ERASED",
+            "errPROG",
+            "errVERIFY",
+            "errADDRESS",
+            "errNOTDONE",
+            "errFIRMWARE",
+            "errVENDOR",
+            "errUSBR",
+            "errPOR",
+            "errUNKNOWN",
+            "errSTALLEDPKT",
+        ];
+        for (code, name) in names.into_iter().enumerate() {
+            let message = DfuError::Status(code as u8).to_string();
+            assert!(
+                message.starts_with(&format!("DFU status error: {name} (")),
+                "{message}"
+            );
+            assert!(message.ends_with(&format!(", code {code})")), "{message}");
+        }
+        assert_eq!(
+            DfuError::Status(0x04).to_string(),
+            "DFU status error: errERASE (memory erase function failed, code 4)"
+        );
+        assert_eq!(
+            DfuError::Status(0x42).to_string(),
+            "DFU status error: code 66"
+        );
     }
 }
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+/// Approximate timings used to estimate how long flashing takes
+///
+/// Defaults are in the ballpark of STM32 internal flash: they are meant to
+/// tell seconds from minutes, not to be exact.
+#[derive(Clone, Debug)]
+pub struct FlashRates {
+    /// erase time per KB of erased page
+    pub erase_per_kb: Duration,
+    /// time to download and program one transfer-size block
+    pub block: Duration,
+}
+
+impl Default for FlashRates {
+    fn default() -> Self {
+        FlashRates {
+            erase_per_kb: Duration::from_millis(10),
+            block: Duration::from_millis(25),
+        }
+    }
+}
+
+impl FlashRates {
+    /// Estimate erasing `page_sizes` and then writing `bytes` in blocks of
+    /// `transfer_size`
+    pub fn estimate(
+        &self,
+        page_sizes: impl IntoIterator<Item = u32>,
+        bytes: u32,
+        transfer_size: u16,
+    ) -> Duration {
+        let erased_kb: u64 =
+            page_sizes.into_iter().map(|size| size as u64).sum::<u64>() / 1024;
+        let blocks = bytes.div_ceil(transfer_size.max(1) as u32);
+        self.erase_per_kb * erased_kb as u32 + self.block * blocks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let rates = FlashRates {
+            erase_per_kb: Duration::from_millis(10),
+            block: Duration::from_millis(20),
+        };
+        assert_eq!(rates.estimate([], 0, 2048), Duration::ZERO);
+        // 4 x 16K pages, 64K in 32 blocks
+        assert_eq!(
+            rates.estimate([16 * 1024; 4], 64 * 1024, 2048),
+            Duration::from_millis(640 + 640)
+        );
+        // partial last block
+        assert_eq!(rates.estimate([], 2049, 2048), Duration::from_millis(40));
+    }
+}
@@ -1,8 +1,9 @@
 use std::{num::NonZeroU8, time::Duration};
 
+use nonempty::NonEmpty;
 use nusb::{self, MaybeFuture};
 
-use crate::memory::*;
+use crate::{DfuError, memory::*};
 
 #[derive(Clone, Debug)]
 pub struct DfuInterface {
@@ -10,25 +11,108 @@ pub struct DfuInterface {
     interface: u8,
     alt_setting: u8,
     layout: DfuMemory,
+    layout_string: String,
+    dfuse_layout: bool,
+}
+
+/// Interface matched for an address range along with the
+/// memory segments covering that range
+#[derive(Clone, Debug)]
+pub struct DfuInterfaceSegments {
+    interface: DfuInterface,
+    segments: NonEmpty<DfuMemSegment>,
+}
+
+// (start address, end address (exclusive), interface index, segment index)
+type SegmentEntry = (u32, u32, usize, usize);
+
+/// Segments of all interfaces sorted by start address, to quickly find
+/// the interface and segment owning an address
+///
+/// Segments overlapping an earlier one (ex: a bootloader interface within
+/// the internal flash) are kept aside, so that the others can be binary
+/// searched.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SegmentIndex {
+    disjoint: Vec<SegmentEntry>,
+    overlapping: Vec<SegmentEntry>,
+}
+
+impl SegmentIndex {
+    pub(crate) fn new(interfaces: &[DfuInterface]) -> Self {
+        let mut entries: Vec<(u32, u32, usize, usize)> = interfaces
+            .iter()
+            .enumerate()
+            .flat_map(|(i, intf)| {
+                intf.layout
+                    .segments
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, s)| (s.start_addr(), s.end_addr(), i, j))
+            })
+            .collect();
+        entries.sort_by_key(|e| e.0);
+        let mut index = SegmentIndex::default();
+        for entry in entries {
+            match index.disjoint.last() {
+                Some(last) if entry.0 < last.1 => index.overlapping.push(entry),
+                _ => index.disjoint.push(entry),
+            }
+        }
+        index
+    }
+
+    /// Interface and segment indexes of the segment containing `addr`
+    ///
+    /// Binary search among the disjoint segments, then linear search
+    /// among the overlapping ones (usually none), the first interface
+    /// wins.
+    pub(crate) fn locate(&self, addr: u32) -> Option<(usize, usize)> {
+        let pos = self.disjoint.partition_point(|e| e.0 <= addr);
+        let disjoint = pos.checked_sub(1).map(|pos| &self.disjoint[pos]);
+        disjoint
+            .into_iter()
+            .chain(&self.overlapping)
+            .filter(|e| e.0 <= addr && addr < e.1)
+            .min_by_key(|e| e.2)
+            .map(|e| (e.2, e.3))
+    }
+
+    /// Pairs of interface indexes with overlapping segments
+    pub(crate) fn overlaps(&self) -> Vec<(usize, usize)> {
+        let mut entries =
+            [self.disjoint.as_slice(), &self.overlapping].concat();
+        entries.sort_by_key(|e| e.0);
+        let mut pairs = Vec::new();
+        for (i, a) in entries.iter().enumerate() {
+            for b in entries[i + 1..].iter().take_while(|b| b.0 < a.1) {
+                let pair = (a.2.min(b.2), a.2.max(b.2));
+                if a.2 != b.2 && !pairs.contains(&pair) {
+                    pairs.push(pair);
+                }
+            }
+        }
+        pairs
+    }
 }
 
 impl DfuInterface {
+    /// Interface described by the memory layout string `intf_str`
+    /// (`None` if the layout cannot be parsed)
     pub(crate) fn new(
-        device: &nusb::Device,
         config: u8,
         interface: u8,
         alt_setting: u8,
-        name_idx: NonZeroU8,
+        intf_str: &str,
     ) -> Option<Self> {
-        let intf_str =
-            get_string_descriptor(device, name_idx, crate::DEFAULT_TIMEOUT)?;
-
-        let layout = parse_memory_layout(&intf_str)?;
+        let layout = parse_memory_layout(intf_str)?;
         Some(Self {
             config,
             interface,
             alt_setting,
             layout,
+            layout_string: intf_str.into(),
+            dfuse_layout: intf_str.starts_with('@'),
         })
     }
 
@@ -44,6 +128,14 @@ impl DfuInterface {
     pub fn layout(&self) -> &DfuMemory {
         &self.layout
     }
+    /// Interface string the layout was parsed from
+    pub fn layout_string(&self) -> &str {
+        &self.layout_string
+    }
+    /// Interface name is a DfuSe memory layout (`@` prefix)
+    pub fn has_dfuse_layout(&self) -> bool {
+        self.dfuse_layout
+    }
 
     pub fn find_segments(
         &self,
@@ -58,7 +150,73 @@ impl DfuInterface {
     }
 }
 
-fn get_string_descriptor(
+impl DfuInterfaceSegments {
+    pub(crate) fn new(
+        interface: DfuInterface,
+        segments: NonEmpty<DfuMemSegment>,
+    ) -> Self {
+        Self {
+            interface,
+            segments,
+        }
+    }
+
+    pub fn interface(&self) -> &DfuInterface {
+        &self.interface
+    }
+
+    pub fn segments(&self) -> &NonEmpty<DfuMemSegment> {
+        &self.segments
+    }
+
+    /// End address (exclusive) of the last matched segment
+    pub fn end_addr(&self) -> u32 {
+        self.segments.last().end_addr()
+    }
+
+    pub fn get_erase_pages(&self, start_addr: u32, end_addr: u32) -> Vec<u32> {
+        self.interface.get_erase_pages(start_addr, end_addr)
+    }
+
+    /// Check that all matched segments can be erased and written,
+    /// before the device fails with a less explicit status error
+    pub fn check_writable(&self) -> Result<(), DfuError> {
+        for segment in &self.segments {
+            if !segment.erasable() {
+                return Err(DfuError::NotErasable(segment.start_addr()));
+            }
+            if !segment.writable() {
+                return Err(DfuError::NotWritable(segment.start_addr()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as [DfuInterfaceSegments::check_writable()], also checks that
+    /// `start_addr` - `end_addr` (inclusive) lies in contiguous segments,
+    /// so that nothing gets erased when the range cannot be fully written
+    pub fn check_writable_range(
+        &self,
+        start_addr: u32,
+        end_addr: u32,
+    ) -> Result<(), DfuError> {
+        self.check_writable()?;
+        let layout = self.interface.layout();
+        if layout.is_range_covered(start_addr, end_addr) {
+            return Ok(());
+        }
+        match layout.contiguous_writable(start_addr) {
+            0 => Err(DfuError::NotWritable(start_addr)),
+            writable => Err(DfuError::AddressOutOfRange {
+                requested: (start_addr, end_addr),
+                available: (start_addr, start_addr + (writable - 1)),
+            }),
+        }
+    }
+}
+
+/// Interface string descriptor (the memory layout for DFU interfaces)
+pub(crate) fn get_string_descriptor(
     device: &nusb::Device,
     desc_index: NonZeroU8,
     timeout: Duration,
@@ -75,3 +233,136 @@ fn get_string_descriptor(
         .wait()
         .ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_writable() {
+        let intf = DfuInterface::new(
+            1,
+            0,
+            0,
+            "@Internal Flash  /0x08000000/02*016Kg,01*016Ka,01*016Kd",
+        )
+        .unwrap();
+        let segments = |start, end| {
+            let segments = NonEmpty::from_vec(intf.find_segments(start, end));
+            DfuInterfaceSegments::new(intf.clone(), segments.unwrap())
+        };
+        assert!(
+            segments(0x08000000, Some(0x08007fff))
+                .check_writable()
+                .is_ok()
+        );
+        assert!(matches!(
+            segments(0x08000000, Some(0x08008fff)).check_writable(),
+            Err(DfuError::NotErasable(0x08008000))
+        ));
+        assert!(matches!(
+            segments(0x0800c000, Some(0x0800cfff)).check_writable(),
+            Err(DfuError::NotErasable(0x0800c000))
+        ));
+    }
+
+    #[test]
+    fn test_check_writable_range() {
+        let intf =
+            DfuInterface::new(1, 0, 0, "@Internal Flash  /0x08000000/02*016Kg")
+                .unwrap();
+        let segments = |start, end| {
+            let segments = NonEmpty::from_vec(intf.find_segments(start, end));
+            DfuInterfaceSegments::new(intf.clone(), segments.unwrap())
+        };
+        assert!(
+            segments(0x08001000, Some(0x08007fff))
+                .check_writable_range(0x08001000, 0x08007fff)
+                .is_ok()
+        );
+        assert!(matches!(
+            segments(0x08001000, Some(0x08008fff))
+                .check_writable_range(0x08001000, 0x08008fff),
+            Err(DfuError::AddressOutOfRange {
+                requested: (0x08001000, 0x08008fff),
+                available: (0x08001000, 0x08007fff),
+            })
+        ));
+        assert!(matches!(
+            segments(0x08001000, Some(0x08007fff))
+                .check_writable_range(0x09000000, 0x09000fff),
+            Err(DfuError::NotWritable(0x09000000))
+        ));
+    }
+
+    #[test]
+    fn test_segment_index() {
+        let interfaces: Vec<DfuInterface> = [
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+            "@Option Bytes  /0x1FFFC000/01*016 e",
+            "@External Flash /0x90000000/032*064Kg",
+        ]
+        .iter()
+        .enumerate()
+        .filter_map(|(alt, layout)| DfuInterface::new(1, 0, alt as u8, layout))
+        .collect();
+        let index = SegmentIndex::new(&interfaces);
+
+        assert_eq!(index.locate(0x08000000), Some((0, 0)));
+        assert_eq!(index.locate(0x0800ffff), Some((0, 0)));
+        assert_eq!(index.locate(0x08010000), Some((0, 1)));
+        assert_eq!(index.locate(0x080fffff), Some((0, 2)));
+        assert_eq!(index.locate(0x08100000), None);
+        assert_eq!(index.locate(0x1fffc00f), Some((1, 0)));
+        assert_eq!(index.locate(0x1fffc010), None);
+        assert_eq!(index.locate(0x901fffff), Some((2, 0)));
+        assert_eq!(index.locate(0x07ffffff), None);
+        assert_eq!(index.locate(0), None);
+        assert!(index.overlaps().is_empty());
+
+        // a shorter segment starting later does not hide an earlier one
+        let interfaces: Vec<DfuInterface> = [
+            "@Internal Flash  /0x08000000/04*016Kg",
+            "@Sector  /0x08004000/01*004Kg",
+        ]
+        .iter()
+        .enumerate()
+        .filter_map(|(alt, layout)| DfuInterface::new(1, 0, alt as u8, layout))
+        .collect();
+        let index = SegmentIndex::new(&interfaces);
+        assert_eq!(index.locate(0x08006000), Some((0, 0)));
+        assert_eq!(index.locate(0x08004000), Some((0, 0)));
+        assert_eq!(index.locate(0x08010000), None);
+
+        // nor does a shorter segment starting at the same address
+        let interfaces: Vec<DfuInterface> = [
+            "@Bootloader  /0x08000000/01*016Kg",
+            "@Internal Flash  /0x08000000/08*016Kg",
+        ]
+        .iter()
+        .enumerate()
+        .filter_map(|(alt, layout)| DfuInterface::new(1, 0, alt as u8, layout))
+        .collect();
+        let index = SegmentIndex::new(&interfaces);
+        assert_eq!(index.locate(0x08000000), Some((0, 0)));
+        assert_eq!(index.locate(0x08008000), Some((1, 0)));
+        assert_eq!(index.locate(0x08020000), None);
+        assert_eq!(index.overlaps(), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_segment_index_overlaps() {
+        let interfaces: Vec<DfuInterface> = [
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+            "@Option Bytes  /0x1FFFC000/01*016 e",
+            "@Bootloader  /0x08000000/02*016Kg",
+            "@Firmware  /0x08020000/06*128Kg",
+        ]
+        .iter()
+        .enumerate()
+        .filter_map(|(alt, layout)| DfuInterface::new(1, 0, alt as u8, layout))
+        .collect();
+        let index = SegmentIndex::new(&interfaces);
+        assert_eq!(index.overlaps(), vec![(0, 2), (0, 3)]);
+    }
+}
@@ -14,7 +14,7 @@
 //! ```
 //! use dfu::find_dfu_devices;
 //!
-//! match find_dfu_devices(None, None) {
+//! match find_dfu_devices(None, None, None) {
 //!     Ok(devices) => {
 //!         if devices.is_empty() {
 //!             println!("No DFU devices found");
@@ -32,20 +32,44 @@
 
 pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_millis(5000u64);
 pub(crate) const DEFAULT_TRANSFER_SIZE: u16 = 1024 * 2;
+pub const REDISCOVER_TIMEOUT: Duration = Duration::from_secs(30);
+pub const REDISCOVER_INTERVAL: Duration = Duration::from_millis(200);
+pub(crate) const OPEN_TIMEOUT: Duration = Duration::from_millis(10000u64);
 
+mod command;
 mod connection;
 mod descriptor;
 mod device;
 mod error;
+mod estimate;
 mod interface;
 mod memory;
+mod progress;
+mod reboot;
+#[cfg(any(test, feature = "recording"))]
+mod recording;
+mod result;
+mod runtime;
+mod uid;
 
 use std::time::Duration;
 
 // Re-exports
-pub use connection::DfuConnection;
+pub use command::DfuseCommand;
+pub use connection::{DfuConnection, DfuState, DfuStatus, PollObserver};
 pub use descriptor::{DFUSE_VERSION_NUMBER, DfuDescriptor};
-pub use device::{DfuDevice, find_dfu_devices};
+pub use device::{
+    DeviceKey, DeviceSummary, DfuDevice, find_dfu_devices,
+    list_dfu_device_infos, wait_for_dfu_devices,
+};
 pub use error::DfuError;
-pub use interface::DfuInterface;
+pub use estimate::FlashRates;
+pub use interface::{DfuInterface, DfuInterfaceSegments};
 pub use memory::{DfuMemSegment, DfuMemory};
+pub use progress::{Phase, Progress, ProgressSink};
+pub use reboot::{REBOOT_MAGIC, RebootFormat};
+#[cfg(any(test, feature = "recording"))]
+pub use recording::ControlTransfer;
+pub use result::{DownloadResult, UploadResult};
+pub use runtime::{DfuRuntimeDevice, list_runtime_devices};
+pub use uid::UID_LEN;
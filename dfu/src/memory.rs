@@ -40,6 +40,99 @@ impl DfuMemory {
             .collect()
     }
 
+    /// Expand the range (`end_addr` inclusive) outward to the boundaries
+    /// of the pages containing `start_addr` and `end_addr`.
+    ///
+    /// Flashing a partial page still erases the whole page: any data
+    /// outside of the requested range but within the aligned range is lost.
+    pub fn align_range_to_pages(
+        &self,
+        start_addr: u32,
+        end_addr: u32,
+    ) -> (u32, u32) {
+        let find = |addr| self.segments.iter().find(|s| s.contains(addr));
+        let start = find(start_addr).map_or(start_addr, |s| {
+            start_addr - (start_addr - s.start_addr()) % s.page_size()
+        });
+        let end = find(end_addr).map_or(end_addr, |s| {
+            end_addr - (end_addr - s.start_addr()) % s.page_size()
+                + s.page_size()
+                - 1
+        });
+        (start, end)
+    }
+
+    /// Number of bytes that can be written from `start_addr` without gap,
+    /// walking adjacent writable segments
+    ///
+    /// Returns 0 if `start_addr` is not within a writable segment.
+    pub fn contiguous_writable(&self, start_addr: u32) -> u32 {
+        let mut segments =
+            self.segments.iter().skip_while(|s| !s.contains(start_addr));
+        let Some(first) = segments.next().filter(|s| s.writable()) else {
+            return 0;
+        };
+        let mut end_addr = first.end_addr();
+        for segment in segments {
+            if segment.start_addr() != end_addr || !segment.writable() {
+                break;
+            }
+            end_addr = segment.end_addr();
+        }
+        end_addr - start_addr
+    }
+
+    /// Address range (`end` exclusive) covered by the segment containing
+    /// `start_addr` and the segments directly following it
+    pub fn contiguous_range(&self, start_addr: u32) -> Option<(u32, u32)> {
+        let mut segments =
+            self.segments.iter().skip_while(|s| !s.contains(start_addr));
+        let first = segments.next()?;
+        let mut end_addr = first.end_addr();
+        for segment in segments {
+            if segment.start_addr() != end_addr {
+                break;
+            }
+            end_addr = segment.end_addr();
+        }
+        Some((first.start_addr(), end_addr))
+    }
+
+    /// Every byte of `start_addr` - `end_addr` (inclusive) lies in
+    /// segments with no gap between them
+    pub fn is_range_covered(&self, start_addr: u32, end_addr: u32) -> bool {
+        start_addr <= end_addr
+            && self
+                .contiguous_range(start_addr)
+                .is_some_and(|(_, end)| end_addr < end)
+    }
+
+    /// Split `length` bytes from `start_addr` into `(address, length)`
+    /// spans which do not cross segment boundaries
+    pub fn split_at_segments(
+        &self,
+        start_addr: u32,
+        length: u32,
+    ) -> Vec<(u32, u32)> {
+        let end_addr = start_addr as u64 + length as u64;
+        let mut spans = Vec::new();
+        let mut addr = start_addr as u64;
+        while addr < end_addr {
+            // next boundary (segment start or end) after `addr`
+            let boundary = self
+                .segments
+                .iter()
+                .flat_map(|s| [s.start_addr() as u64, s.end_addr() as u64])
+                .filter(|&b| b > addr)
+                .min()
+                .unwrap_or(end_addr)
+                .min(end_addr);
+            spans.push((addr as u32, (boundary - addr) as u32));
+            addr = boundary;
+        }
+        spans
+    }
+
     pub fn get_erase_pages(&self, start_addr: u32, end_addr: u32) -> Vec<u32> {
         self.segments
             .iter()
@@ -70,7 +163,7 @@ impl DfuMemSegment {
         (self.end_addr - self.start_addr) / self.page_size
     }
     pub fn is_contained_in(&self, start_addr: u32, end_addr: u32) -> bool {
-        start_addr <= self.start_addr && self.end_addr < end_addr
+        start_addr <= self.start_addr && self.end_addr - 1 <= end_addr
     }
     pub fn contains(&self, addr: u32) -> bool {
         addr >= self.start_addr && addr < self.end_addr
@@ -81,10 +174,14 @@ impl DfuMemSegment {
         end_addr: u32,
     ) -> (u32, u32) {
         let erase_start = cmp::max(start_addr, self.start_addr);
-        let erase_end = cmp::min(end_addr, self.end_addr);
+        let erase_end = cmp::min(end_addr, self.end_addr - 1);
+
+        // pages are aligned on this segment's own page size
+        let first_page = (erase_start - self.start_addr) / self.page_size;
+        let last_page = (erase_end - self.start_addr) / self.page_size;
         (
-            erase_start,
-            (erase_end + 1 - erase_start).div_ceil(self.page_size()),
+            self.start_addr + first_page * self.page_size,
+            last_page + 1 - first_page,
         )
     }
     pub fn readable(&self) -> bool {
@@ -96,6 +193,48 @@ impl DfuMemSegment {
     pub fn writable(&self) -> bool {
         self.mem_type & 4 == 4
     }
+    /// Access flags as "rwe" combination (ex: "re")
+    pub fn access(&self) -> String {
+        [
+            (self.readable(), 'r'),
+            (self.writable(), 'w'),
+            (self.erasable(), 'e'),
+        ]
+        .iter()
+        .filter_map(|(set, c)| set.then_some(*c))
+        .collect()
+    }
+}
+
+/// Start address of the main flash: the first writable segment of the
+/// first layout named like a flash memory (ex: "Internal Flash")
+pub(crate) fn main_flash_start_address<'a>(
+    layouts: impl IntoIterator<Item = &'a DfuMemory>,
+) -> Option<u32> {
+    layouts
+        .into_iter()
+        .filter(|layout| layout.name.to_lowercase().contains("flash"))
+        .find_map(|layout| layout.segments.iter().find(|s| s.writable()))
+        .map(|s| s.start_addr())
+}
+
+/// Total size of the segments of the layouts named like a flash memory,
+/// segments listed by several layouts are counted once
+pub(crate) fn flash_size<'a>(
+    layouts: impl IntoIterator<Item = &'a DfuMemory>,
+) -> u64 {
+    let mut segments: Vec<(u32, u32)> = layouts
+        .into_iter()
+        .filter(|layout| layout.name.to_lowercase().contains("flash"))
+        .flat_map(|layout| layout.segments.iter())
+        .map(|s| (s.start_addr(), s.end_addr()))
+        .collect();
+    segments.sort_unstable();
+    segments.dedup();
+    segments
+        .iter()
+        .map(|(start, end)| (end - start) as u64)
+        .sum()
 }
 
 pub(crate) fn parse_memory_layout(mem_layout_str: &str) -> Option<DfuMemory> {
@@ -178,6 +317,110 @@ mod tests {
         );
     }
 
+    /// Layout strings from `tests/fixtures/layouts.txt` along with the
+    /// expected memory layout
+    fn load_layout_fixtures() -> Vec<(String, DfuMemory)> {
+        let fixtures = include_str!("../tests/fixtures/layouts.txt");
+        let parse_hex = |s: &str| {
+            u32::from_str_radix(s.trim_start_matches("0x"), 16).unwrap()
+        };
+        fixtures
+            .split("\n\n")
+            .filter_map(|block| {
+                let mut lines =
+                    block.lines().filter(|line| !line.starts_with('#'));
+                let layout = lines.next()?.to_string();
+                let name = lines.next()?.strip_prefix("= ")?.to_string();
+                let segments = lines
+                    .map(|line| {
+                        let fields: Vec<&str> =
+                            line.split_whitespace().collect();
+                        DfuMemSegment {
+                            start_addr: parse_hex(fields[0]),
+                            end_addr: parse_hex(fields[1]),
+                            page_size: fields[2].parse().unwrap(),
+                            mem_type: fields[3].as_bytes()[0] & 7,
+                        }
+                    })
+                    .collect();
+                let segments = NonEmpty::from_vec(segments)?;
+                Some((layout, DfuMemory { name, segments }))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_memory_layout_fixtures() {
+        let fixtures = load_layout_fixtures();
+        assert!(fixtures.len() >= 10);
+        for (layout, expected) in fixtures {
+            assert_eq!(
+                parse_memory_layout(&layout),
+                Some(expected),
+                "{layout}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_main_flash_start_address() {
+        let layouts: Vec<DfuMemory> = [
+            "@Option Bytes  /0x1FFFC000/01*016 e",
+            "@OTP Memory /0x1FFF7800/01*512 e,01*016 e",
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+        ]
+        .into_iter()
+        .filter_map(parse_memory_layout)
+        .collect();
+        assert_eq!(main_flash_start_address(&layouts), Some(0x08000000));
+
+        // read-only flash segments are skipped
+        let layouts: Vec<DfuMemory> = [
+            "@Option Bytes  /0x1FFFC000/01*016 e",
+            "@Internal Flash  /0x08000000/01*016Ka,03*016Kg",
+        ]
+        .into_iter()
+        .filter_map(parse_memory_layout)
+        .collect();
+        assert_eq!(main_flash_start_address(&layouts), Some(0x08004000));
+
+        let layouts: Vec<DfuMemory> = ["@Option Bytes  /0x1FFFC000/01*016 e"]
+            .into_iter()
+            .filter_map(parse_memory_layout)
+            .collect();
+        assert_eq!(main_flash_start_address(&layouts), None);
+    }
+
+    #[test]
+    fn test_flash_size() {
+        let layouts: Vec<DfuMemory> = [
+            "@Option Bytes  /0x1FFFC000/01*016 e",
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+            "@External Flash  /0x90000000/128*064Kg",
+        ]
+        .into_iter()
+        .filter_map(parse_memory_layout)
+        .collect();
+        assert_eq!(flash_size(&layouts), (1024 + 8192) * 1024);
+        assert_eq!(flash_size(&layouts[..1]), 0);
+    }
+
+    #[test]
+    fn test_access() {
+        let access = |layout: &str| {
+            parse_memory_layout(layout)
+                .unwrap()
+                .segments
+                .first()
+                .access()
+        };
+        assert_eq!(access("@Internal Flash   /0x08000000/8*08Kg"), "rwe");
+        assert_eq!(access("@Option Bytes   /0x5200201C/01*128 e"), "rw");
+        assert_eq!(access("@OTP Memory /0x1FF0F000/01*1Ka"), "r");
+        assert_eq!(access("@Flash /0x08000000/01*1Kb"), "e");
+    }
+
     #[test]
     fn test_find_segments() {
         let layout =
@@ -262,4 +505,173 @@ mod tests {
             vec![0x08000000, 0x08002000],
         );
     }
+
+    #[test]
+    fn test_align_range_to_pages() {
+        let layout = parse_memory_layout(
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+        )
+        .unwrap();
+
+        assert_eq!(
+            layout.align_range_to_pages(0x08000000, 0x08003fff),
+            (0x08000000, 0x08003fff)
+        );
+        assert_eq!(
+            layout.align_range_to_pages(0x08000100, 0x080001ff),
+            (0x08000000, 0x08003fff)
+        );
+        assert_eq!(
+            layout.align_range_to_pages(0x08006000, 0x08012000),
+            (0x08004000, 0x0801ffff)
+        );
+        // outside of layout
+        assert_eq!(
+            layout.align_range_to_pages(0x07fff000, 0x09000000),
+            (0x07fff000, 0x09000000)
+        );
+    }
+
+    #[test]
+    fn test_contiguous_writable() {
+        let layout = parse_memory_layout(
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+        )
+        .unwrap();
+        assert_eq!(layout.contiguous_writable(0x08000000), 1024 * 1024);
+        assert_eq!(layout.contiguous_writable(0x08010000), 960 * 1024);
+        assert_eq!(layout.contiguous_writable(0x080fffff), 1);
+        assert_eq!(layout.contiguous_writable(0x08100000), 0);
+        assert_eq!(layout.contiguous_writable(0x07ffffff), 0);
+
+        // read-only segment in the middle
+        let layout = parse_memory_layout(
+            "@Internal Flash  /0x08000000/02*016Kg,01*016Ka,02*016Kg",
+        )
+        .unwrap();
+        assert_eq!(layout.contiguous_writable(0x08000000), 32 * 1024);
+        assert_eq!(layout.contiguous_writable(0x08004000), 16 * 1024);
+        assert_eq!(layout.contiguous_writable(0x08008000), 0);
+        assert_eq!(layout.contiguous_writable(0x0800c000), 32 * 1024);
+
+        // address gap between segments
+        let segment = |start_addr| DfuMemSegment {
+            start_addr,
+            end_addr: start_addr + 0x4000,
+            page_size: 0x4000,
+            mem_type: b'g' & 7,
+        };
+        let layout = DfuMemory {
+            name: "Internal Flash".into(),
+            segments: nonempty![segment(0x08000000), segment(0x08008000)],
+        };
+        assert_eq!(layout.contiguous_writable(0x08000000), 0x4000);
+        assert_eq!(layout.contiguous_writable(0x08004000), 0);
+    }
+
+    #[test]
+    fn test_is_range_covered() {
+        let segment = |start_addr| DfuMemSegment {
+            start_addr,
+            end_addr: start_addr + 0x4000,
+            page_size: 0x4000,
+            mem_type: b'g' & 7,
+        };
+        let layout = DfuMemory {
+            name: "Internal Flash".into(),
+            segments: nonempty![
+                segment(0x08000000),
+                segment(0x08004000),
+                segment(0x0800c000)
+            ],
+        };
+        assert!(layout.is_range_covered(0x08000000, 0x08007fff));
+        assert!(layout.is_range_covered(0x08003000, 0x08004fff));
+        assert!(!layout.is_range_covered(0x08000000, 0x08008000));
+        assert!(!layout.is_range_covered(0x08006000, 0x0800c000));
+        assert!(!layout.is_range_covered(0x07ffffff, 0x08000000));
+        assert!(layout.is_range_covered(0x0800c000, 0x0800ffff));
+        assert!(!layout.is_range_covered(0x08001000, 0x08000000));
+    }
+
+    #[test]
+    fn test_split_at_segments() {
+        let layout = parse_memory_layout(
+            "@Internal Flash  /0x08000000/02*016Kg,01*064Kg",
+        )
+        .unwrap();
+        // read across both segments
+        assert_eq!(
+            layout.split_at_segments(0x08006000, 0x4000),
+            vec![(0x08006000, 0x2000), (0x08008000, 0x2000)]
+        );
+        // within a single segment
+        assert_eq!(
+            layout.split_at_segments(0x08000000, 0x100),
+            vec![(0x08000000, 0x100)]
+        );
+        // past the last segment
+        assert_eq!(
+            layout.split_at_segments(0x08017000, 0x2000),
+            vec![(0x08017000, 0x1000), (0x08018000, 0x1000)]
+        );
+        assert!(layout.split_at_segments(0x08000000, 0).is_empty());
+    }
+
+    #[test]
+    fn test_contiguous_range() {
+        let layout = parse_memory_layout(
+            "@Internal Flash  /0x08000000/02*016Kg,01*016Ka,02*016Kg",
+        )
+        .unwrap();
+        assert_eq!(
+            layout.contiguous_range(0x08006000),
+            Some((0x08000000, 0x08014000))
+        );
+        assert_eq!(layout.contiguous_range(0x08014000), None);
+    }
+
+    #[test]
+    fn test_erase_pages_across_segments() {
+        let layout = parse_memory_layout(
+            "@Internal Flash  /0x08000000/04*016Kg,01*064Kg,07*128Kg",
+        )
+        .unwrap();
+
+        assert_eq!(
+            layout.get_erase_pages(0x08000000, 0x0801ffff),
+            vec![0x08000000, 0x08004000, 0x08008000, 0x0800c000, 0x08010000],
+        );
+
+        assert_eq!(
+            layout.get_erase_pages(0x08006000, 0x08020000),
+            vec![0x08004000, 0x08008000, 0x0800c000, 0x08010000, 0x08020000],
+        );
+    }
+
+    #[test]
+    fn test_erase_pages_external_flash() {
+        let layout =
+            parse_memory_layout("@External Flash /0x90000000/032*064Kg")
+                .unwrap();
+        let segment = layout.segments.first();
+        assert_eq!(segment.page_size(), 64 * 1024);
+        assert_eq!(segment.end_addr(), 0x90000000 + 2 * 1024 * 1024);
+
+        assert_eq!(
+            layout.get_erase_pages(0x90000000, 0x9001ffff),
+            vec![0x90000000, 0x90010000],
+        );
+
+        assert_eq!(
+            layout.get_erase_pages(0x90018000, 0x90028000),
+            vec![0x90010000, 0x90020000],
+        );
+
+        // never erase past the end of the segment
+        assert_eq!(
+            layout.get_erase_pages(0x901f0000, 0x90ffffff),
+            vec![0x901f0000],
+        );
+    }
 }
@@ -0,0 +1,95 @@
+/// Phase of a long running operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Erasing,
+    Writing,
+    Reading,
+    Verifying,
+}
+
+/// Progress of a long running operation
+///
+/// `current` and `total` are expressed in the unit of the phase
+/// (pages when erasing, bytes otherwise).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub phase: Phase,
+    pub current: u64,
+    pub total: u64,
+}
+
+/// Receiver of [Progress] updates
+pub trait ProgressSink {
+    /// Called every time some progress has been made
+    fn update(&mut self, progress: &Progress);
+
+    /// Called once the phase has completed
+    fn finish(&mut self, _progress: &Progress) {}
+}
+
+impl Phase {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Phase::Erasing => "Erasing",
+            Phase::Writing => "Flashing",
+            Phase::Reading => "Reading",
+            Phase::Verifying => "Verifying",
+        }
+    }
+}
+
+impl Progress {
+    pub fn new(phase: Phase, total: u64) -> Self {
+        Progress {
+            phase,
+            current: 0,
+            total,
+        }
+    }
+
+    /// Advance by `amount` units and return the new state
+    pub fn advance(&mut self, amount: u64) -> &Self {
+        self.current = (self.current + amount).min(self.total);
+        self
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.current >= self.total
+    }
+
+    /// Completion percentage (0 - 100)
+    pub fn percentage(&self) -> u64 {
+        self.fraction(100)
+    }
+
+    /// Completion scaled to `width` (ex: number of filled cells in a bar)
+    pub fn fraction(&self, width: u64) -> u64 {
+        (width * self.current)
+            .checked_div(self.total)
+            .unwrap_or(width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let mut progress = Progress::new(Phase::Writing, 200);
+        assert_eq!(progress.percentage(), 0);
+        assert!(!progress.is_done());
+
+        progress.advance(50);
+        assert_eq!(progress.percentage(), 25);
+        assert_eq!(progress.fraction(60), 15);
+
+        progress.advance(500);
+        assert_eq!(progress.current, 200);
+        assert_eq!(progress.percentage(), 100);
+        assert!(progress.is_done());
+
+        let empty = Progress::new(Phase::Erasing, 0);
+        assert_eq!(empty.percentage(), 100);
+    }
+}
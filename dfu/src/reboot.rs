@@ -0,0 +1,65 @@
+//! Reboot payloads understood by EdgeTX bootloaders
+//!
+//! Before jumping to the reboot address, a structure is written at the
+//! reboot tag address to request the bootloader to stay in DFU mode:
+//!
+//! - [RebootFormat::Tag]: the magic value alone (`BDFU`).
+//! - [RebootFormat::Extended]: the magic value followed by flags and
+//!   the jump address (both little endian `u32`), accepted by newer
+//!   bootloaders.
+
+/// Magic value requesting the bootloader to stay in DFU mode
+pub const REBOOT_MAGIC: &[u8] = b"BDFU";
+
+/// Structure written at the reboot tag address
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RebootFormat {
+    /// magic value only
+    #[default]
+    Tag,
+    /// magic value + flags + jump address
+    Extended { flags: u32 },
+}
+
+impl RebootFormat {
+    /// Format matching the reboot flags declared in a UF2 file (if any)
+    pub fn from_flags(flags: Option<u32>) -> Self {
+        match flags {
+            Some(flags) => RebootFormat::Extended { flags },
+            None => RebootFormat::Tag,
+        }
+    }
+
+    /// Payload to be written at the reboot tag address
+    pub fn encode(&self, magic: &[u8], jump_addr: u32) -> Vec<u8> {
+        let mut payload = magic.to_vec();
+        if let RebootFormat::Extended { flags } = self {
+            payload.extend_from_slice(&flags.to_le_bytes());
+            payload.extend_from_slice(&jump_addr.to_le_bytes());
+        }
+        payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            RebootFormat::Tag.encode(REBOOT_MAGIC, 0x0800_0000),
+            b"BDFU"
+        );
+        assert_eq!(
+            RebootFormat::Extended { flags: 0x0102 }
+                .encode(REBOOT_MAGIC, 0x0800_0000),
+            b"BDFU\x02\x01\x00\x00\x00\x00\x00\x08"
+        );
+        assert_eq!(
+            RebootFormat::from_flags(Some(1)),
+            RebootFormat::Extended { flags: 1 }
+        );
+        assert_eq!(RebootFormat::from_flags(None), RebootFormat::Tag);
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::Mutex;
+
+use nusb::transfer::TransferError;
+
+use crate::DfuError;
+use crate::connection::{
+    DFU_CMD_ABORT, DFU_CMD_CLRSTATUS, DFU_CMD_DOWNLOAD, DFU_CMD_GETSTATE,
+    DFU_CMD_GETSTATUS, DFU_CMD_UPLOAD, DFU_STATE_DFU_DOWNLOAD_IDLE,
+    DFU_STATE_DFU_ERROR, DFU_STATE_DFU_IDLE, DFU_STATE_DFU_UPLOAD_IDLE,
+};
+
+/// Control transfer recorded by [DfuConnection::recording()]
+///
+/// [DfuConnection::recording()]: crate::DfuConnection::recording
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ControlTransfer {
+    Out {
+        request: u8,
+        value: u16,
+        data: Vec<u8>,
+    },
+    In {
+        request: u8,
+        value: u16,
+        length: u16,
+    },
+    /// Standard `CLEAR_FEATURE(ENDPOINT_HALT)` request
+    ClearHalt { endpoint: u8 },
+    /// USB port reset (not a control transfer, recorded in sequence)
+    UsbReset,
+}
+
+/// In-memory transport recording every control transfer
+///
+/// Requests are answered as a device without memory errors would,
+/// following the DFU state machine: `GETSTATUS` and `GETSTATE` report the
+/// current state, uploads return zeroes. Requests not allowed in the
+/// current state stall and leave the device in `dfuERROR` (ex: a download
+/// in `dfuUPLOAD-IDLE`, or a zero-length download in `dfuIDLE`).
+pub(crate) struct Recorder {
+    transfers: Mutex<Vec<ControlTransfer>>,
+    state: Mutex<u8>,
+    /// State reached by a zero-length download (ex: `appIDLE` for a
+    /// device resetting right away)
+    manifest_state: Mutex<u8>,
+    /// Requests failing once with this error (ex: device resetting)
+    failures: Mutex<Vec<(u8, TransferError)>>,
+}
+
+// bStatus reported after a stalled request
+const STATUS_ERR_STALLED_PKT: u8 = 0x0f;
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder {
+            transfers: Mutex::default(),
+            state: Mutex::new(DFU_STATE_DFU_IDLE),
+            manifest_state: Mutex::new(DFU_STATE_DFU_IDLE),
+            failures: Mutex::default(),
+        }
+    }
+}
+
+impl Recorder {
+    pub(crate) fn control_out(
+        &self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> Result<(), DfuError> {
+        self.push(ControlTransfer::Out {
+            request,
+            value,
+            data: data.to_vec(),
+        });
+        self.take_failure(request)?;
+        let mut state = self.state.lock().unwrap();
+        *state = match (request, *state) {
+            (DFU_CMD_DOWNLOAD, DFU_STATE_DFU_IDLE) if !data.is_empty() => {
+                DFU_STATE_DFU_DOWNLOAD_IDLE
+            }
+            // a zero-length download manifests the firmware
+            (DFU_CMD_DOWNLOAD, DFU_STATE_DFU_DOWNLOAD_IDLE)
+                if data.is_empty() =>
+            {
+                *self.manifest_state.lock().unwrap()
+            }
+            (DFU_CMD_DOWNLOAD, DFU_STATE_DFU_DOWNLOAD_IDLE) => *state,
+            (
+                DFU_CMD_ABORT,
+                DFU_STATE_DFU_IDLE
+                | DFU_STATE_DFU_DOWNLOAD_IDLE
+                | DFU_STATE_DFU_UPLOAD_IDLE,
+            )
+            | (DFU_CMD_CLRSTATUS, DFU_STATE_DFU_ERROR) => DFU_STATE_DFU_IDLE,
+            (DFU_CMD_DOWNLOAD | DFU_CMD_ABORT | DFU_CMD_CLRSTATUS, _) => {
+                *state = DFU_STATE_DFU_ERROR;
+                return Err(TransferError::Stall.into());
+            }
+            _ => *state,
+        };
+        Ok(())
+    }
+
+    pub(crate) fn control_in(
+        &self,
+        request: u8,
+        value: u16,
+        length: u16,
+    ) -> Result<Vec<u8>, DfuError> {
+        self.push(ControlTransfer::In {
+            request,
+            value,
+            length,
+        });
+        self.take_failure(request)?;
+        let mut state = self.state.lock().unwrap();
+        let reply = match request {
+            DFU_CMD_GETSTATUS => {
+                let status = match *state {
+                    DFU_STATE_DFU_ERROR => STATUS_ERR_STALLED_PKT,
+                    _ => 0,
+                };
+                vec![status, 0, 0, 0, *state, 0]
+            }
+            DFU_CMD_GETSTATE => vec![*state],
+            DFU_CMD_UPLOAD => match *state {
+                DFU_STATE_DFU_IDLE | DFU_STATE_DFU_UPLOAD_IDLE => {
+                    *state = DFU_STATE_DFU_UPLOAD_IDLE;
+                    vec![]
+                }
+                _ => {
+                    *state = DFU_STATE_DFU_ERROR;
+                    return Err(TransferError::Stall.into());
+                }
+            },
+            _ => vec![],
+        };
+        let mut data = vec![0u8; length as usize];
+        let len = data.len().min(reply.len());
+        data[..len].copy_from_slice(&reply[..len]);
+        Ok(data)
+    }
+
+    /// Force the device state (ex: a busy device)
+    #[cfg(test)]
+    pub(crate) fn set_state(&self, state: u8) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_manifest_state(&self, state: u8) {
+        *self.manifest_state.lock().unwrap() = state;
+    }
+
+    /// Make the next `request` fail with `err`
+    #[cfg(test)]
+    pub(crate) fn fail_next(&self, request: u8, err: TransferError) {
+        self.failures.lock().unwrap().push((request, err));
+    }
+
+    fn take_failure(&self, request: u8) -> Result<(), DfuError> {
+        let mut failures = self.failures.lock().unwrap();
+        match failures.iter().position(|&(r, _)| r == request) {
+            Some(pos) => Err(failures.remove(pos).1.into()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn clear_halt(&self, endpoint: u8) {
+        self.push(ControlTransfer::ClearHalt { endpoint });
+    }
+
+    pub(crate) fn reset(&self) {
+        self.push(ControlTransfer::UsbReset);
+    }
+
+    pub(crate) fn transfers(&self) -> Vec<ControlTransfer> {
+        self.transfers.lock().unwrap().clone()
+    }
+
+    fn push(&self, transfer: ControlTransfer) {
+        self.transfers.lock().unwrap().push(transfer);
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+/// Outcome of a download (erase and write) operation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DownloadResult {
+    /// Bytes written, padding included
+    pub bytes_written: u64,
+    pub pages_erased: u64,
+    pub elapsed: Duration,
+}
+
+/// Outcome of an upload (read) operation
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UploadResult {
+    pub start_address: u32,
+    pub bytes_read: u64,
+    pub elapsed: Duration,
+}
+
+impl DownloadResult {
+    /// Accumulate the result of another range (ex: several UF2 ranges)
+    pub fn add(&mut self, other: &DownloadResult) {
+        self.bytes_written += other.bytes_written;
+        self.pages_erased += other.pages_erased;
+        self.elapsed += other.elapsed;
+    }
+
+    /// Average write throughput, erase time included
+    pub fn kb_per_second(&self) -> f64 {
+        kb_per_second(self.bytes_written, self.elapsed)
+    }
+}
+
+impl UploadResult {
+    /// Average read throughput
+    pub fn kb_per_second(&self) -> f64 {
+        kb_per_second(self.bytes_read, self.elapsed)
+    }
+}
+
+fn kb_per_second(bytes: u64, elapsed: Duration) -> f64 {
+    (bytes as f64 / 1024.0) / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_download_result() {
+        let mut result = DownloadResult {
+            bytes_written: 1024,
+            pages_erased: 1,
+            elapsed: Duration::from_millis(500),
+        };
+        result.add(&DownloadResult {
+            bytes_written: 3072,
+            pages_erased: 2,
+            elapsed: Duration::from_millis(1500),
+        });
+        assert_eq!(result.bytes_written, 4096);
+        assert_eq!(result.pages_erased, 3);
+        assert_eq!(result.elapsed, Duration::from_secs(2));
+        assert_eq!(result.kb_per_second(), 2.0);
+    }
+
+    #[test]
+    fn test_upload_result() {
+        let result = UploadResult {
+            start_address: 0x08000000,
+            bytes_read: 2048,
+            elapsed: Duration::from_millis(250),
+        };
+        assert_eq!(result.kb_per_second(), 8.0);
+
+        // no division by zero on instant operations
+        let result = UploadResult::default();
+        assert_eq!(result.kb_per_second(), 0.0);
+    }
+}
@@ -0,0 +1,91 @@
+use nusb::{self, MaybeFuture};
+
+use crate::{
+    DfuConnection, DfuDescriptor, DfuError,
+    device::{DFU_CLASS, DFU_PROTOCOL_RUNTIME, DFU_SUBCLASS, serial_matches},
+};
+
+/// Device running its application and exposing a DFU runtime interface
+///
+/// Such a device has no memory layout: it can only be asked to switch to
+/// DFU mode (see [DfuRuntimeDevice::detach()]), after which it enumerates
+/// again as a [crate::DfuDevice].
+pub struct DfuRuntimeDevice {
+    dev: nusb::DeviceInfo,
+    interface: u8,
+}
+
+impl DfuRuntimeDevice {
+    /// Build a runtime device from an already enumerated USB device
+    ///
+    /// Returns `None` if the device has no DFU runtime interface.
+    pub fn from_device_info(dev: nusb::DeviceInfo) -> Option<Self> {
+        let interface = dev
+            .interfaces()
+            .find(|i| {
+                i.class() == DFU_CLASS
+                    && i.subclass() == DFU_SUBCLASS
+                    && i.protocol() == DFU_PROTOCOL_RUNTIME
+            })?
+            .interface_number();
+        Some(DfuRuntimeDevice { dev, interface })
+    }
+
+    pub fn device_info(&self) -> &nusb::DeviceInfo {
+        &self.dev
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.dev.vendor_id()
+    }
+
+    pub fn product_id(&self) -> u16 {
+        self.dev.product_id()
+    }
+
+    /// Number of the DFU runtime interface
+    pub fn interface(&self) -> u8 {
+        self.interface
+    }
+
+    /// Ask the device to switch to DFU mode, resetting it unless it
+    /// detaches by itself (see [DfuConnection::detach()])
+    ///
+    /// Returns whether the device detaches by itself.
+    pub fn detach(&self) -> Result<bool, DfuError> {
+        let dev = self.dev.open().wait()?;
+        let desc = dev
+            .configurations()
+            .flat_map(|config| {
+                config
+                    .interface_alt_settings()
+                    .filter(|alt| alt.interface_number() == self.interface)
+                    .flat_map(|alt| alt.descriptors())
+                    .find_map(|desc| DfuDescriptor::parse(&desc))
+            })
+            .next()
+            .unwrap_or_default();
+        let interface = dev.claim_interface(self.interface).wait()?;
+        DfuConnection::new(dev, interface, desc.transfer_size())
+            .detach(&desc)?;
+        Ok(desc.will_detach())
+    }
+}
+
+/// USB devices exposing a DFU runtime interface, matching the given IDs
+/// and serial number (case-insensitive)
+pub fn list_runtime_devices(
+    vid: Option<u16>,
+    pid: Option<u16>,
+    serial: Option<&str>,
+) -> Result<Vec<DfuRuntimeDevice>, DfuError> {
+    Ok(nusb::list_devices()
+        .wait()?
+        .filter(|dev| {
+            vid.is_none_or(|id| dev.vendor_id() == id)
+                && pid.is_none_or(|id| dev.product_id() == id)
+                && serial_matches(dev.serial_number(), serial)
+        })
+        .filter_map(DfuRuntimeDevice::from_device_info)
+        .collect())
+}
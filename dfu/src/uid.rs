@@ -0,0 +1,46 @@
+//! STM32 96-bit unique device ID
+//!
+//! The UID base address differs between families. The family is detected
+//! from the option bytes region exposed by the bootloader, whose address
+//! is specific to each family.
+
+/// Length of the unique device ID in bytes
+pub const UID_LEN: usize = 12;
+
+// (option bytes start address, UID base address)
+const UID_ADDRESSES: [(u32, u32); 3] = [
+    // STM32F2 / STM32F4
+    (0x1fff_c000, 0x1fff_7a10),
+    // STM32F7
+    (0x1fff_0000, 0x1ff0_f420),
+    // STM32H7
+    (0x5200_201c, 0x1ff1_e800),
+];
+
+/// UID base address for the family exposing a segment starting at one
+/// of `segment_starts`
+pub(crate) fn uid_address(
+    mut segment_starts: impl Iterator<Item = u32>,
+) -> Option<u32> {
+    segment_starts.find_map(|start| {
+        UID_ADDRESSES
+            .iter()
+            .find(|(option_bytes, _)| *option_bytes == start)
+            .map(|(_, uid)| *uid)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uid_address() {
+        assert_eq!(
+            uid_address([0x0800_0000, 0x1fff_c000].into_iter()),
+            Some(0x1fff_7a10)
+        );
+        assert_eq!(uid_address([0x5200_201c].into_iter()), Some(0x1ff1_e800));
+        assert_eq!(uid_address([0x0800_0000, 0x9000_0000].into_iter()), None);
+    }
+}
@@ -8,6 +8,7 @@
 #include <fstream>
 #include <iostream>
 #include <stdexcept>
+#include <thread>
 #include <vector>
 
 using namespace std::chrono_literals;
@@ -33,15 +34,13 @@ void print_devices(const Vec<DfuDevice> &devices) {
   }
 }
 
-void update_erase_status(size_t page, size_t pages) {
-  fmt::print("\r  Erasing page {:2} of {:2}{}", page, pages,
-             page == pages ? "\n" : "");
-  fflush(stdout);
-}
-
-void update_download_status(size_t bytes, size_t total) {
-  int percent = (100 * bytes) / total;
-  fmt::print("\r  Flashing {:3}%{}", percent, percent == 100 ? "\n" : "");
+void update_status(const Progress &progress) {
+  const char *label = progress.phase == ProgressPhase::Erasing ? "Erasing"
+                                                               : "Flashing";
+  auto percent = progress.total ? (100 * progress.current) / progress.total
+                                : 100;
+  fmt::print("\r  {} {:3}%{}", label, percent,
+             progress.current == progress.total ? "\n" : "");
   fflush(stdout);
 }
 
@@ -50,12 +49,9 @@ int write_region(const DfuDevice &device, uint32_t addr, const SliceU8 &data) {
   auto end_address = start_address + data.size() - 1;
 
   auto ctx = device.start_download(start_address, end_address);
-  auto erase_pages = ctx->get_erase_pages();
-  auto pages = erase_pages.size();
-
-  for (size_t i = 0; i < erase_pages.size(); i++) {
-    update_erase_status(i + 1, pages);
-    ctx->page_erase(erase_pages[i]);
+  for (auto page : ctx->get_erase_pages()) {
+    ctx->page_erase(page);
+    update_status(ctx->progress());
   }
 
   size_t bytes_downloaded = 0;
@@ -66,12 +62,15 @@ int write_region(const DfuDevice &device, uint32_t addr, const SliceU8 &data) {
     auto single_xfer_size =
         std::min(uint32_t(xfer_size), uint32_t(data.size() - bytes_downloaded));
     bytes_downloaded += single_xfer_size;
-    update_download_status(bytes_downloaded, data.size());
     ctx->download(addr, SliceU8(data_ptr, single_xfer_size));
+    update_status(ctx->progress());
     addr += single_xfer_size;
     data_ptr += single_xfer_size;
   }
 
+  auto result = ctx->result();
+  fmt::println("  {} bytes written, {} pages erased in {} ms",
+               result.bytes_written, result.pages_erased, result.elapsed_ms);
   return 0;
 }
 
@@ -80,14 +79,21 @@ void reboot_and_rediscover(DfuDevice &device, uint32_t addr,
                            const SliceU8 &data, uint32_t reboot_addr,
                            Duration timeout) {
   fmt::println("Rebooting into DFU...");
-  device.reboot(addr, data, reboot_addr);
-  auto start = std::chrono::steady_clock::now();
-  while (std::chrono::steady_clock::now() - start < timeout) {
-    if (device.rediscover()) {
+  auto timeout_ms =
+      std::chrono::duration_cast<std::chrono::milliseconds>(timeout).count();
+  auto reboot = device.start_reboot(addr, data, reboot_addr, timeout_ms);
+  fmt::println("Waiting for device to reconnect...");
+  while (true) {
+    switch (reboot->poll(device)) {
+    case RebootState::Reconnected:
+      fmt::println("Device reconnected after {} ms", reboot->elapsed_ms());
       return;
+    case RebootState::TimedOut:
+      throw std::runtime_error("timeout while reconnection to device");
+    default:
+      std::this_thread::sleep_for(std::chrono::milliseconds(200));
     }
   }
-  throw std::runtime_error("timeout while reconnection to device");
 }
 
 int main(int argc, char *argv[]) {
@@ -1,21 +1,79 @@
 //! Main crate
 
+use std::time::{Duration, Instant};
+
 #[cxx::bridge]
 mod ffi {
 
     struct DeviceInfo {
         vendor_id: u16,
         product_id: u16,
+        manufacturer_string: String,
         product_string: String,
+        serial_number: String,
+        bcd_device: u16,
+    }
+
+    struct DeviceSummary {
+        interfaces: u32,
+        readable: bool,
+        writable: bool,
+        erasable: bool,
+        flash_size: u64,
+        dfuse: bool,
     }
 
     struct MemorySegment {
         start_addr: u32,
         end_addr: u32,
         page_size: u32,
+        pages: u32,
         readable: bool,
         writable: bool,
         erasable: bool,
+        access: String,
+    }
+
+    struct DfuDescriptorInfo {
+        attributes: u8,
+        can_download: bool,
+        can_upload: bool,
+        manifestation_tolerant: bool,
+        will_detach: bool,
+        detach_timeout: u16,
+        transfer_size: u16,
+        dfu_version: u16,
+    }
+
+    enum ProgressPhase {
+        Erasing,
+        Writing,
+        Reading,
+        Verifying,
+    }
+
+    enum RebootState {
+        WaitingForDevice,
+        Reconnected,
+        TimedOut,
+    }
+
+    struct Progress {
+        phase: ProgressPhase,
+        current: u64,
+        total: u64,
+    }
+
+    struct DownloadResult {
+        bytes_written: u64,
+        pages_erased: u64,
+        elapsed_ms: u64,
+    }
+
+    struct UploadResult {
+        start_address: u32,
+        bytes_read: u64,
+        elapsed_ms: u64,
     }
 
     extern "Rust" {
@@ -26,6 +84,7 @@ mod ffi {
 
         fn with_vendor_id(&mut self, vid: u16);
         fn with_product_id(&mut self, pid: u16);
+        fn with_serial_number(&mut self, serial: String);
         fn find_devices(&self) -> Result<Vec<DfuDevice>>;
     }
 
@@ -33,9 +92,30 @@ mod ffi {
         type DfuDevice;
 
         fn device_info(&self) -> DeviceInfo;
-        fn interfaces(&self) -> Vec<DfuInterface>;
+        fn descriptor_info(&self) -> Result<DfuDescriptorInfo>;
+        fn summary(&self) -> DeviceSummary;
+        fn interfaces(&self) -> &Vec<DfuInterface>;
         fn reset_state(&self) -> Result<()>;
         fn default_start_address(&self) -> u32;
+        fn can_upload(&self) -> Result<bool>;
+        fn can_download(&self) -> Result<bool>;
+        fn locate(
+            &self,
+            address: u32,
+            interface: &mut u8,
+            alt_setting: &mut u8,
+            segment: &mut MemorySegment,
+        ) -> bool;
+        fn is_range_covered(
+            &self,
+            start_address: u32,
+            end_address: u32,
+        ) -> bool;
+        fn estimate_flash_time_ms(
+            &self,
+            start_address: u32,
+            end_address: u32,
+        ) -> Result<u64>;
         fn start_upload(
             &self,
             start_address: u32,
@@ -52,10 +132,25 @@ mod ffi {
             data: &[u8],
             reboot_addr: u32,
         ) -> Result<()>;
+        fn start_reboot(
+            &self,
+            addr: u32,
+            data: &[u8],
+            reboot_addr: u32,
+            timeout_ms: u32,
+        ) -> Result<Box<DfuReboot>>;
         fn rediscover(&mut self) -> Result<bool>;
         fn leave(&self) -> Result<()>;
     }
 
+    extern "Rust" {
+        type DfuReboot;
+
+        fn state(&self) -> RebootState;
+        fn elapsed_ms(&self) -> u64;
+        fn poll(&mut self, device: &mut DfuDevice) -> Result<RebootState>;
+    }
+
     extern "Rust" {
         type DfuInterface;
 
@@ -70,6 +165,8 @@ mod ffi {
 
         fn get_length(&self) -> u32;
         fn get_transfer_size(&self) -> u16;
+        fn progress(&self) -> Progress;
+        fn result(&self) -> UploadResult;
         fn upload(&mut self, length: u16) -> Result<Vec<u8>>;
     }
 
@@ -78,8 +175,10 @@ mod ffi {
 
         fn get_erase_pages(&self) -> Vec<u32>;
         fn get_transfer_size(&self) -> u16;
-        fn page_erase(&self, addr: u32) -> Result<()>;
-        fn download(&self, addr: u32, data: &[u8]) -> Result<()>;
+        fn progress(&self) -> Progress;
+        fn result(&self) -> DownloadResult;
+        fn page_erase(&mut self, addr: u32) -> Result<()>;
+        fn download(&mut self, addr: u32, data: &[u8]) -> Result<()>;
     }
 
     extern "Rust" {
@@ -104,6 +203,8 @@ mod ffi {
         fn reboot_address(self: &UF2AddressRange, addr: &mut u32) -> bool;
 
         fn is_uf2_payload(data: &[u8]) -> bool;
+        fn uf2_device_description(data: &[u8]) -> String;
+        fn uf2_matches_target(device_description: &str, target: &str) -> bool;
     }
 }
 
@@ -111,25 +212,46 @@ mod ffi {
 pub struct DfuDeviceFilter {
     vid: Option<u16>,
     pid: Option<u16>,
+    serial: Option<String>,
 }
 
 pub struct DfuDevice {
     inner: dfu::DfuDevice,
+    // converted once, as the FFI queries them repeatedly
+    interfaces: Vec<DfuInterface>,
 }
 
 pub struct DfuInterface {
     inner: dfu::DfuInterface,
 }
 
+/// Upload in progress (dropping it aborts the transfer, see
+/// [dfu::DfuConnection])
 pub struct DfuUpload {
     connection: dfu::DfuConnection,
     length: u32,
     block_nr: u16,
+    progress: dfu::Progress,
+    start_address: u32,
+    start: Instant,
+}
+
+/// Reboot in progress, polled until the device has reconnected
+pub struct DfuReboot {
+    state: ffi::RebootState,
+    start: Instant,
+    timeout: Duration,
 }
 
+/// Download in progress (dropping it before all the data is written aborts
+/// the transfer, see [dfu::DfuConnection])
 pub struct DfuDownload {
     connection: dfu::DfuConnection,
     erase_pages: Vec<u32>,
+    length: u32,
+    progress: dfu::Progress,
+    result: dfu::DownloadResult,
+    start: Instant,
 }
 
 impl DfuDeviceFilter {
@@ -145,31 +267,64 @@ impl DfuDeviceFilter {
         self.pid.replace(pid);
     }
 
+    fn with_serial_number(&mut self, serial: String) {
+        self.serial.replace(serial);
+    }
+
     fn find_devices(&self) -> Result<Vec<DfuDevice>, dfu::DfuError> {
-        dfu::find_dfu_devices(self.vid, self.pid)
+        dfu::find_dfu_devices(self.vid, self.pid, self.serial.clone())
             .map(|devices| devices.into_iter().map(DfuDevice::new).collect())
     }
 }
 
 impl DfuDevice {
     fn new(device: dfu::DfuDevice) -> Self {
-        DfuDevice { inner: device }
+        let interfaces = Self::convert_interfaces(&device);
+        DfuDevice {
+            inner: device,
+            interfaces,
+        }
+    }
+
+    fn convert_interfaces(device: &dfu::DfuDevice) -> Vec<DfuInterface> {
+        device
+            .interfaces()
+            .iter()
+            .map(|intf| DfuInterface::new(intf.to_owned()))
+            .collect()
     }
 
     fn device_info(&self) -> ffi::DeviceInfo {
         ffi::DeviceInfo {
             vendor_id: self.inner.vendor_id(),
             product_id: self.inner.product_id(),
+            manufacturer_string: self
+                .inner
+                .manufacturer_string()
+                .unwrap_or("")
+                .into(),
             product_string: self.inner.product_string().unwrap_or("").into(),
+            serial_number: self.inner.serial_number().unwrap_or("").into(),
+            bcd_device: self.inner.bcd_device(),
         }
     }
 
-    fn interfaces(&self) -> Vec<DfuInterface> {
-        self.inner
-            .interfaces()
-            .iter()
-            .map(|intf| DfuInterface::new(intf.to_owned()))
-            .collect()
+    fn descriptor_info(&self) -> Result<ffi::DfuDescriptorInfo, dfu::DfuError> {
+        let desc = self.inner.dfu_descriptor()?;
+        Ok(ffi::DfuDescriptorInfo {
+            attributes: desc.attributes(),
+            can_download: desc.can_download(),
+            can_upload: desc.can_upload(),
+            manifestation_tolerant: desc.manifestation_tolerant(),
+            will_detach: desc.will_detach(),
+            detach_timeout: desc.detach_timeout(),
+            transfer_size: desc.transfer_size(),
+            dfu_version: desc.dfu_version(),
+        })
+    }
+
+    fn interfaces(&self) -> &Vec<DfuInterface> {
+        &self.interfaces
     }
 
     fn reset_state(&self) -> Result<(), dfu::DfuError> {
@@ -177,10 +332,66 @@ impl DfuDevice {
         connection.reset_state()
     }
 
+    fn summary(&self) -> ffi::DeviceSummary {
+        let summary = self.inner.summary();
+        ffi::DeviceSummary {
+            interfaces: summary.interfaces as u32,
+            readable: summary.readable,
+            writable: summary.writable,
+            erasable: summary.erasable,
+            flash_size: summary.flash_size,
+            dfuse: summary.dfuse,
+        }
+    }
+
     fn default_start_address(&self) -> u32 {
         self.inner.get_default_start_address()
     }
 
+    fn can_upload(&self) -> Result<bool, dfu::DfuError> {
+        self.inner.can_upload()
+    }
+
+    fn can_download(&self) -> Result<bool, dfu::DfuError> {
+        self.inner.can_download()
+    }
+
+    /// The range (inclusive) is entirely backed by the contiguous
+    /// segments of one interface
+    fn is_range_covered(&self, start_address: u32, end_address: u32) -> bool {
+        self.inner.interfaces().iter().any(|intf| {
+            intf.layout().is_range_covered(start_address, end_address)
+        })
+    }
+
+    fn locate(
+        &self,
+        address: u32,
+        interface: &mut u8,
+        alt_setting: &mut u8,
+        segment: &mut ffi::MemorySegment,
+    ) -> bool {
+        match self.inner.locate(address) {
+            Some((intf, alt, seg)) => {
+                *interface = intf;
+                *alt_setting = alt;
+                *segment = ffi::MemorySegment::from_dfu_segment(seg);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn estimate_flash_time_ms(
+        &self,
+        start_address: u32,
+        end_address: u32,
+    ) -> Result<u64, dfu::DfuError> {
+        let duration =
+            self.inner.estimate_flash_time(start_address, end_address)?;
+        Ok(duration.as_millis() as u64)
+    }
+
     fn start_upload(
         &self,
         start_address: u32,
@@ -191,22 +402,18 @@ impl DfuDevice {
         } else {
             None
         };
-        let intf = self.inner.find_interface(start_address, end_address)?;
-        let segments = intf.find_segments(start_address, end_address);
-        if segments.is_empty() {
-            return Err(dfu::DfuError::NoMemorySegments);
-        }
-
-        let end_address =
-            end_address.unwrap_or(segments.last().unwrap().end_addr() - 1);
+        let (connection, intf_segments) =
+            self.inner.connect_for_address(start_address, end_address)?;
+        let end_address = end_address.unwrap_or(intf_segments.end_addr() - 1);
         let length = end_address - start_address + 1;
-        let connection =
-            self.inner.connect(intf.interface(), intf.alt_setting())?;
 
         Ok(Box::new(DfuUpload {
             connection,
             length,
             block_nr: 0,
+            progress: dfu::Progress::new(dfu::Phase::Reading, length as u64),
+            start_address,
+            start: Instant::now(),
         }))
     }
 
@@ -215,15 +422,20 @@ impl DfuDevice {
         start_address: u32,
         end_address: u32,
     ) -> Result<Box<DfuDownload>, dfu::DfuError> {
-        let intf = self
+        let (connection, intf) = self
             .inner
-            .find_interface(start_address, Some(end_address))?;
+            .connect_for_address(start_address, Some(end_address))?;
+        intf.check_writable_range(start_address, end_address)?;
         let erase_pages = intf.get_erase_pages(start_address, end_address);
-        let connection =
-            self.inner.connect(intf.interface(), intf.alt_setting())?;
+        let progress =
+            dfu::Progress::new(dfu::Phase::Erasing, erase_pages.len() as u64);
         Ok(Box::new(DfuDownload {
             connection,
             erase_pages,
+            length: end_address - start_address + 1,
+            progress,
+            result: dfu::DownloadResult::default(),
+            start: Instant::now(),
         }))
     }
 
@@ -237,16 +449,32 @@ impl DfuDevice {
         connection.reboot(addr, data, reboot_addr)
     }
 
+    fn start_reboot(
+        &self,
+        addr: u32,
+        data: &[u8],
+        reboot_addr: u32,
+        timeout_ms: u32,
+    ) -> Result<Box<DfuReboot>, dfu::DfuError> {
+        self.reboot(addr, data, reboot_addr)?;
+        Ok(Box::new(DfuReboot {
+            state: ffi::RebootState::WaitingForDevice,
+            start: Instant::now(),
+            timeout: if timeout_ms > 0 {
+                Duration::from_millis(timeout_ms as u64)
+            } else {
+                dfu::REDISCOVER_TIMEOUT
+            },
+        }))
+    }
+
     fn rediscover(&mut self) -> Result<bool, dfu::DfuError> {
-        let devices = dfu::find_dfu_devices(
-            Some(self.inner.vendor_id()),
-            Some(self.inner.product_id()),
-        )?;
-        Ok(if !devices.is_empty() {
-            self.inner = devices.into_iter().next().unwrap();
-            true
-        } else {
-            false
+        Ok(match self.inner.rediscover()? {
+            Some(device) => {
+                *self = DfuDevice::new(device);
+                true
+            }
+            None => false,
         })
     }
 
@@ -256,6 +484,33 @@ impl DfuDevice {
     }
 }
 
+impl DfuReboot {
+    fn state(&self) -> ffi::RebootState {
+        self.state
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    /// Look for the device once, updating `device` when it has reconnected
+    fn poll(
+        &mut self,
+        device: &mut DfuDevice,
+    ) -> Result<ffi::RebootState, dfu::DfuError> {
+        if self.state == ffi::RebootState::WaitingForDevice {
+            // a device still initializing may fail to open: retried
+            // until the timeout elapses
+            if device.rediscover().unwrap_or(false) {
+                self.state = ffi::RebootState::Reconnected;
+            } else if self.start.elapsed() >= self.timeout {
+                self.state = ffi::RebootState::TimedOut;
+            }
+        }
+        Ok(self.state)
+    }
+}
+
 impl DfuInterface {
     fn new(interface: dfu::DfuInterface) -> Self {
         DfuInterface { inner: interface }
@@ -292,9 +547,23 @@ impl DfuUpload {
         self.length
     }
 
+    fn progress(&self) -> ffi::Progress {
+        ffi::Progress::from(&self.progress)
+    }
+
+    /// Bytes read so far and time elapsed since the upload started
+    fn result(&self) -> ffi::UploadResult {
+        ffi::UploadResult::from(&dfu::UploadResult {
+            start_address: self.start_address,
+            bytes_read: self.progress.current,
+            elapsed: self.start.elapsed(),
+        })
+    }
+
     fn upload(&mut self, length: u16) -> Result<Vec<u8>, dfu::DfuError> {
         let data = self.connection.upload(self.block_nr, length)?;
         self.block_nr += 1;
+        self.progress.advance(data.len() as u64);
         Ok(data)
     }
 }
@@ -308,12 +577,43 @@ impl DfuDownload {
         self.connection.transfer_size()
     }
 
-    fn page_erase(&self, addr: u32) -> Result<(), dfu::DfuError> {
-        self.connection.dfuse_page_erase(addr)
+    fn progress(&self) -> ffi::Progress {
+        ffi::Progress::from(&self.progress)
+    }
+
+    /// Pages erased and bytes written so far, and time elapsed since the
+    /// download started
+    fn result(&self) -> ffi::DownloadResult {
+        ffi::DownloadResult::from(&dfu::DownloadResult {
+            elapsed: self.start.elapsed(),
+            ..self.result
+        })
+    }
+
+    fn page_erase(&mut self, addr: u32) -> Result<(), dfu::DfuError> {
+        self.connection.dfuse_page_erase(addr)?;
+        self.progress.advance(1);
+        self.result.pages_erased += 1;
+        Ok(())
     }
 
-    fn download(&self, addr: u32, data: &[u8]) -> Result<(), dfu::DfuError> {
-        self.connection.download(addr, data)
+    fn download(
+        &mut self,
+        addr: u32,
+        data: &[u8],
+    ) -> Result<(), dfu::DfuError> {
+        self.connection.download(addr, data)?;
+        if self.progress.phase != dfu::Phase::Writing {
+            self.progress =
+                dfu::Progress::new(dfu::Phase::Writing, self.length as u64);
+        }
+        self.progress.advance(data.len() as u64);
+        self.result.bytes_written += data.len() as u64;
+        if self.progress.is_done() {
+            // ready for `leave`, not aborted on drop
+            self.connection.complete_download();
+        }
+        Ok(())
     }
 }
 
@@ -323,9 +623,46 @@ impl ffi::MemorySegment {
             start_addr: segment.start_addr(),
             end_addr: segment.end_addr(),
             page_size: segment.page_size(),
+            pages: segment.pages(),
             readable: segment.readable(),
             writable: segment.writable(),
             erasable: segment.erasable(),
+            access: segment.access(),
+        }
+    }
+}
+
+impl From<&dfu::Progress> for ffi::Progress {
+    fn from(progress: &dfu::Progress) -> Self {
+        ffi::Progress {
+            phase: match progress.phase {
+                dfu::Phase::Erasing => ffi::ProgressPhase::Erasing,
+                dfu::Phase::Writing => ffi::ProgressPhase::Writing,
+                dfu::Phase::Reading => ffi::ProgressPhase::Reading,
+                dfu::Phase::Verifying => ffi::ProgressPhase::Verifying,
+            },
+            current: progress.current,
+            total: progress.total,
+        }
+    }
+}
+
+impl From<&dfu::DownloadResult> for ffi::DownloadResult {
+    fn from(result: &dfu::DownloadResult) -> Self {
+        ffi::DownloadResult {
+            bytes_written: result.bytes_written,
+            pages_erased: result.pages_erased,
+            elapsed_ms: result.elapsed.as_millis() as u64,
+        }
+    }
+}
+
+impl From<&dfu::UploadResult> for ffi::UploadResult {
+    fn from(result: &dfu::UploadResult) -> Self {
+        ffi::UploadResult {
+            start_address: result.start_address,
+            bytes_read: result.bytes_read,
+            elapsed_ms: result.elapsed.as_millis() as u64,
         }
     }
 }
@@ -348,6 +685,7 @@ impl<'a> UF2RangeIterator<'a> {
         }))
     }
 
+    /// Move the next range into `addr_range` (payloads are never copied)
     fn next(&mut self, addr_range: &mut UF2AddressRange) -> bool {
         match self.inner.next() {
             Some(elmt) => {
@@ -368,6 +706,7 @@ impl UF2AddressRange {
         self.inner.start_address
     }
 
+    /// Borrowed payload, valid until `addr_range` is advanced or dropped
     fn payload(self: &UF2AddressRange) -> &[u8] {
         &self.inner.payload
     }
@@ -386,3 +725,11 @@ impl UF2AddressRange {
 pub fn is_uf2_payload(data: &[u8]) -> bool {
     uf2::is_uf2_payload(data)
 }
+
+pub fn uf2_device_description(data: &[u8]) -> String {
+    uf2::device_description(data).unwrap_or_default()
+}
+
+pub fn uf2_matches_target(device_description: &str, target: &str) -> bool {
+    uf2::matches_target(device_description, target)
+}
@@ -0,0 +1,57 @@
+use crate::*;
+
+/// Fields of the device extension
+///
+/// The description is assumed to be formatted as
+/// `<firmware> on <board>[;<variant>]`, fields being separated by `;`
+/// (ex: "EdgeTX on Radiomaster TX16S;flysky"). Descriptions without " on "
+/// are taken as the board name alone.
+pub struct UF2DeviceDescription {
+    raw: String,
+    firmware: Option<String>,
+    board: String,
+    variant: Option<String>,
+}
+
+impl UF2DeviceDescription {
+    pub fn parse(description: &str) -> Self {
+        let mut fields = description.split(';').map(str::trim);
+        let main = fields.next().unwrap_or_default();
+        let (firmware, board) = match main.split_once(" on ") {
+            Some((firmware, board)) => {
+                (Some(firmware.trim().to_string()), board.trim())
+            }
+            None => (None, main),
+        };
+        UF2DeviceDescription {
+            raw: description.to_string(),
+            firmware,
+            board: board.to_string(),
+            variant: fields.next().filter(|v| !v.is_empty()).map(Into::into),
+        }
+    }
+
+    /// Description as stored in the file
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Firmware name (ex: "EdgeTX")
+    pub fn firmware(&self) -> Option<&str> {
+        self.firmware.as_deref()
+    }
+
+    /// Board name (ex: "Radiomaster TX16S")
+    pub fn board(&self) -> &str {
+        &self.board
+    }
+
+    pub fn variant(&self) -> Option<&str> {
+        self.variant.as_deref()
+    }
+
+    /// Same as [matches_target()] on the board name only
+    pub fn matches_target(&self, target: &str) -> bool {
+        matches_target(&self.board, target)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::*;
+
+/// Payload size of the blocks written by [encode()]
+pub const UF2_DEFAULT_PAYLOAD_SIZE: usize = 256;
+
+/// Encode a raw binary to UF2 blocks of [UF2_DEFAULT_PAYLOAD_SIZE] bytes
+/// starting at `base_address`, tagged with `family_id` if given
+///
+/// Fails if the data does not fit in the 32-bit address space.
+pub fn encode(
+    data: &[u8],
+    base_address: u32,
+    family_id: Option<u32>,
+) -> Result<Vec<u8>, UF2DecodeError> {
+    let end = u32::try_from(data.len())
+        .ok()
+        .and_then(|len| base_address.checked_add(len.checked_sub(1)?));
+    if end.is_none() && !data.is_empty() {
+        return Err(UF2DecodeError::new(format!(
+            "{} bytes from {base_address:#010x} exceed the address space",
+            data.len()
+        )));
+    }
+    let total_blocks = data.chunks(UF2_DEFAULT_PAYLOAD_SIZE).len() as u32;
+    let flags = match family_id {
+        Some(_) => UF2Flags::FAMILY_ID_PRESENT,
+        None => 0,
+    };
+    let mut encoded =
+        Vec::with_capacity(total_blocks as usize * UF2_BLOCK_SIZE);
+    for (i, payload) in data.chunks(UF2_DEFAULT_PAYLOAD_SIZE).enumerate() {
+        let offset = (i * UF2_DEFAULT_PAYLOAD_SIZE) as u32;
+        let header = [
+            UF2_MAGIC_START1,
+            UF2_MAGIC_START2,
+            flags,
+            base_address + offset,
+            payload.len() as u32,
+            i as u32,
+            total_blocks,
+            family_id.unwrap_or(0),
+        ];
+        let mut block = [0u8; UF2_BLOCK_SIZE];
+        for (j, value) in header.iter().enumerate() {
+            block[j * 4..(j + 1) * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        block[UF2_HEADER_SIZE..UF2_HEADER_SIZE + payload.len()]
+            .copy_from_slice(payload);
+        block[UF2_BLOCK_SIZE - 4..]
+            .copy_from_slice(&UF2_MAGIC_FINAL.to_le_bytes());
+        encoded.extend_from_slice(&block);
+    }
+    Ok(encoded)
+}
+
+/// Main flash content of a UF2 file flattened by [flatten()]
+pub struct UF2Image {
+    pub start_address: u32,
+    pub data: Vec<u8>,
+    /// Bytes filled in the gaps between ranges
+    pub filled: u32,
+}
+
+/// Flatten the main flash blocks (reboot and other non-flash blocks are
+/// ignored) to a single image starting at the lowest address, gaps being
+/// filled with `fill`
+///
+/// Images larger than `max_size` bytes are refused before allocating them
+/// (ex: ranges in both internal and external flash, far apart).
+pub fn flatten(
+    data: &[u8],
+    mode: UF2DecodeMode,
+    fill: u8,
+    max_size: u32,
+) -> Result<UF2Image, UF2DecodeError> {
+    let (start, end) = flash_extent_with_mode(data, mode)?;
+    if end - start > max_size {
+        return Err(UF2DecodeError::new(format!(
+            "flattened image would be {} bytes ({start:#010x} - {end:#010x}), \
+            more than {max_size} bytes",
+            end - start
+        )));
+    }
+    let mut image = vec![fill; (end - start) as usize];
+    let mut covered = 0;
+    for chunk in data.chunks(UF2_BLOCK_SIZE) {
+        let block = UF2BlockData::decode_with_mode(chunk, mode)?;
+        if !block.flags.is_main_flash() {
+            continue;
+        }
+        let offset = (block.flash_address - start) as usize;
+        image[offset..offset + block.payload.len()]
+            .copy_from_slice(&block.payload);
+        covered += block.payload.len();
+    }
+    Ok(UF2Image {
+        start_address: start,
+        filled: image.len().saturating_sub(covered) as u32,
+        data: image,
+    })
+}
@@ -1,11 +1,19 @@
 use crate::*;
 
+/// Iterates over the ranges of a UF2 file
+///
+/// Each range is address-contiguous and family-homogeneous: a new range
+/// starts whenever the flash address is not contiguous or the family ID
+/// (if any) changes.
 pub struct UF2RangeIterator<'a> {
     block_iter: Option<std::slice::Chunks<'a, u8>>,
+    mode: UF2DecodeMode,
     start_address: u32,
     end_address: u32,
     payload: Vec<u8>,
     reboot_address: Option<u32>,
+    reboot_flags: Option<u32>,
+    family_id: Option<u32>,
 }
 
 #[derive(Default)]
@@ -13,12 +21,22 @@ pub struct UF2AddressRange {
     pub start_address: u32,
     pub payload: Vec<u8>,
     pub reboot_address: Option<u32>,
+    /// Flags of the extended reboot format (if used)
+    pub reboot_flags: Option<u32>,
+    pub family_id: Option<u32>,
 }
 
 impl<'a> UF2RangeIterator<'a> {
     pub fn new(data: &'a [u8]) -> Result<Self, UF2DecodeError> {
+        Self::with_mode(data, UF2DecodeMode::Strict)
+    }
+
+    pub fn with_mode(
+        data: &'a [u8],
+        mode: UF2DecodeMode,
+    ) -> Result<Self, UF2DecodeError> {
         for (i, chunk) in data.chunks(UF2_BLOCK_SIZE).enumerate() {
-            if !is_uf2_block(chunk) {
+            if !mode.is_valid_block(chunk) {
                 let offset = i * UF2_BLOCK_SIZE;
                 return Err(UF2DecodeError::new(format!(
                     "invalid UF2 block at {:#x}",
@@ -27,30 +45,49 @@ impl<'a> UF2RangeIterator<'a> {
             }
         }
 
+        if mode == UF2DecodeMode::Lenient
+            && !data.chunks(UF2_BLOCK_SIZE).all(is_uf2_block)
+        {
+            log::warn!("UF2 blocks without final magic value");
+        }
+
         let mut block_iter = data.chunks(UF2_BLOCK_SIZE);
-        let block = UF2BlockData::decode(block_iter.next().unwrap())?;
+        let block = match block_iter.next() {
+            Some(block) => UF2BlockData::decode_with_mode(block, mode)?,
+            None => {
+                return Err(UF2DecodeError::new("empty file".to_string()));
+            }
+        };
         Ok(UF2RangeIterator {
             block_iter: Some(block_iter),
+            mode,
             start_address: block.flash_address,
             end_address: block.flash_address + (block.payload.len() as u32),
-            payload: block.payload.clone(),
             reboot_address: block.get_reboot_address(),
+            reboot_flags: block.get_reboot_flags(),
+            family_id: block.family_id(),
+            payload: block.payload,
         })
     }
 
+    // the payload is moved out, not copied
     fn make_range(&mut self) -> UF2AddressRange {
         UF2AddressRange {
             start_address: self.start_address,
-            payload: self.payload.clone(),
+            payload: std::mem::take(&mut self.payload),
             reboot_address: self.reboot_address.take(),
+            reboot_flags: self.reboot_flags.take(),
+            family_id: self.family_id,
         }
     }
 
-    fn reset(&mut self, block: &UF2BlockData) {
+    fn reset(&mut self, block: UF2BlockData) {
         self.start_address = block.flash_address;
         self.end_address = block.flash_address + (block.payload.len() as u32);
-        self.payload = block.payload.clone();
         self.reboot_address = block.get_reboot_address();
+        self.reboot_flags = block.get_reboot_flags();
+        self.family_id = block.family_id();
+        self.payload = block.payload;
     }
 }
 
@@ -59,10 +96,13 @@ impl<'a> Iterator for UF2RangeIterator<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         for block in self.block_iter.as_mut()?.by_ref() {
-            let block = UF2BlockData::decode(block).ok()?;
-            if self.end_address != block.flash_address {
+            let block =
+                UF2BlockData::decode_with_mode(block, self.mode).ok()?;
+            if self.end_address != block.flash_address
+                || self.family_id != block.family_id()
+            {
                 let item = self.make_range();
-                self.reset(&block);
+                self.reset(block);
                 return Some(item);
             } else {
                 self.end_address += block.payload.len() as u32;
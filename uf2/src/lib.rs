@@ -1,6 +1,16 @@
+use std::cmp;
+
+pub use description::*;
+pub use encode::*;
 pub use iter::*;
+pub use stream::*;
+pub use validate::*;
 
+mod description;
+mod encode;
 mod iter;
+mod stream;
+mod validate;
 
 pub const UF2_BLOCK_SIZE: usize = 512;
 pub const UF2_HEADER_SIZE: usize = 32;
@@ -40,6 +50,40 @@ pub struct UF2Extension {
     pub payload: Vec<u8>,
 }
 
+/// How strictly UF2 blocks are validated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UF2DecodeMode {
+    /// All magic values must be present
+    #[default]
+    Strict,
+    /// Only the start magic values are required (some older tools omit
+    /// the final magic value)
+    Lenient,
+}
+
+/// Reboot extension payload
+pub struct UF2Reboot {
+    pub address: u32,
+    /// Flags of the extended reboot format
+    pub flags: Option<u32>,
+}
+
+impl UF2Reboot {
+    /// Decode the jump address, optionally followed by the flags of the
+    /// extended reboot format (little endian 32-bit words)
+    ///
+    /// Trailing bytes of newer formats are ignored.
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        if payload.len() < 4 {
+            return None;
+        }
+        Some(UF2Reboot {
+            address: extract_u32(payload, 0),
+            flags: (payload.len() >= 8).then(|| extract_u32(payload, 4)),
+        })
+    }
+}
+
 pub struct UF2DecodeError {
     pub err: String,
 }
@@ -72,9 +116,25 @@ impl UF2Flags {
     }
 }
 
+impl UF2DecodeMode {
+    pub fn is_valid_block(&self, data: &[u8]) -> bool {
+        match self {
+            UF2DecodeMode::Strict => is_uf2_block(data),
+            UF2DecodeMode::Lenient => is_uf2_block_start(data),
+        }
+    }
+}
+
 impl UF2BlockData {
     pub fn decode(data: &[u8]) -> Result<UF2BlockData, UF2DecodeError> {
-        if !is_uf2_block(data) {
+        Self::decode_with_mode(data, UF2DecodeMode::Strict)
+    }
+
+    pub fn decode_with_mode(
+        data: &[u8],
+        mode: UF2DecodeMode,
+    ) -> Result<UF2BlockData, UF2DecodeError> {
+        if !mode.is_valid_block(data) {
             return Err(UF2DecodeError::new(
                 "magic values check failed".to_string(),
             ));
@@ -138,14 +198,25 @@ impl UF2BlockData {
             .map(String::from)
     }
 
+    /// Raw payload of the reboot extension
+    pub fn get_reboot_extension(&self) -> Option<&[u8]> {
+        self.get_extension(REBOOT_EXTENSION_TAG)
+            .map(|ext| ext.payload.as_slice())
+    }
+
+    /// Decoded reboot extension (see [UF2Reboot::parse()])
+    pub fn get_reboot(&self) -> Option<UF2Reboot> {
+        UF2Reboot::parse(self.get_reboot_extension()?)
+    }
+
+    /// Jump address of the reboot extension
     pub fn get_reboot_address(&self) -> Option<u32> {
-        let ext = self.get_extension(REBOOT_EXTENSION_TAG)?;
-        if ext.payload.len() == 4 {
-            let addr = u32::from_le_bytes(ext.payload[..].try_into().unwrap());
-            Some(addr)
-        } else {
-            None
-        }
+        self.get_reboot().map(|reboot| reboot.address)
+    }
+
+    /// Flags of the extended reboot format (if used)
+    pub fn get_reboot_flags(&self) -> Option<u32> {
+        self.get_reboot()?.flags
     }
 
     fn get_extension_string(&self, tag: u32) -> Option<&str> {
@@ -180,6 +251,131 @@ pub fn is_uf2_block(data: &[u8]) -> bool {
     check_magic(UF2_MAGIC_VALUES, data)
 }
 
+/// Full sized block with start magic values (final magic value not checked)
+pub fn is_uf2_block_start(data: &[u8]) -> bool {
+    data.len() == UF2_BLOCK_SIZE && check_magic(&UF2_MAGIC_VALUES[0..2], data)
+}
+
+/// Lowest start and highest end (exclusive) flash address of all
+/// main flash blocks (reboot and other non-flash blocks are ignored)
+pub fn flash_extent(data: &[u8]) -> Result<(u32, u32), UF2DecodeError> {
+    flash_extent_with_mode(data, UF2DecodeMode::Strict)
+}
+
+pub fn flash_extent_with_mode(
+    data: &[u8],
+    mode: UF2DecodeMode,
+) -> Result<(u32, u32), UF2DecodeError> {
+    let mut extent: Option<(u32, u32)> = None;
+    for chunk in data.chunks(UF2_BLOCK_SIZE) {
+        let block = UF2BlockData::decode_with_mode(chunk, mode)?;
+        if !block.flags.is_main_flash() {
+            continue;
+        }
+        let start = block.flash_address;
+        let end =
+            start
+                .checked_add(block.payload.len() as u32)
+                .ok_or_else(|| {
+                    UF2DecodeError::new(format!(
+                        "block at {start:#010x} exceeds the address space"
+                    ))
+                })?;
+        extent = Some(match extent {
+            Some((min, max)) => (cmp::min(min, start), cmp::max(max, end)),
+            None => (start, end),
+        });
+    }
+    extent.ok_or(UF2DecodeError::new("no flash blocks".to_string()))
+}
+
+/// Raw payload starting at `address` within the contiguous range containing
+/// it, up to `length` bytes (or to the end of the range)
+pub fn extract_region(
+    data: &[u8],
+    mode: UF2DecodeMode,
+    address: u32,
+    length: Option<u32>,
+) -> Result<Vec<u8>, UF2DecodeError> {
+    let range = UF2RangeIterator::with_mode(data, mode)?
+        .find(|r| {
+            r.start_address <= address
+                && address - r.start_address < r.payload.len() as u32
+        })
+        .ok_or(UF2DecodeError::new(format!(
+            "no range containing {address:#010x}"
+        )))?;
+    let offset = (address - range.start_address) as usize;
+    let end = match length {
+        Some(length) => offset + length as usize,
+        None => range.payload.len(),
+    };
+    if end > range.payload.len() {
+        return Err(UF2DecodeError::new(format!(
+            "{} bytes from {address:#010x} exceed the range ({:#010x}: {} bytes)",
+            end - offset,
+            range.start_address,
+            range.payload.len()
+        )));
+    }
+    Ok(range.payload[offset..end].to_vec())
+}
+
+/// Copy of the UF2 file `template` with the payload of each main flash
+/// block replaced by the bytes at its address within `ranges` (start
+/// address and content)
+///
+/// Headers, extensions and non-flash blocks are kept as-is, so that the
+/// result is byte-identical to `template` when the content matches.
+pub fn replace_payloads(
+    template: &[u8],
+    mode: UF2DecodeMode,
+    ranges: &[(u32, Vec<u8>)],
+) -> Result<Vec<u8>, UF2DecodeError> {
+    let mut data = template.to_vec();
+    for chunk in data.chunks_mut(UF2_BLOCK_SIZE) {
+        let block = UF2BlockData::decode_with_mode(chunk, mode)?;
+        if !block.flags.is_main_flash() || block.payload.is_empty() {
+            continue;
+        }
+        let start = block.flash_address;
+        let len = block.payload.len();
+        let payload = ranges
+            .iter()
+            .find_map(|(range_start, content)| {
+                let offset = start.checked_sub(*range_start)? as usize;
+                content.get(offset..offset + len)
+            })
+            .ok_or(UF2DecodeError::new(format!(
+                "no content for block at {start:#010x}"
+            )))?;
+        chunk[UF2_HEADER_SIZE..UF2_HEADER_SIZE + len].copy_from_slice(payload);
+    }
+    Ok(data)
+}
+
+/// Device description (ex: "EdgeTX on Radiomaster TX16S") of the first block
+pub fn device_description(data: &[u8]) -> Option<String> {
+    let block = data.get(0..UF2_BLOCK_SIZE)?;
+    UF2BlockData::decode_with_mode(block, UF2DecodeMode::Lenient)
+        .ok()?
+        .get_device_description()
+}
+
+/// Check whether `target` designates the device described by
+/// `device_description`. The comparison ignores case, spaces and
+/// punctuation (ex: "tx16s" matches "EdgeTX on Radiomaster TX16S").
+pub fn matches_target(device_description: &str, target: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let target = normalize(target);
+    !target.is_empty() && normalize(device_description).contains(&target)
+}
+
 fn check_magic(magics: &[(usize, u32)], data: &[u8]) -> bool {
     magics.iter().all(|(offset, magic)| {
         (data.len() >= offset + 4) && (*magic == extract_u32(data, *offset))
@@ -200,25 +396,417 @@ fn decode_extensions(flags: UF2Flags, data: &[u8]) -> Vec<UF2Extension> {
     let mut extensions: Vec<UF2Extension> = Vec::new();
 
     if flags.extension_tags_present() {
-        while offset < data.len() {
+        while offset + 4 <= data.len() {
             let hdr = extract_u32(data, offset);
             if hdr == 0 {
                 break;
             }
 
-            let length = hdr & 0xff;
+            // the length includes the 4 bytes header, the next extension
+            // starts on the next 4 bytes boundary
+            let length = (hdr & 0xff) as usize;
             let tag = (hdr >> 8) & 0xffffff;
+            if length < 4 || offset + length > data.len() {
+                log::warn!("malformed UF2 extension {tag:#08x}");
+                break;
+            }
 
             extensions.push(UF2Extension {
                 tag,
-                payload: Vec::from(
-                    &data[(offset + 4)..(offset + (length as usize))],
-                ),
+                payload: Vec::from(&data[(offset + 4)..(offset + length)]),
             });
 
-            offset += pad32(length as usize);
+            offset += pad32(length);
         }
     }
 
     extensions
 }
+
+#[cfg(test)]
+mod tests {
+    use md5::Digest;
+
+    use super::*;
+
+    fn make_block(flash_address: u32, payload: &[u8]) -> Vec<u8> {
+        let mut block = vec![0u8; UF2_BLOCK_SIZE];
+        let header = [
+            UF2_MAGIC_START1,
+            UF2_MAGIC_START2,
+            0,
+            flash_address,
+            payload.len() as u32,
+            0,
+            1,
+            0,
+        ];
+        for (i, value) in header.iter().enumerate() {
+            block[i * 4..(i + 1) * 4].copy_from_slice(&value.to_le_bytes());
+        }
+        block[UF2_HEADER_SIZE..UF2_HEADER_SIZE + payload.len()]
+            .copy_from_slice(payload);
+        block[UF2_BLOCK_SIZE - 4..]
+            .copy_from_slice(&UF2_MAGIC_FINAL.to_le_bytes());
+        block
+    }
+
+    fn with_reboot_extension(mut block: Vec<u8>, payload: &[u8]) -> Vec<u8> {
+        block[8..12].copy_from_slice(
+            &(UF2Flags::NOT_MAIN_FLASH | UF2Flags::EXTENSION_TAGS_PRESENT)
+                .to_le_bytes(),
+        );
+        let payload_size = extract_u32(&block, 16) as usize;
+        let hdr = (REBOOT_EXTENSION_TAG << 8) | (payload.len() as u32 + 4);
+        let offset = UF2_HEADER_SIZE + payload_size;
+        block[offset..offset + 4].copy_from_slice(&hdr.to_le_bytes());
+        block[offset + 4..offset + 4 + payload.len()].copy_from_slice(payload);
+        block
+    }
+
+    fn numbered(mut block: Vec<u8>, block_nr: u32, total: u32) -> Vec<u8> {
+        block[20..24].copy_from_slice(&block_nr.to_le_bytes());
+        block[24..28].copy_from_slice(&total.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn test_replace_payloads() {
+        let mut data = make_block(0x08000000, &[1; 256]);
+        data.extend(make_block(0x08000100, &[2; 256]));
+        data.extend(make_block(0x90000000, &[3; 16]));
+
+        let mut content = [[1; 256], [2; 256]].concat();
+        let ranges = [(0x08000000, content.clone()), (0x90000000, vec![3; 16])];
+        let copy = replace_payloads(&data, UF2DecodeMode::Strict, &ranges);
+        assert!(copy.ok().unwrap() == data);
+
+        content[0x180] = 0;
+        let ranges = [(0x08000000, content), (0x90000000, vec![3; 16])];
+        let copy = replace_payloads(&data, UF2DecodeMode::Strict, &ranges)
+            .ok()
+            .unwrap();
+        assert_eq!(copy.len(), data.len());
+        assert_eq!(copy[UF2_BLOCK_SIZE + UF2_HEADER_SIZE + 0x7f], 2);
+        assert_eq!(copy[UF2_BLOCK_SIZE + UF2_HEADER_SIZE + 0x80], 0);
+
+        // missing content for the last block
+        let ranges = [(0x08000000, vec![1; 512])];
+        assert!(
+            replace_payloads(&data, UF2DecodeMode::Strict, &ranges).is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut data = numbered(make_block(0x08000000, &[1; 256]), 0, 2);
+        data.extend(numbered(make_block(0x08000100, &[2; 256]), 1, 2));
+        let report = validate(&data).ok().unwrap();
+        assert_eq!(report.blocks, 2);
+        assert_eq!(report.extent, (0x08000000, 0x08000200));
+        assert_eq!(report.reboot_address, None);
+
+        // MD5 checksum of the first block payload
+        let mut block = numbered(make_block(0x08000000, &[1; 256]), 0, 2);
+        block[8..12]
+            .copy_from_slice(&UF2Flags::MD5_CHECKSUM_PRESENT.to_le_bytes());
+        block[484..488].copy_from_slice(&0x08000000u32.to_le_bytes());
+        block[488..492].copy_from_slice(&256u32.to_le_bytes());
+        block[492..508].copy_from_slice(&md5::Md5::digest([1; 256]));
+        let mut md5_data = block.clone();
+        md5_data.extend(&data[UF2_BLOCK_SIZE..]);
+        assert_eq!(validate(&md5_data).ok().unwrap().md5_verified, 1);
+        md5_data[492] ^= 0xff;
+        assert!(validate(&md5_data).is_err());
+
+        // numbering
+        let mut bad = data[..UF2_BLOCK_SIZE].to_vec();
+        bad.extend(&data[..UF2_BLOCK_SIZE]);
+        assert!(validate(&bad).is_err());
+        assert!(validate(&data[..UF2_BLOCK_SIZE]).is_err());
+
+        // truncated block, malformed extension
+        assert!(validate(&data[..UF2_BLOCK_SIZE + 100]).is_err());
+        let mut block = make_block(0x08000000, b"BDFU");
+        block[8..12]
+            .copy_from_slice(&UF2Flags::EXTENSION_TAGS_PRESENT.to_le_bytes());
+        block[36..40].copy_from_slice(&0xe6083502u32.to_le_bytes());
+        assert!(validate(&block).is_err());
+    }
+
+    #[test]
+    fn test_pad32() {
+        assert_eq!(pad32(0), 0);
+        assert_eq!(pad32(4), 4);
+        assert_eq!(pad32(5), 8);
+        assert_eq!(pad32(7), 8);
+        assert_eq!(pad32(8), 8);
+        assert_eq!(pad32(12), 12);
+    }
+
+    fn encode_extensions(extensions: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (tag, payload) in extensions {
+            let length = payload.len() + 4;
+            data.extend(((tag << 8) | length as u32).to_le_bytes());
+            data.extend(*payload);
+            data.resize(pad32(data.len()), 0);
+        }
+        data.resize(data.len() + 8, 0);
+        data
+    }
+
+    #[test]
+    fn test_decode_extensions() {
+        let present = UF2Flags::EXTENSION_TAGS_PRESENT;
+        let payloads: [&[u8]; 5] =
+            [b"a", b"abc", b"abcd", b"abcde", b"abcdefgh"];
+        let extensions: Vec<(u32, &[u8])> = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| (0x100 + i as u32, *payload))
+            .collect();
+        let data = encode_extensions(&extensions);
+        // headers and padded payloads: 8 + 8 + 8 + 12 + 12 bytes
+        assert_eq!(data.len(), 48 + 8);
+
+        let decoded = decode_extensions(UF2Flags(present), &data);
+        assert_eq!(decoded.len(), payloads.len());
+        for (ext, (tag, payload)) in decoded.iter().zip(&extensions) {
+            assert_eq!(ext.tag, *tag);
+            assert_eq!(ext.payload, *payload);
+        }
+
+        // without the flag, nothing is decoded
+        assert!(decode_extensions(UF2Flags(0), &data).is_empty());
+
+        // lengths shorter than the header or beyond the data area stop
+        // the decoding
+        for hdr in [0x10000u32, 0x10002, 0x100ff] {
+            let mut data = encode_extensions(&extensions[..1]);
+            data.splice(0..0, hdr.to_le_bytes());
+            let decoded = decode_extensions(UF2Flags(present), &data[..12]);
+            assert!(decoded.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_reboot_extension() {
+        let tag = make_block(0x08000000, b"BDFU");
+        let block = with_reboot_extension(tag.clone(), &[0, 0, 0, 8]);
+        let block = UF2BlockData::decode(&block).ok().unwrap();
+        assert!(block.is_reboot_block());
+        assert_eq!(block.get_reboot_address(), Some(0x08000000));
+        assert_eq!(block.get_reboot_flags(), None);
+
+        let block =
+            with_reboot_extension(tag.clone(), &[0, 0, 0, 8, 1, 0, 0, 0]);
+        let block = UF2BlockData::decode(&block).ok().unwrap();
+        assert_eq!(block.get_reboot_address(), Some(0x08000000));
+        assert_eq!(block.get_reboot_flags(), Some(1));
+
+        let payload = [0, 0, 0, 8, 1, 0, 0, 0, 0x42, 0, 0, 0];
+        let block = with_reboot_extension(tag.clone(), &payload);
+        let block = UF2BlockData::decode(&block).ok().unwrap();
+        assert_eq!(block.get_reboot_extension(), Some(&payload[..]));
+        assert_eq!(block.get_reboot_address(), Some(0x08000000));
+        assert_eq!(block.get_reboot_flags(), Some(1));
+
+        let block = with_reboot_extension(tag, &[0, 0, 8]);
+        let block = UF2BlockData::decode(&block).ok().unwrap();
+        assert_eq!(block.get_reboot_extension(), Some(&[0, 0, 8][..]));
+        assert_eq!(block.get_reboot_address(), None);
+    }
+
+    #[test]
+    fn test_ranges_split_on_family_id() {
+        let mut data = make_block(0x08000000, &[0; 256]);
+        data.extend(make_block(0x08000100, &[0; 256]));
+
+        let mut block = make_block(0x08000200, &[0; 256]);
+        block[8..12]
+            .copy_from_slice(&UF2Flags::FAMILY_ID_PRESENT.to_le_bytes());
+        block[28..32].copy_from_slice(&0x57755a57u32.to_le_bytes());
+        data.extend(block);
+
+        let ranges: Vec<UF2AddressRange> =
+            UF2RangeIterator::new(&data).ok().unwrap().collect();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].start_address, 0x08000000);
+        assert_eq!(ranges[0].payload.len(), 512);
+        assert_eq!(ranges[0].family_id, None);
+        assert_eq!(ranges[1].start_address, 0x08000200);
+        assert_eq!(ranges[1].family_id, Some(0x57755a57));
+    }
+
+    #[test]
+    fn test_matches_target() {
+        let description = "EdgeTX on Radiomaster TX16S";
+        assert!(matches_target(description, "tx16s"));
+        assert!(matches_target(description, "Radiomaster TX-16S"));
+        assert!(matches_target(description, description));
+        assert!(!matches_target(description, "TX12"));
+        assert!(!matches_target(description, "STM32  BOOTLOADER"));
+        assert!(!matches_target(description, ""));
+    }
+
+    #[test]
+    fn test_device_description() {
+        let desc = UF2DeviceDescription::parse("EdgeTX on Radiomaster TX16S");
+        assert_eq!(desc.firmware(), Some("EdgeTX"));
+        assert_eq!(desc.board(), "Radiomaster TX16S");
+        assert_eq!(desc.variant(), None);
+        assert!(desc.matches_target("tx16s"));
+        assert!(!desc.matches_target("EdgeTX"));
+
+        let desc = UF2DeviceDescription::parse("EdgeTX on Flysky PL18;EL18");
+        assert_eq!(desc.raw(), "EdgeTX on Flysky PL18;EL18");
+        assert_eq!(desc.board(), "Flysky PL18");
+        assert_eq!(desc.variant(), Some("EL18"));
+        assert!(!desc.matches_target("EL18"));
+
+        let desc = UF2DeviceDescription::parse("TX16S");
+        assert_eq!(desc.firmware(), None);
+        assert_eq!(desc.board(), "TX16S");
+    }
+
+    #[test]
+    fn test_block_reader() {
+        let mut data = make_block(0x08000000, &[1; 256]);
+        data.extend(make_block(0x08000100, &[2; 256]));
+        let blocks: Vec<UF2BlockData> =
+            UF2BlockReader::new(&data[..], UF2DecodeMode::Strict)
+                .map(|block| block.ok().unwrap())
+                .collect();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].flash_address, 0x08000100);
+        assert_eq!(blocks[1].payload, [2; 256]);
+
+        // truncated last block
+        let mut reader =
+            UF2BlockReader::new(&data[..600], UF2DecodeMode::Strict);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_encode_flatten() {
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let encoded = encode(&data, 0x08000000, None).ok().unwrap();
+        assert_eq!(encoded.len(), 3 * UF2_BLOCK_SIZE);
+        let report = validate(&encoded).ok().unwrap();
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.extent, (0x08000000, 0x08000000 + 600));
+
+        let image = flatten(&encoded, UF2DecodeMode::Strict, 0xff, 1024)
+            .ok()
+            .unwrap();
+        assert_eq!(image.start_address, 0x08000000);
+        assert_eq!(image.data, data);
+        assert_eq!(image.filled, 0);
+
+        let encoded = encode(&data[..16], 0x08000000, Some(0x57755a57))
+            .ok()
+            .unwrap();
+        let block = UF2BlockData::decode(&encoded).ok().unwrap();
+        assert_eq!(block.family_id(), Some(0x57755a57));
+
+        // gaps are filled, reboot blocks ignored
+        let mut data = make_block(0x08000100, &[1; 16]);
+        data.extend(make_block(0x08000000, &[2; 16]));
+        data.extend(with_reboot_extension(
+            make_block(0x2001fff0, b"BDFU"),
+            &[0, 0, 0, 8],
+        ));
+        let image = flatten(&data, UF2DecodeMode::Strict, 0xff, 0x110)
+            .ok()
+            .unwrap();
+        assert_eq!(image.start_address, 0x08000000);
+        assert_eq!(image.data.len(), 0x110);
+        assert_eq!(image.data[..16], [2; 16]);
+        assert_eq!(image.data[16..0x100], [0xff; 0xf0]);
+        assert_eq!(image.data[0x100..], [1; 16]);
+        assert_eq!(image.filled, 0xf0);
+        // refused before allocating
+        assert!(flatten(&data, UF2DecodeMode::Strict, 0xff, 0x10f).is_err());
+
+        // top of the address space
+        assert!(encode(&[0; 16], 0xfffffff0, None).is_ok());
+        assert!(encode(&[0; 17], 0xfffffff0, None).is_err());
+        assert!(encode(&[], 0xffffffff, None).ok().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_flash_extent() {
+        let mut data = make_block(0x08000000, &[0; 256]);
+        data.extend(make_block(0x08000100, &[0; 256]));
+        data.extend(make_block(0x08010000, &[0; 16]));
+
+        let mut reboot = make_block(0x00000000, &[0; 4]);
+        reboot[8..12].copy_from_slice(&UF2Flags::NOT_MAIN_FLASH.to_le_bytes());
+        data.extend(reboot);
+
+        assert_eq!(flash_extent(&data).ok(), Some((0x08000000, 0x08010010)));
+        assert!(flash_extent(&[]).is_err());
+        // no wrapping around the end of the address space
+        let data = encode(&[0; 16], 0xfffffff0, None).ok().unwrap();
+        assert!(flash_extent(&data).is_err());
+    }
+
+    #[test]
+    fn test_extract_region() {
+        let mut data = make_block(0x08000000, &[1; 256]);
+        data.extend(make_block(0x08000100, &[2; 256]));
+        data.extend(make_block(0x90000000, &[3; 16]));
+        let mode = UF2DecodeMode::Strict;
+
+        let region = extract_region(&data, mode, 0x08000000, None).ok();
+        assert_eq!(region.map(|r| r.len()), Some(512));
+
+        let region = extract_region(&data, mode, 0x080000fe, Some(4)).ok();
+        assert_eq!(region, Some(vec![1, 1, 2, 2]));
+
+        let region = extract_region(&data, mode, 0x90000000, None).ok();
+        assert_eq!(region, Some(vec![3; 16]));
+
+        assert!(extract_region(&data, mode, 0x08000200, None).is_err());
+        assert!(extract_region(&data, mode, 0x90000008, Some(9)).is_err());
+    }
+
+    #[test]
+    fn test_decode_mode() {
+        let mut block = make_block(0x08000000, &[1, 2, 3, 4]);
+        assert!(UF2BlockData::decode(&block).is_ok());
+
+        // drop final magic
+        block[UF2_BLOCK_SIZE - 4..].fill(0);
+        assert!(UF2BlockData::decode(&block).is_err());
+        assert!(UF2RangeIterator::new(&block).is_err());
+
+        let decoded =
+            UF2BlockData::decode_with_mode(&block, UF2DecodeMode::Lenient)
+                .ok()
+                .unwrap();
+        assert_eq!(decoded.flash_address, 0x08000000);
+        assert_eq!(decoded.payload, vec![1, 2, 3, 4]);
+
+        let ranges: Vec<UF2AddressRange> =
+            UF2RangeIterator::with_mode(&block, UF2DecodeMode::Lenient)
+                .ok()
+                .unwrap()
+                .collect();
+        assert_eq!(ranges.len(), 1);
+
+        // start magics are still required
+        block[0..4].fill(0);
+        assert!(
+            UF2BlockData::decode_with_mode(&block, UF2DecodeMode::Lenient)
+                .is_err()
+        );
+
+        // truncated blocks are never accepted
+        let block = make_block(0x08000000, &[1, 2, 3, 4]);
+        assert!(!UF2DecodeMode::Lenient.is_valid_block(&block[..256]));
+    }
+}
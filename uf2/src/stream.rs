@@ -0,0 +1,66 @@
+use std::io::{self, Read};
+
+use crate::*;
+
+/// Reads and decodes UF2 blocks one at a time, without loading the whole
+/// file in memory
+pub struct UF2BlockReader<R> {
+    reader: R,
+    mode: UF2DecodeMode,
+    offset: u64,
+    done: bool,
+}
+
+impl<R: Read> UF2BlockReader<R> {
+    pub fn new(reader: R, mode: UF2DecodeMode) -> Self {
+        UF2BlockReader {
+            reader,
+            mode,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    fn read_block(&mut self) -> Result<Option<UF2BlockData>, UF2DecodeError> {
+        let mut block = [0u8; UF2_BLOCK_SIZE];
+        let mut len = 0;
+        while len < UF2_BLOCK_SIZE {
+            match self.reader.read(&mut block[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    return Err(UF2DecodeError::new(format!(
+                        "read error at {:#x}: {err}",
+                        self.offset
+                    )));
+                }
+            }
+        }
+        if len == 0 {
+            return Ok(None);
+        }
+        if len < UF2_BLOCK_SIZE || !self.mode.is_valid_block(&block) {
+            return Err(UF2DecodeError::new(format!(
+                "invalid UF2 block at {:#x}",
+                self.offset
+            )));
+        }
+        self.offset += UF2_BLOCK_SIZE as u64;
+        UF2BlockData::decode_with_mode(&block, self.mode).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for UF2BlockReader<R> {
+    type Item = Result<UF2BlockData, UF2DecodeError>;
+
+    /// Stops after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.read_block().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result
+    }
+}
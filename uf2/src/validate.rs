@@ -0,0 +1,157 @@
+use md5::{Digest, Md5};
+
+use crate::*;
+
+// The MD5 checksum (if present) fills the end of the data area:
+// region address, region length and digest
+const MD5_AREA_SIZE: usize = 24;
+
+/// Summary of a UF2 file checked by [validate()]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UF2Report {
+    pub blocks: u32,
+    /// family IDs in order of appearance
+    pub families: Vec<u32>,
+    /// lowest start and highest end (exclusive) flash address
+    pub extent: (u32, u32),
+    pub reboot_address: Option<u32>,
+    /// MD5 checksums verified against the file content (regions not
+    /// contained in the file cannot be verified)
+    pub md5_verified: u32,
+}
+
+/// Check every block of a UF2 file without a device
+///
+/// Magic values, block numbering, payload sizes, extensions and MD5
+/// checksums (if present) are verified, the first failure is returned
+/// along with the offending block index.
+pub fn validate(data: &[u8]) -> Result<UF2Report, UF2DecodeError> {
+    if data.is_empty() {
+        return Err(UF2DecodeError::new("empty file".to_string()));
+    }
+    let mut report = UF2Report::default();
+    // (next block number, total blocks) of the current sequence
+    let mut sequence: Option<(u32, u32)> = None;
+    let mut checksums = Vec::new();
+
+    for (i, chunk) in data.chunks(UF2_BLOCK_SIZE).enumerate() {
+        let invalid =
+            |err: String| UF2DecodeError::new(format!("block {i}: {err}"));
+        if chunk.len() != UF2_BLOCK_SIZE {
+            return Err(invalid(format!("truncated ({} bytes)", chunk.len())));
+        }
+        if !is_uf2_block(chunk) {
+            return Err(invalid("magic values check failed".to_string()));
+        }
+
+        let flags = UF2Flags(extract_u32(chunk, 8));
+        let payload_size = extract_u32(chunk, 16) as usize;
+        let data_end = if flags.md5_checksum_present() {
+            UF2_BLOCK_SIZE - 4 - MD5_AREA_SIZE
+        } else {
+            UF2_BLOCK_SIZE - 4
+        };
+        if UF2_HEADER_SIZE + payload_size > data_end {
+            return Err(invalid(format!(
+                "payload size too big ({payload_size})"
+            )));
+        }
+        if flags.extension_tags_present() {
+            check_extensions(&chunk[UF2_HEADER_SIZE + payload_size..data_end])
+                .map_err(invalid)?;
+        }
+
+        let block_nr = extract_u32(chunk, 20);
+        let total_blocks = extract_u32(chunk, 24);
+        sequence = match sequence {
+            // a new sequence starts (ex: next family in the same file)
+            _ if block_nr == 0 => {
+                if let Some((next, total)) = sequence
+                    && next != total
+                {
+                    return Err(invalid(format!(
+                        "previous sequence ended at {next} of {total} blocks"
+                    )));
+                }
+                Some((1, total_blocks))
+            }
+            Some((next, total))
+                if block_nr == next && total_blocks == total =>
+            {
+                Some((next + 1, total))
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "unexpected block number {block_nr} of {total_blocks}"
+                )));
+            }
+        };
+        if block_nr >= total_blocks {
+            return Err(invalid(format!(
+                "block number {block_nr} exceeds total ({total_blocks})"
+            )));
+        }
+
+        let block = UF2BlockData::decode(chunk)?;
+        if let Some(family) = block.family_id()
+            && !report.families.contains(&family)
+        {
+            report.families.push(family);
+        }
+        if report.reboot_address.is_none() {
+            report.reboot_address = block.get_reboot_address();
+        }
+        if flags.md5_checksum_present() {
+            let area = &chunk[data_end..UF2_BLOCK_SIZE - 4];
+            checksums.push((i, extract_u32(area, 0), extract_u32(area, 4)));
+        }
+        report.blocks += 1;
+    }
+
+    if let Some((next, total)) = sequence
+        && next != total
+    {
+        return Err(UF2DecodeError::new(format!(
+            "missing blocks ({next} of {total})"
+        )));
+    }
+    report.extent = flash_extent(data)?;
+
+    for (i, address, length) in checksums {
+        let Ok(region) =
+            extract_region(data, UF2DecodeMode::Strict, address, Some(length))
+        else {
+            continue;
+        };
+        let offset = i * UF2_BLOCK_SIZE + UF2_BLOCK_SIZE - 4 - 16;
+        if Md5::digest(&region)[..] != data[offset..offset + 16] {
+            return Err(UF2DecodeError::new(format!(
+                "block {i}: MD5 checksum mismatch ({address:#010x}, \
+                {length} bytes)"
+            )));
+        }
+        report.md5_verified += 1;
+    }
+    Ok(report)
+}
+
+// Extension headers: length (incl. header) in the low byte, tag above,
+// a zero header ends the list
+fn check_extensions(data: &[u8]) -> Result<(), String> {
+    let mut offset = 0;
+    while offset + 4 <= data.len() {
+        let hdr = extract_u32(data, offset);
+        if hdr == 0 {
+            return Ok(());
+        }
+        let length = (hdr & 0xff) as usize;
+        if length < 4 || offset + length > data.len() {
+            return Err(format!(
+                "malformed extension {:#08x} (length {length})",
+                hdr >> 8
+            ));
+        }
+        offset += pad32(length);
+    }
+    Ok(())
+}